@@ -0,0 +1,407 @@
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::TAU;
+
+use bevy::audio::AudioSource;
+use bevy::prelude::*;
+
+use crate::settings::Settings;
+use crate::AppState;
+
+/// Plays sounds for gameplay events. Gameplay code only sends [`AudioCue`]s, which are
+/// recorded in the [`CueLog`] whether or not there's anything to play them on, so the cues can
+/// be checked without an audio device.
+pub struct AudioPlugin;
+
+impl Plugin for AudioPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_event::<AudioCue>()
+            .init_resource::<AudioSettings>()
+            .init_resource::<AudioUnlocked>()
+            .init_resource::<CueLog>()
+            .init_resource::<SoundCache>()
+            .add_system(state_cues.system().label(CueSystem))
+            .add_system(record_cues.system().after(CueSystem))
+            .add_system(play_cues.system().after(CueSystem))
+            .add_system(music.system())
+            .add_system(apply_settings.system());
+        // Browsers only allow audio to start from a user gesture, so the output is opened once
+        // START has been pressed instead of by bevy's audio plugin at startup
+        #[cfg(target_arch = "wasm32")]
+        app.add_asset::<AudioSource>()
+            .init_resource::<Audio<AudioSource>>()
+            .add_system_to_stage(CoreStage::PostUpdate, open_output.exclusive_system())
+            .add_system_to_stage(CoreStage::PostUpdate, play_queued.exclusive_system());
+    }
+}
+
+#[derive(SystemLabel, Clone, Eq, PartialEq, Hash, Debug, Default)]
+struct CueSystem;
+
+/// Something happened that should make a sound
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum AudioCue {
+    Shoot,
+    Points,
+    AsteroidSplit,
+    ShipHit,
+    GameStart,
+    Paused,
+    Resumed,
+    GameOver,
+}
+
+/// Volumes from 0 to 1. Sound effects and music are both scaled by `master`.
+pub struct AudioSettings {
+    pub master: f32,
+    pub sfx: f32,
+    pub music: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master: 0.8,
+            sfx: 1.,
+            music: 0.5,
+        }
+    }
+}
+
+impl AudioSettings {
+    fn volume(&self, sound: Sound) -> f32 {
+        let channel = match sound {
+            Sound::Cue(_) => self.sfx,
+            Sound::Music => self.music,
+        };
+        (self.master * channel).clamp(0., 1.)
+    }
+}
+
+fn apply_settings(settings: Res<Settings>, mut audio: ResMut<AudioSettings>) {
+    if settings.is_changed() {
+        audio.master = settings.master_volume;
+        audio.sfx = settings.sfx_volume;
+        audio.music = settings.music_volume;
+    }
+}
+
+/// Whether sounds may be played yet. On the web this waits for the first press of START.
+pub struct AudioUnlocked(pub bool);
+
+impl Default for AudioUnlocked {
+    fn default() -> Self {
+        AudioUnlocked(!cfg!(target_arch = "wasm32"))
+    }
+}
+
+/// The most recently requested cues, oldest first
+#[derive(Default)]
+pub struct CueLog {
+    pub cues: VecDeque<AudioCue>,
+}
+
+impl CueLog {
+    const CAPACITY: usize = 64;
+
+    pub fn record(&mut self, cue: AudioCue) {
+        if self.cues.len() == Self::CAPACITY {
+            self.cues.pop_front();
+        }
+        self.cues.push_back(cue);
+    }
+}
+
+fn record_cues(mut cues: EventReader<AudioCue>, mut log: ResMut<CueLog>) {
+    for cue in cues.iter() {
+        log.record(*cue);
+    }
+}
+
+/// Cues for moving between screens
+fn state_cues(
+    state: Res<State<AppState>>,
+    mut cues: EventWriter<AudioCue>,
+    mut last: Local<Option<AppState>>,
+) {
+    let current = *state.current();
+    let cue = match (*last, current) {
+        (Some(last), current) if last == current => None,
+        (Some(AppState::Settings), _) | (_, AppState::Settings) => None,
+        (Some(AppState::Controller), _) | (_, AppState::Controller) => None,
+        (Some(AppState::Paused), AppState::InGame) => Some(AudioCue::Resumed),
+        (_, AppState::InGame) | (_, AppState::Online) => Some(AudioCue::GameStart),
+        (_, AppState::Paused) => Some(AudioCue::Paused),
+        (_, AppState::End) => Some(AudioCue::GameOver),
+        _ => None,
+    };
+    *last = Some(current);
+    if let Some(cue) = cue {
+        cues.send(cue);
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum Sound {
+    Cue(AudioCue),
+    Music,
+}
+
+/// Synthesized sounds, by sound and volume in percent. The volume is baked into the samples,
+/// since bevy can't change the volume of a sound while playing it.
+#[derive(Default)]
+struct SoundCache(HashMap<(Sound, u8), Handle<AudioSource>>);
+
+impl SoundCache {
+    /// The sound at `volume`, or `None` if it would be silent
+    fn get(
+        &mut self,
+        sources: &mut Assets<AudioSource>,
+        sound: Sound,
+        volume: f32,
+    ) -> Option<Handle<AudioSource>> {
+        let percent = (volume * 100.).round() as u8;
+        if percent == 0 {
+            return None;
+        }
+        let handle = self.0.entry((sound, percent)).or_insert_with(|| {
+            sources.add(AudioSource {
+                bytes: wav(&synthesize(sound), percent as f32 / 100.).into(),
+            })
+        });
+        Some(handle.clone())
+    }
+}
+
+fn play_cues(
+    audio: Res<Audio<AudioSource>>,
+    settings: Res<AudioSettings>,
+    unlocked: Res<AudioUnlocked>,
+    mut sources: ResMut<Assets<AudioSource>>,
+    mut cache: ResMut<SoundCache>,
+    mut cues: EventReader<AudioCue>,
+) {
+    for cue in cues.iter().filter(|_| unlocked.0) {
+        let sound = Sound::Cue(*cue);
+        if let Some(handle) = cache.get(&mut sources, sound, settings.volume(sound)) {
+            audio.play(handle);
+        }
+    }
+}
+
+/// Loop the music while playing. Bevy can't stop a sound once started, so the current loop
+/// plays out after leaving the game.
+fn music(
+    time: Res<Time>,
+    state: Res<State<AppState>>,
+    audio: Res<Audio<AudioSource>>,
+    settings: Res<AudioSettings>,
+    unlocked: Res<AudioUnlocked>,
+    mut sources: ResMut<Assets<AudioSource>>,
+    mut cache: ResMut<SoundCache>,
+    mut until_next: Local<f32>,
+) {
+    if *state.current() != AppState::InGame || !unlocked.0 {
+        *until_next = 0.;
+        return;
+    }
+    *until_next -= time.delta_seconds();
+    if *until_next > 0. {
+        return;
+    }
+    *until_next = MUSIC_LENGTH;
+    if let Some(handle) = cache.get(&mut sources, Sound::Music, settings.volume(Sound::Music)) {
+        audio.play(handle);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn open_output(world: &mut World) {
+    let unlocked = world.get_resource::<AudioUnlocked>().map_or(false, |u| u.0);
+    if unlocked
+        && world
+            .get_non_send_resource::<bevy::audio::AudioOutput<AudioSource>>()
+            .is_none()
+    {
+        world.insert_non_send(bevy::audio::AudioOutput::<AudioSource>::default());
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn play_queued(world: &mut World) {
+    if world
+        .get_non_send_resource::<bevy::audio::AudioOutput<AudioSource>>()
+        .is_some()
+    {
+        bevy::audio::play_queued_audio_system::<AudioSource>(world);
+    }
+}
+
+const SAMPLE_RATE: u32 = 22050;
+const MUSIC_LENGTH: f32 = 8.;
+
+/// Samples from -1 to 1 for `duration` seconds, given the time of each sample
+fn samples(duration: f32, f: impl FnMut(f32) -> f32) -> Vec<f32> {
+    let count = (duration * SAMPLE_RATE as f32) as usize;
+    (0..count)
+        .map(|i| i as f32 / SAMPLE_RATE as f32)
+        .map(f)
+        .collect()
+}
+
+/// Cheap white noise, so sounds come out the same every time
+fn noise(state: &mut u32) -> f32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    *state as f32 / u32::MAX as f32 * 2. - 1.
+}
+
+/// Sine notes played one after another, each fading out
+fn arpeggio(notes: &[f32], note_length: f32) -> Vec<f32> {
+    samples(note_length * notes.len() as f32, |t| {
+        let note = ((t / note_length) as usize).min(notes.len() - 1);
+        let local = t - note as f32 * note_length;
+        (TAU * notes[note] * t).sin() * (1. - local / note_length) * 0.5
+    })
+}
+
+fn synthesize(sound: Sound) -> Vec<f32> {
+    let mut seed = 0x9e37_79b9;
+    match sound {
+        Sound::Cue(AudioCue::Shoot) => {
+            let length = 0.12;
+            samples(length, |t| {
+                // Sweep down from 900Hz to 250Hz
+                let phase = TAU * (900. * t - 650. * t * t / (2. * length));
+                phase.sin().signum() * 0.25 * (1. - t / length).powi(2)
+            })
+        }
+        Sound::Cue(AudioCue::Points) => arpeggio(&[880., 1320.], 0.08),
+        Sound::Cue(AudioCue::AsteroidSplit) => {
+            let length = 0.5;
+            samples(length, |t| {
+                let fade = 1. - t / length;
+                noise(&mut seed) * 0.6 * fade.powi(3) + (TAU * 60. * t).sin() * 0.4 * fade.powi(2)
+            })
+        }
+        Sound::Cue(AudioCue::ShipHit) => {
+            let length = 0.7;
+            samples(length, |t| {
+                let fade = 1. - t / length;
+                let phase = TAU * (200. * t - 160. * t * t / (2. * length));
+                noise(&mut seed) * 0.5 * fade.powi(2) + phase.sin() * 0.5 * fade
+            })
+        }
+        Sound::Cue(AudioCue::GameStart) => arpeggio(&[440., 554.37, 659.25, 880.], 0.1),
+        Sound::Cue(AudioCue::GameOver) => arpeggio(&[440., 349.23, 293.66, 220.], 0.18),
+        Sound::Cue(AudioCue::Paused) => arpeggio(&[329.63], 0.15),
+        Sound::Cue(AudioCue::Resumed) => arpeggio(&[440.], 0.15),
+        Sound::Music => samples(MUSIC_LENGTH, |t| {
+            // A slowly swelling drone, faded at both ends so the loop doesn't click
+            let swell = 0.6 + 0.4 * (TAU * t / MUSIC_LENGTH).sin();
+            let edge = (t / 0.05).min((MUSIC_LENGTH - t) / 0.05).min(1.);
+            let chord: f32 = [55., 82.41, 110., 164.81]
+                .iter()
+                .map(|freq| (TAU * freq * t).sin())
+                .sum();
+            chord * 0.15 * swell * edge
+        }),
+    }
+}
+
+/// Encode mono samples as a 16-bit PCM WAV file
+fn wav(samples: &[f32], gain: f32) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let mut bytes = Vec::with_capacity(44 + data_len as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, mono
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&1u16.to_le_bytes());
+    bytes.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    bytes.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    // Bytes per frame, bits per sample
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        let sample = ((sample * gain).clamp(-1., 1.) * i16::MAX as f32) as i16;
+        bytes.extend_from_slice(&sample.to_le_bytes());
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::Events;
+
+    use super::*;
+
+    const CUES: [AudioCue; 8] = [
+        AudioCue::Shoot,
+        AudioCue::Points,
+        AudioCue::AsteroidSplit,
+        AudioCue::ShipHit,
+        AudioCue::GameStart,
+        AudioCue::Paused,
+        AudioCue::Resumed,
+        AudioCue::GameOver,
+    ];
+
+    fn app() -> AppBuilder {
+        let mut app = App::build();
+        app.add_event::<AudioCue>()
+            .init_resource::<CueLog>()
+            .add_system(record_cues.system());
+        app
+    }
+
+    fn send(app: &mut AppBuilder, cues: impl IntoIterator<Item = AudioCue>) {
+        let mut events = app
+            .world_mut()
+            .get_resource_mut::<Events<AudioCue>>()
+            .unwrap();
+        for cue in cues {
+            events.send(cue);
+        }
+    }
+
+    fn logged(app: &mut AppBuilder) -> Vec<AudioCue> {
+        let log = app.world().get_resource::<CueLog>().unwrap();
+        log.cues.iter().copied().collect()
+    }
+
+    #[test]
+    fn records_cues_in_order() {
+        let mut app = app();
+        send(&mut app, vec![AudioCue::Shoot, AudioCue::Points]);
+        app.app.update();
+        assert_eq!(logged(&mut app), vec![AudioCue::Shoot, AudioCue::Points]);
+
+        // Cues are only recorded once, even though events stay around for two frames
+        app.app.update();
+        send(&mut app, vec![AudioCue::GameOver]);
+        app.app.update();
+        assert_eq!(
+            logged(&mut app),
+            vec![AudioCue::Shoot, AudioCue::Points, AudioCue::GameOver]
+        );
+    }
+
+    #[test]
+    fn keeps_only_the_latest_cues() {
+        let mut app = app();
+        let sent: Vec<AudioCue> = (0..CueLog::CAPACITY + 10)
+            .map(|i| CUES[i % CUES.len()])
+            .collect();
+        send(&mut app, sent[..20].to_vec());
+        app.app.update();
+        send(&mut app, sent[20..].to_vec());
+        app.app.update();
+        assert_eq!(logged(&mut app).len(), CueLog::CAPACITY);
+        assert_eq!(logged(&mut app), sent[10..].to_vec());
+    }
+}
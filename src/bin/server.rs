@@ -0,0 +1,3 @@
+fn main() -> anyhow::Result<()> {
+    asteroids_3d_lib::server_main()
+}
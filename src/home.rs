@@ -1,197 +1,126 @@
-#[cfg(not(target_arch = "wasm32"))]
-use bevy::app::AppExit;
-use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
 
-use crate::util::{cursor_locked, set_grab_cursor};
+use crate::in_game::RunKind;
+use crate::players::PlayerDevices;
+use crate::ui::{Menu, MenuAction, MenuAlign, MenuButton, MenuPlugin, StateScoped, UiTheme};
+use crate::util::cursor_locked;
+use crate::AppState;
 
 pub struct HomePlugin;
 
 impl Plugin for HomePlugin {
     fn build(&self, app: &mut AppBuilder) {
-        let update_set = SystemSet::on_update(crate::AppState::Home)
-            .with_system(button_system.system())
-            .with_system(resume_button_system.system())
-            .with_system(resume.system())
-            .with_system(update_gamepad.system());
-        #[cfg(not(target_arch = "wasm32"))]
-        let update_set = update_set.with_system(quit_button_system.system());
-        app.init_resource::<ButtonMaterials>()
-            .add_system_set(
-                SystemSet::on_enter(crate::AppState::Home).with_system(setup_menu.system()),
-            )
-            .add_system_set(update_set)
-            .add_system_set(
-                SystemSet::on_exit(crate::AppState::Home).with_system(remove_menu.system()),
-            );
-    }
-}
-
-struct ButtonMaterials {
-    normal: Handle<ColorMaterial>,
-    hovered: Handle<ColorMaterial>,
-    pressed: Handle<ColorMaterial>,
-}
-
-impl FromWorld for ButtonMaterials {
-    fn from_world(world: &mut World) -> Self {
-        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
-        ButtonMaterials {
-            normal: materials.add(Color::rgba(0.15, 0.15, 0.15, 0.).into()),
-            hovered: materials.add(Color::rgba(0.25, 0.25, 0.25, 0.9).into()),
-            pressed: materials.add(Color::rgb(0.2, 0.2, 0.2).into()),
-        }
+        app.add_plugin(MenuPlugin {
+            state: AppState::Home,
+            menu: Menu {
+                title: None,
+                buttons: vec![
+                    MenuButton::new("START", MenuAction::Start(RunKind::Normal)),
+                    MenuButton::new("DAILY", MenuAction::Start(RunKind::Daily)),
+                    MenuButton::new("GHOST RACE", MenuAction::Start(RunKind::Ghost)),
+                    MenuButton::new("PLAYERS", MenuAction::Adjust("players")),
+                    MenuButton::new("ONLINE", MenuAction::Replace(AppState::Online)),
+                    MenuButton::new("SETTINGS", MenuAction::Push(AppState::Settings)),
+                    MenuButton::new("QUIT", MenuAction::Quit),
+                ],
+                align: MenuAlign::Left,
+                back: None,
+            },
+        })
+        .add_system_set(SystemSet::on_enter(AppState::Home).with_system(setup_controls.system()))
+        .add_system_set(SystemSet::on_resume(AppState::Home).with_system(setup_controls.system()))
+        .add_system_set(
+            SystemSet::on_update(AppState::Home)
+                .with_system(resume.system())
+                .with_system(update_gamepad.system())
+                .with_system(update_device_list.system()),
+        );
     }
 }
 
-fn create_button<'a, 'c>(
-    parent: &'c mut ChildBuilder<'a, '_>,
-    button_materials: &ButtonMaterials,
-    font: Handle<Font>,
-    text: &str,
-) -> EntityCommands<'a, 'c> {
-    let mut button = parent.spawn_bundle(ButtonBundle {
-        style: Style {
-            size: Size::new(Val::Percent(100.), Val::Auto),
-            padding: Rect::all(Val::Px(5.)),
-            margin: Rect::all(Val::Auto),
-            justify_content: JustifyContent::Center,
-            align_items: AlignItems::Center,
-            ..Default::default()
-        },
-        material: button_materials.normal.clone(),
-        ..Default::default()
-    });
-    button.with_children(|parent| {
-        parent.spawn_bundle(TextBundle {
-            text: Text::with_section(
-                text,
-                TextStyle {
-                    font,
-                    font_size: 40.0,
-                    color: Color::rgb(0.65, 0.65, 0.65),
-                },
-                Default::default(),
-            ),
-            ..Default::default()
-        });
-    });
-    button
-}
-
-struct PartOfUi;
-
-struct StartButton;
 struct GamepadInstructions;
 
-#[cfg(not(target_arch = "wasm32"))]
-struct QuitButton;
+/// Connected gamepads and who they belong to
+struct DeviceList;
 
-fn setup_menu(
+/// Pictures of the controls, to the right of the menu, and the connected gamepads below them
+fn setup_controls(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    button_materials: Res<ButtonMaterials>,
+    theme: Res<UiTheme>,
     mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
     commands
         .spawn_bundle(NodeBundle {
             style: Style {
                 size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
-                justify_content: JustifyContent::FlexEnd,
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
                 align_items: AlignItems::Center,
-                flex_direction: FlexDirection::ColumnReverse,
                 ..Default::default()
             },
-            material: materials.add(Color::rgba(0., 0., 0.05, 0.85).into()),
+            material: theme.transparent.clone(),
             ..Default::default()
         })
-        .insert(PartOfUi)
+        .insert(StateScoped(AppState::Home))
         .with_children(|parent| {
+            parent.spawn_bundle(ImageBundle {
+                style: Style {
+                    size: Size::new(Val::Px(250.0), Val::Px(442.0)),
+                    ..Default::default()
+                },
+                material: materials.add(asset_server.load(asset!("controls/keyboard.png")).into()),
+                ..Default::default()
+            });
             parent
-                .spawn_bundle(NodeBundle {
+                .spawn_bundle(ImageBundle {
                     style: Style {
-                        size: Size::new(Val::Px(200.), Val::Percent(100.)),
-                        margin: Rect {
-                            left: Val::Px(30.),
-                            ..Default::default()
-                        },
-                        flex_direction: FlexDirection::Column,
-                        justify_content: JustifyContent::Center,
+                        size: Size::new(Val::Px(514.0), Val::Px(396.0)),
+                        display: Display::None,
                         ..Default::default()
                     },
-                    material: materials.add(Color::NONE.into()),
+                    material: materials
+                        .add(asset_server.load(asset!("controls/gamepad.png")).into()),
                     ..Default::default()
                 })
-                .with_children(|parent| {
-                    parent
-                        .spawn_bundle(NodeBundle {
-                            style: Style {
-                                flex_direction: FlexDirection::ColumnReverse,
-                                ..Default::default()
-                            },
-                            material: materials.add(Color::NONE.into()),
-                            ..Default::default()
-                        })
-                        .with_children(|parent| {
-                            create_button(
-                                parent,
-                                &button_materials,
-                                asset_server.load(asset!("RobotoCondensed-Regular.ttf")),
-                                "START",
-                            )
-                            .insert(StartButton);
-                            #[cfg(not(target_arch = "wasm32"))]
-                            create_button(
-                                parent,
-                                &button_materials,
-                                asset_server.load(asset!("RobotoCondensed-Regular.ttf")),
-                                "QUIT",
-                            )
-                            .insert(QuitButton);
-                        });
-                });
-            parent
-                .spawn_bundle(NodeBundle {
-                    material: materials.add(Color::NONE.into()),
-                    ..Default::default()
-                })
-                .with_children(|parent| {
-                    parent.spawn_bundle(ImageBundle {
-                        style: Style {
-                            size: Size::new(Val::Px(250.0), Val::Px(442.0)),
-                            ..Default::default()
-                        },
-                        material: materials
-                            .add(asset_server.load(asset!("controls/keyboard.png")).into()),
-                        ..Default::default()
-                    });
-                    parent
-                        .spawn_bundle(ImageBundle {
-                            style: Style {
-                                size: Size::new(Val::Px(514.0), Val::Px(396.0)),
-                                display: Display::None,
-                                ..Default::default()
-                            },
-                            material: materials
-                                .add(asset_server.load(asset!("controls/gamepad.png")).into()),
-                            ..Default::default()
-                        })
-                        .insert(GamepadInstructions);
-                });
+                .insert(GamepadInstructions);
         });
-}
-
-fn remove_menu(mut commands: Commands, interaction_query: Query<Entity, With<PartOfUi>>) {
-    interaction_query.for_each(|e| commands.entity(e).despawn_recursive());
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(10.0),
+                    right: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font_size: theme.font_size * 0.6,
+                    ..theme.text_style()
+                },
+                TextAlignment {
+                    horizontal: HorizontalAlign::Right,
+                    ..Default::default()
+                },
+            ),
+            ..Default::default()
+        })
+        .insert(DeviceList)
+        .insert(StateScoped(AppState::Home));
 }
 
 fn update_gamepad(
-    gamepad: Res<Option<Gamepad>>,
+    devices: Res<PlayerDevices>,
+    added: Query<(), Added<GamepadInstructions>>,
     mut q: Query<&mut Style, With<GamepadInstructions>>,
 ) {
-    if gamepad.is_changed() {
+    if devices.is_changed() || added.iter().next().is_some() {
         if let Ok(mut style) = q.single_mut() {
-            style.display = if gamepad.is_some() {
+            style.display = if !devices.connected().is_empty() {
                 Display::Flex
             } else {
                 Display::None
@@ -200,66 +129,9 @@ fn update_gamepad(
     }
 }
 
-fn button_system(
-    button_materials: Res<ButtonMaterials>,
-    mut interaction_query: Query<
-        (&Interaction, &mut Handle<ColorMaterial>, &Children),
-        (Changed<Interaction>, With<Button>),
-    >,
-    mut text_query: Query<&mut Text>,
-) {
-    for (interaction, mut material, children) in interaction_query.iter_mut() {
-        let mut text = text_query.get_mut(children[0]).unwrap();
-        match *interaction {
-            Interaction::Clicked => {
-                *material = button_materials.pressed.clone();
-            }
-            Interaction::Hovered => {
-                *material = button_materials.hovered.clone();
-                text.sections[0].style.color = Color::rgb(1., 1., 1.);
-            }
-            Interaction::None => {
-                *material = button_materials.normal.clone();
-                text.sections[0].style.color = Color::rgb(0.65, 0.65, 0.65);
-            }
-        }
-    }
-}
-
-fn resume_button_system(
-    mut windows: ResMut<Windows>,
-    #[cfg(target_arch = "wasm32")] winit_windows: Res<bevy::winit::WinitWindows>,
-    interaction_query: Query<&Interaction, (Changed<Interaction>, With<StartButton>)>,
-    mut change_next: Local<bool>,
-) {
-    if let Ok(interaction) = interaction_query.single() {
-        if *interaction == Interaction::Clicked {
-            *change_next = true;
-        } else if *change_next {
-            let window = windows.get_primary_mut().unwrap();
-            set_grab_cursor(
-                window,
-                true,
-                #[cfg(target_arch = "wasm32")]
-                &winit_windows,
-            );
-            *change_next = false;
-        }
-    }
-}
-
-#[cfg(not(target_arch = "wasm32"))]
-fn quit_button_system(
-    interaction_query: Query<&Interaction, (Changed<Interaction>, With<QuitButton>)>,
-    mut app_exit_events: EventWriter<AppExit>,
-) {
-    if matches!(interaction_query.single(), Ok(&Interaction::Clicked)) {
-        app_exit_events.send(AppExit);
-    }
-}
-
+/// Start the game once START has grabbed the cursor
 fn resume(
-    mut state: ResMut<State<crate::AppState>>,
+    mut state: ResMut<State<AppState>>,
     windows: ResMut<Windows>,
     #[cfg(target_arch = "wasm32")] winit_windows: Res<bevy::winit::WinitWindows>,
 ) {
@@ -269,6 +141,36 @@ fn resume(
         #[cfg(target_arch = "wasm32")]
         &winit_windows,
     ) {
-        log_error!(state.replace(crate::AppState::InGame));
+        log_error!(state.replace(AppState::InGame));
+    }
+}
+
+fn update_device_list(
+    devices: Res<PlayerDevices>,
+    added: Query<(), Added<DeviceList>>,
+    mut query: Query<&mut Text, With<DeviceList>>,
+) {
+    if !devices.is_changed() && added.iter().next().is_none() {
+        return;
+    }
+    let waiting = devices.waiting();
+    let lines: Vec<_> = devices
+        .connected()
+        .iter()
+        .map(|&gamepad| {
+            let role = match (devices.owner(gamepad), waiting) {
+                (Some(player), _) => format!("PLAYER {}", player + 1),
+                (None, Some(player)) => format!("PRESS A FOR PLAYER {}", player + 1),
+                (None, None) => "MENUS ONLY".to_string(),
+            };
+            format!("GAMEPAD {} - {}", gamepad.0, role)
+        })
+        .collect();
+    for mut text in query.iter_mut() {
+        text.sections[0].value = if lines.is_empty() {
+            "NO GAMEPADS".to_string()
+        } else {
+            lines.join("\n")
+        };
     }
 }
@@ -1,72 +1,135 @@
+use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
 use bevy_rapier3d::physics::{RapierConfiguration, RapierPhysicsPlugin};
 use bevy_rapier3d::rapier::dynamics::MassProperties;
 use bevy_rapier3d::rapier::math::Vector;
 
+pub use boost::BoostSettings;
+pub use controls::{fly, Controls, FlightAssist, MovementSettings, Player, ReadInput, ShipInput};
+pub use events::VersusSettings;
+pub use laser::beam_transform;
 pub use points::Points;
+pub use records::Records;
+pub use replica::{BodyKind, Looks, Replica, ReplicaPlugin, ShipReplica};
+pub use run::{Run, RunKind};
 
 use crate::custom_asset;
 use crate::in_game::controls::Controllable;
+use crate::players::PlayerDevices;
+use crate::settings::Settings;
+use crate::util::set_grab_cursor;
 
 mod asteroids;
+mod boost;
 mod bounds;
+mod bullets;
 mod controls;
+mod daily;
+mod enemies;
+mod energy;
 mod events;
 mod game_area;
+mod ghost;
+mod hud;
+mod laser;
+mod missiles;
+mod particles;
 mod points;
+mod powerups;
+mod radar;
+mod records;
+mod replica;
+mod run;
+mod threats;
 
 pub struct InGamePlugin;
 
 impl Plugin for InGamePlugin {
     fn build(&self, app: &mut AppBuilder) {
         let state = super::AppState::InGame;
+        app.add_plugin(SimulationPlugin(state))
+            .add_plugin(controls::ControlPlugin(state))
+            .add_plugin(energy::EnergyHudPlugin(state))
+            .add_plugin(ghost::GhostPlugin(state))
+            .add_plugin(hud::HudPlugin(state))
+            .add_plugin(laser::LaserHudPlugin(state))
+            .add_plugin(missiles::MissilesHudPlugin(state))
+            .add_plugin(particles::ParticlesPlugin(state))
+            .add_plugin(points::PointsHudPlugin(state))
+            .add_plugin(powerups::PowerUpsHudPlugin(state))
+            .add_plugin(radar::RadarPlugin(state))
+            .add_plugin(records::RecordsPlugin(state))
+            .add_plugin(threats::ThreatsPlugin(state))
+            .add_system(scale_hud.system())
+            .add_system_set(SystemSet::on_enter(state).with_system(enter.system()))
+            .add_system_set(SystemSet::on_resume(state).with_system(resume.system()))
+            .add_system_set(SystemSet::on_pause(state).with_system(pause.system()))
+            .add_system_set(SystemSet::on_exit(state).with_system(release_cursor.system()));
+    }
+}
+
+/// The game itself: ships flown by their [`ShipInput`] and everything they run into. None of it
+/// needs a window or anyone at the keyboard, so the server runs it too.
+pub struct SimulationPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for SimulationPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        let state = self.0.clone();
         app.add_plugin(RapierPhysicsPlugin)
             .insert_resource(RapierConfiguration {
                 gravity: Vector::default(),
                 ..Default::default()
             })
-            .add_plugin(asteroids::AsteroidsPlugin(state))
-            .add_plugin(controls::ControlPlugin(state))
-            .add_plugin(events::EventsPlugin(state))
-            .add_plugin(game_area::GameAreaPlugin(state))
-            .add_plugin(bounds::CalcBoundsPlugin(state))
-            .add_plugin(points::PointsPlugin(state))
-            .add_system_set(SystemSet::on_enter(state).with_system(enter.system()))
-            .add_system_set(SystemSet::on_resume(state).with_system(resume.system()))
-            .add_system_set(SystemSet::on_pause(state).with_system(pause.system()))
-            .add_system_set(SystemSet::on_exit(state).with_system(exit.system()));
+            .add_plugin(ArenaPlugin(state.clone()))
+            .add_plugin(asteroids::AsteroidsPlugin(state.clone()))
+            .add_plugin(boost::BoostPlugin(state.clone()))
+            .add_plugin(bullets::BulletsPlugin(state.clone()))
+            .add_plugin(controls::FlightPlugin(state.clone()))
+            .add_plugin(enemies::EnemiesPlugin(state.clone()))
+            .add_plugin(energy::EnergyPlugin(state.clone()))
+            .add_plugin(events::EventsPlugin(state.clone()))
+            .add_plugin(missiles::MissilesPlugin(state.clone()))
+            .add_plugin(laser::LaserPlugin(state.clone()))
+            .add_plugin(points::PointsPlugin(state.clone()))
+            .add_plugin(powerups::PowerUpsPlugin(state.clone()))
+            .add_plugin(run::RunPlugin(state));
     }
 }
 
-struct Bullet;
+/// The game area's walls and the colliders of what's in it. Everything [`TiedToGame`] is
+/// despawned when `T` is left. Online clients fly their own ship in it ahead of the server.
+pub struct ArenaPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for ArenaPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_plugin(game_area::GameAreaPlugin(self.0.clone()))
+            .add_plugin(bounds::CalcBoundsPlugin(self.0.clone()))
+            .add_system_set(SystemSet::on_exit(self.0.clone()).with_system(exit.system()));
+    }
+}
+
+pub struct Bullet;
+
+/// Asteroid hits dealt by a bullet, if more than one
+struct Damage(u8);
 
 #[derive(Default)]
 struct TiedToGame;
 
-fn enter(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let mut transform = Transform::from_xyz(0., 0., 20.);
-    transform.scale = Vec3::splat(0.1);
-    transform.rotation = Quat::from_rotation_y(std::f32::consts::PI);
-    commands
-        .spawn_bundle(PbrBundle {
-            mesh: asset_server.load(asset!("ship.glb", "Mesh0/Primitive0")),
-            material: asset_server.load(asset!("ship.glb", "Material0")),
-            ..Default::default()
-        })
-        .insert(Controllable)
-        .insert(bounds::CalcBounds)
-        .insert(TiedToGame)
-        .insert(
-            asset_server.load::<custom_asset::CustomAsset, _>(asset!(
-                "vhacd/ship.custom",
-                "Mesh0/Primitive0"
-            )),
-        )
-        .insert(MassProperties::from_cuboid(
-            1.,
-            Vector::from_row_slice(&[0.5, 0.5, 0.5]),
-        ))
-        .with_children(|parent| {
+/// A HUD node, drawn bigger or smaller with the HUD scale setting
+struct HudElement;
+
+/// HUD text. Text ignores its transform's scale, so its font size is scaled instead.
+struct HudText {
+    font_size: f32,
+}
+
+/// Spawn a ship for each player, side by side
+fn enter(mut commands: Commands, asset_server: Res<AssetServer>, devices: Res<PlayerDevices>) {
+    let players = devices.count();
+    for player in 0..players {
+        let mut ship = spawn_player_ship(&mut commands, &asset_server, player, players);
+        ship.with_children(|parent| {
             parent.spawn_bundle(LightBundle {
                 light: Light {
                     color: Color::rgb(1.0, 1.0, 1.0),
@@ -78,6 +141,80 @@ fn enter(mut commands: Commands, asset_server: Res<AssetServer>) {
                 ..Default::default()
             });
         });
+        if player == 0 {
+            ship.insert(Controllable);
+        }
+    }
+}
+
+/// Spawn `player`'s ship at its start, side by side with the other `players`
+pub fn spawn_player_ship<'a, 'b>(
+    commands: &'b mut Commands<'a>,
+    asset_server: &AssetServer,
+    player: usize,
+    players: usize,
+) -> EntityCommands<'a, 'b> {
+    let x = (player as f32 - (players - 1) as f32 / 2.) * 10.;
+    let mut transform = Transform::from_xyz(x, 0., 20.);
+    transform.rotation = Quat::from_rotation_y(std::f32::consts::PI);
+    let mut ship = spawn_ship(
+        commands,
+        asset_server,
+        asset_server.load(asset!("ship.glb", "Material0")),
+        transform,
+    );
+    ship.insert(Player(player));
+    ship
+}
+
+/// Spawn a ship model with its physics set up. Used for both the player and enemies.
+fn spawn_ship<'a, 'b>(
+    commands: &'b mut Commands<'a>,
+    asset_server: &AssetServer,
+    material: Handle<StandardMaterial>,
+    mut transform: Transform,
+) -> EntityCommands<'a, 'b> {
+    transform.scale = Vec3::splat(0.1);
+    let mut ship = commands.spawn_bundle(PbrBundle {
+        mesh: asset_server.load(asset!("ship.glb", "Mesh0/Primitive0")),
+        material,
+        transform,
+        ..Default::default()
+    });
+    ship.insert(bounds::CalcBounds)
+        .insert(TiedToGame)
+        .insert(
+            asset_server.load::<custom_asset::CustomAsset, _>(asset!(
+                "vhacd/ship.custom",
+                "Mesh0/Primitive0"
+            )),
+        )
+        .insert(MassProperties::from_cuboid(
+            1.,
+            Vector::from_row_slice(&[0.5, 0.5, 0.5]),
+        ));
+    ship
+}
+
+fn scale_hud(
+    settings: Res<Settings>,
+    mut nodes: Query<&mut Transform, With<HudElement>>,
+    mut texts: Query<(&HudText, &mut Text)>,
+) {
+    let scale = Vec3::splat(settings.hud_scale);
+    for mut transform in nodes.iter_mut() {
+        if transform.scale != scale {
+            transform.scale = scale;
+        }
+    }
+    for (hud_text, mut text) in texts.iter_mut() {
+        let font_size = hud_text.font_size * settings.hud_scale;
+        if text.sections.iter().any(|s| s.style.font_size != font_size) {
+            for section in text.sections.iter_mut() {
+                section.style.font_size = font_size;
+            }
+        }
+    }
 }
 
 fn resume(mut config: ResMut<RapierConfiguration>) {
@@ -90,6 +227,21 @@ fn pause(mut config: ResMut<RapierConfiguration>) {
     config.query_pipeline_active = false;
 }
 
+/// Let go of the mouse for whatever comes after the game
+fn release_cursor(
+    mut windows: ResMut<Windows>,
+    #[cfg(target_arch = "wasm32")] winit_windows: Res<bevy::winit::WinitWindows>,
+) {
+    if let Some(window) = windows.get_primary_mut() {
+        set_grab_cursor(
+            window,
+            false,
+            #[cfg(target_arch = "wasm32")]
+            &winit_windows,
+        );
+    }
+}
+
 fn exit(mut commands: Commands, despawn: Query<Entity, With<TiedToGame>>) {
     despawn.for_each(|e| commands.entity(e).despawn_recursive());
 }
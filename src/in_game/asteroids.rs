@@ -9,12 +9,15 @@ use crate::in_game::TiedToGame;
 
 use super::bounds::ColliderProps;
 use super::game_area::{HEIGHT, LENGTH, WIDTH};
+use super::run::{Run, RunRng};
 
 pub struct AsteroidsPlugin<T>(pub T);
 
 impl<T: crate::util::StateType> Plugin for AsteroidsPlugin<T> {
     fn build(&self, app: &mut AppBuilder) {
         app.init_resource::<Asteroids>()
+            .init_resource::<SpawnTimer>()
+            .add_system_set(SystemSet::on_enter(self.0.clone()).with_system(reset_timer.system()))
             .add_system_set(SystemSet::on_update(self.0.clone()).with_system(spawn.system()));
     }
 }
@@ -154,35 +157,48 @@ impl Distribution<[f32; 3]> for OpenNeg11 {
     }
 }
 
+/// Time until the next asteroid comes in
+struct SpawnTimer(Timer);
+
+impl Default for SpawnTimer {
+    fn default() -> Self {
+        SpawnTimer(Timer::from_seconds(2., false))
+    }
+}
+
+/// Every run starts with the same wait, so runs on the same seed match up
+fn reset_timer(mut timer: ResMut<SpawnTimer>) {
+    *timer = SpawnTimer::default();
+}
+
 fn spawn(
     mut commands: Commands,
     time: Res<Time>,
     asteroids: Res<Asteroids>,
-    mut timer_opt: Local<Option<Timer>>,
+    run: Res<Run>,
+    mut rng: ResMut<RunRng>,
+    mut timer: ResMut<SpawnTimer>,
 ) {
-    let mut rng = rand::thread_rng();
+    let rng = &mut rng.asteroids;
     let radius = (LENGTH * LENGTH + WIDTH * WIDTH + HEIGHT * HEIGHT).sqrt();
-    if let Some(ref mut timer) = *timer_opt {
-        if timer.tick(time.delta()).finished() {
-            *timer = Timer::from_seconds(rng.gen_range(1.0..3.0), false);
-            let vec = Vec3::from(rng.sample::<[f32; 3], _>(OpenNeg11)).normalize();
-            let position = vec * radius;
-            let mut origin = Transform::from_translation(position);
-            origin.scale = Vec3::new(
-                rng.gen_range(0.5..1.5),
-                rng.gen_range(0.5..1.5),
-                rng.gen_range(0.5..1.5),
-            );
-            let child = (asteroids.0).choose(&mut rng).unwrap();
-            commands.spawn_bundle(AsteroidBundle {
-                collider_props: ColliderProps {
-                    linvel: -vec * rng.gen_range(5.0..8.0),
-                    ..Default::default()
-                },
-                ..AsteroidBundle::new(&child, origin)
-            });
-        }
-    } else {
-        *timer_opt = Some(Timer::from_seconds(2., false));
+    if timer.0.tick(time.delta()).finished() {
+        let (min, max) = run.waves.interval;
+        timer.0 = Timer::from_seconds(rng.gen_range(min..max), false);
+        let vec = Vec3::from(rng.sample::<[f32; 3], _>(OpenNeg11)).normalize();
+        let position = vec * radius;
+        let mut origin = Transform::from_translation(position);
+        origin.scale = Vec3::new(
+            rng.gen_range(0.5..1.5),
+            rng.gen_range(0.5..1.5),
+            rng.gen_range(0.5..1.5),
+        );
+        let child = (asteroids.0).choose(rng).unwrap();
+        commands.spawn_bundle(AsteroidBundle {
+            collider_props: ColliderProps {
+                linvel: -vec * rng.gen_range(run.waves.speed.0..run.waves.speed.1),
+                ..Default::default()
+            },
+            ..AsteroidBundle::new(&child, origin)
+        });
     }
 }
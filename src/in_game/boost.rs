@@ -0,0 +1,69 @@
+use bevy::prelude::*;
+
+use super::controls::{Player, ReadInput, ShipInput};
+use super::energy::Energy;
+
+pub struct BoostPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for BoostPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<BoostSettings>().add_system_set(
+            SystemSet::on_update(self.0.clone())
+                .with_system(equip.system())
+                .with_system(boost.system().label(BoostInput).after(ReadInput)),
+        );
+    }
+}
+
+/// Decides whether each ship is boosting this frame
+#[derive(SystemLabel, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub(super) struct BoostInput;
+
+pub struct BoostSettings {
+    /// Acceleration is multiplied by this while boosting
+    pub accel_multiplier: f32,
+    /// The speed limit is multiplied by this while boosting
+    pub speed_multiplier: f32,
+    pub energy_per_second: f32,
+    /// Boosting can't start with less energy than this, so it doesn't stutter when empty
+    pub min_energy: f32,
+}
+
+impl Default for BoostSettings {
+    fn default() -> Self {
+        Self {
+            accel_multiplier: 2.5,
+            speed_multiplier: 3.,
+            energy_per_second: 35.,
+            min_energy: 10.,
+        }
+    }
+}
+
+/// Whether a ship is boosting
+#[derive(Default)]
+pub struct Boost {
+    pub active: bool,
+}
+
+fn equip(mut commands: Commands, ships: Query<Entity, (With<Player>, Without<Boost>)>) {
+    for ship in ships.iter() {
+        commands.entity(ship).insert(Boost::default());
+    }
+}
+
+fn boost(
+    time: Res<Time>,
+    settings: Res<BoostSettings>,
+    mut query: Query<(&ShipInput, &mut Energy, &mut Boost)>,
+) {
+    for (input, mut energy, mut boost) in query.iter_mut() {
+        let can_start = boost.active || energy.current >= settings.min_energy;
+        let active = input.boost
+            && can_start
+            && energy.drain(settings.energy_per_second * time.delta_seconds());
+        if boost.active != active {
+            boost.active = active;
+        }
+    }
+}
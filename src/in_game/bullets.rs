@@ -0,0 +1,288 @@
+use bevy::ecs::system::SystemParam;
+use bevy::math::const_vec3;
+use bevy::prelude::*;
+use bevy_rapier3d::physics::RigidBodyHandleComponent;
+use bevy_rapier3d::rapier::dynamics::{RigidBodyBuilder, RigidBodySet};
+use bevy_rapier3d::rapier::geometry::ColliderBuilder;
+use bevy_rapier3d::rapier::math::Vector;
+
+use crate::in_game::TiedToGame;
+
+use super::enemies::EnemyBullet;
+
+/// Number of bullets kept around to be reused
+const POOL_SIZE: usize = 64;
+const LIFETIME: f32 = 5.;
+/// Where bullets come out of a ship, relative to it
+pub(super) const MUZZLE_OFFSET: Vec3 = const_vec3!([0., 0., 5.]);
+pub(super) const SPEED: f32 = 45.;
+
+pub struct BulletsPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for BulletsPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<BulletAssets>()
+            .init_resource::<BulletPool>()
+            .add_event::<RemoveBullet>()
+            .add_system_set(SystemSet::on_enter(self.0.clone()).with_system(fill_pool.system()))
+            .add_system_set(SystemSet::on_exit(self.0.clone()).with_system(empty_pool.system()))
+            .add_system_set(
+                SystemSet::on_update(self.0.clone())
+                    .with_system(expire.system())
+                    .with_system(recycle.system()),
+            );
+    }
+}
+
+pub struct BulletAssets {
+    pub(super) mesh: Handle<Mesh>,
+    pub(super) material: Handle<StandardMaterial>,
+    pub(super) enemy_material: Handle<StandardMaterial>,
+}
+
+impl FromWorld for BulletAssets {
+    fn from_world(world: &mut World) -> Self {
+        BulletAssets {
+            mesh: world
+                .get_resource_mut::<Assets<Mesh>>()
+                .unwrap()
+                .add(Mesh::from(shape::Capsule {
+                    ..Default::default()
+                })),
+            material: world
+                .get_resource_mut::<Assets<StandardMaterial>>()
+                .unwrap()
+                .add(StandardMaterial {
+                    base_color: Color::rgb(1., 0., 0.),
+                    emissive: Color::rgb(0.65, 0., 0.),
+                    ..Default::default()
+                }),
+            enemy_material: world
+                .get_resource_mut::<Assets<StandardMaterial>>()
+                .unwrap()
+                .add(StandardMaterial {
+                    base_color: Color::rgb(0., 1., 0.2),
+                    emissive: Color::rgb(0., 0.65, 0.1),
+                    ..Default::default()
+                }),
+        }
+    }
+}
+
+/// Bullets that are parked out of the way, ready to be fired again
+#[derive(Default)]
+pub struct BulletPool {
+    free: Vec<Entity>,
+    /// Bullets taken out of play this frame. Their markers are only removed once commands are
+    /// applied, so they're freed the frame after, before anything can fire them again.
+    recycled: Vec<Entity>,
+}
+
+pub struct PooledBullet {
+    pub(super) active: bool,
+    lifetime: Timer,
+}
+
+/// Who fired a bullet
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) enum Shooter {
+    Player(usize),
+    Enemy,
+}
+
+/// The player who fired a bullet, so they get the points for what it hits
+pub(super) struct FiredBy(pub usize);
+
+/// Take a bullet out of play. Pooled bullets go back to the pool, anything else (e.g. missiles)
+/// is despawned.
+pub(super) struct RemoveBullet(pub Entity);
+
+/// Where an inactive bullet waits, far outside of the game area. Every bullet gets its own spot
+/// so they don't collide with each other and wake up.
+fn parking_spot(entity: Entity) -> Vec3 {
+    Vec3::new(entity.id() as f32 * 5., -10_000., 0.)
+}
+
+fn bullet_builder(position: Vec3, rotation: Quat, linvel: Vec3) -> RigidBodyBuilder {
+    RigidBodyBuilder::new_dynamic()
+        .position(crate::util::nalgebra_pos(position, rotation))
+        .linvel(linvel.x, linvel.y, linvel.z)
+}
+
+fn fill_pool(mut commands: Commands, assets: Res<BulletAssets>, mut pool: ResMut<BulletPool>) {
+    pool.free.clear();
+    pool.recycled.clear();
+    for _ in 0..POOL_SIZE {
+        let mut bullet = commands.spawn_bundle(PbrBundle {
+            mesh: assets.mesh.clone(),
+            material: assets.material.clone(),
+            visible: Visible {
+                is_visible: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let entity = bullet.id();
+        let rigid_body = bullet_builder(parking_spot(entity), Quat::IDENTITY, Vec3::ZERO);
+        bullet
+            .insert(PooledBullet {
+                active: false,
+                lifetime: Timer::from_seconds(LIFETIME, false),
+            })
+            .insert(TiedToGame)
+            .insert(rigid_body.sleeping(true))
+            .insert(ColliderBuilder::capsule_y(0.5, 0.1));
+        pool.free.push(entity);
+    }
+}
+
+fn empty_pool(mut pool: ResMut<BulletPool>) {
+    pool.free.clear();
+    pool.recycled.clear();
+}
+
+/// Fires bullets, reusing them from the pool when possible
+#[derive(SystemParam)]
+pub struct Bullets<'a> {
+    commands: Commands<'a>,
+    assets: Res<'a, BulletAssets>,
+    pool: ResMut<'a, BulletPool>,
+    rigid_bodies: ResMut<'a, RigidBodySet>,
+    pooled: Query<
+        'a,
+        (
+            &'static mut PooledBullet,
+            &'static mut Visible,
+            &'static mut Handle<StandardMaterial>,
+            &'static RigidBodyHandleComponent,
+        ),
+    >,
+}
+
+impl<'a> Bullets<'a> {
+    /// Fire a bullet out of the nose of `ship`
+    pub fn fire(&mut self, ship: &Transform, shooter: Shooter) {
+        let translation = ship.translation + ship.rotation * MUZZLE_OFFSET;
+        let rotation = ship.rotation * Quat::from_rotation_x(std::f32::consts::FRAC_PI_2);
+        let linvel = ship.local_z() * SPEED;
+        let material = match shooter {
+            Shooter::Enemy => self.assets.enemy_material.clone(),
+            Shooter::Player(_) => self.assets.material.clone(),
+        };
+
+        // Bullets spawned this frame don't have a rigid body yet, so they can't be reused
+        let reusable = match self.pool.free.last() {
+            Some(&entity) => self.pooled.get_mut(entity).is_ok(),
+            None => false,
+        };
+        let entity = match self.pool.free.pop() {
+            Some(entity) if reusable => {
+                let (mut bullet, mut visible, mut bullet_material, rigid_body_component) =
+                    self.pooled.get_mut(entity).unwrap();
+                if let Some(rb) = self.rigid_bodies.get_mut(rigid_body_component.handle()) {
+                    rb.set_position(crate::util::nalgebra_pos(translation, rotation), true);
+                    rb.set_linvel(Vector::from(linvel), true);
+                    rb.set_angvel(Vector::zeros(), true);
+                }
+                bullet.active = true;
+                bullet.lifetime.reset();
+                visible.is_visible = true;
+                *bullet_material = material;
+                entity
+            }
+            // Pool's empty, so grow it
+            popped => {
+                if let Some(entity) = popped {
+                    self.pool.free.push(entity);
+                }
+                self.commands
+                    .spawn_bundle(PbrBundle {
+                        mesh: self.assets.mesh.clone(),
+                        material,
+                        transform: Transform {
+                            translation,
+                            rotation,
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    })
+                    .insert(PooledBullet {
+                        active: true,
+                        lifetime: Timer::from_seconds(LIFETIME, false),
+                    })
+                    .insert(TiedToGame)
+                    .insert(bullet_builder(translation, rotation, linvel))
+                    .insert(ColliderBuilder::capsule_y(0.5, 0.1))
+                    .id()
+            }
+        };
+        match shooter {
+            Shooter::Enemy => {
+                self.commands.entity(entity).insert(EnemyBullet);
+            }
+            Shooter::Player(player) => {
+                self.commands
+                    .entity(entity)
+                    .insert(super::Bullet)
+                    .insert(FiredBy(player));
+            }
+        }
+    }
+}
+
+fn expire(
+    time: Res<Time>,
+    mut removed: EventWriter<RemoveBullet>,
+    mut query: Query<(Entity, &mut PooledBullet)>,
+) {
+    for (entity, mut bullet) in query.iter_mut() {
+        if bullet.active && bullet.lifetime.tick(time.delta()).just_finished() {
+            removed.send(RemoveBullet(entity));
+        }
+    }
+}
+
+fn recycle(
+    mut commands: Commands,
+    mut removed: EventReader<RemoveBullet>,
+    mut pool: ResMut<BulletPool>,
+    mut rigid_bodies: ResMut<RigidBodySet>,
+    mut pooled: Query<(
+        &mut PooledBullet,
+        &mut Visible,
+        Option<&RigidBodyHandleComponent>,
+    )>,
+) {
+    let pool = &mut *pool;
+    pool.free.append(&mut pool.recycled);
+    for &RemoveBullet(entity) in removed.iter() {
+        let (mut bullet, mut visible, rigid_body_component) = match pooled.get_mut(entity) {
+            Ok(bullet) => bullet,
+            Err(_) => {
+                commands.entity(entity).despawn_recursive();
+                continue;
+            }
+        };
+        // It might have hit more than one thing at once
+        if !bullet.active {
+            continue;
+        }
+        bullet.active = false;
+        visible.is_visible = false;
+        if let Some(rb) = rigid_body_component.and_then(|rb| rigid_bodies.get_mut(rb.handle())) {
+            rb.set_position(
+                crate::util::nalgebra_pos(parking_spot(entity), Quat::IDENTITY),
+                false,
+            );
+            rb.set_linvel(Vector::zeros(), false);
+            rb.set_angvel(Vector::zeros(), false);
+            rb.sleep();
+        }
+        commands
+            .entity(entity)
+            .remove::<super::Bullet>()
+            .remove::<EnemyBullet>()
+            .remove::<FiredBy>();
+        pool.recycled.push(entity);
+    }
+}
@@ -1,101 +1,288 @@
+use bevy::ecs::system::SystemParam;
 use bevy::input::mouse::MouseMotion;
 use bevy::prelude::*;
 use bevy_rapier3d::physics::RigidBodyHandleComponent;
-use bevy_rapier3d::rapier::dynamics::{RigidBodyBuilder, RigidBodySet};
-use bevy_rapier3d::rapier::geometry::ColliderBuilder;
+use bevy_rapier3d::rapier::dynamics::{RigidBody, RigidBodySet};
 use bevy_rapier3d::rapier::math::Vector;
 
-use crate::in_game::TiedToGame;
-use crate::util::DespawnTimer;
+use crate::audio::AudioCue;
+use crate::in_game::powerups::{self, ActivePowerUp, PowerUpKind, PowerUpSettings};
+use crate::players::{GamepadLost, PlayerDevices, PlayerInput, MAX_PLAYERS};
+use crate::settings::Settings;
 use crate::util::{cursor_locked, set_grab_cursor};
 
+use super::boost::{Boost, BoostInput, BoostSettings};
+use super::bullets::{Bullets, Shooter};
+use super::particles::Thrust;
+
 mod camera;
+mod shake;
+mod split_screen;
+mod stick;
+
+pub use camera::CameraMode;
+pub use split_screen::{MainView, SplitScreen};
+pub use stick::StickSettings;
+
+/// Flies and fires every player's ship, however its [`ShipInput`] was filled in
+pub struct FlightPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for FlightPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<MovementSettings>()
+            .init_resource::<FlightAssist>()
+            .add_system_set(
+                SystemSet::on_update(self.0.clone())
+                    .with_system(equip.system())
+                    .with_system(fly_ships.system().after(ReadInput).after(BoostInput))
+                    .with_system(shoot.system().after(ReadInput)),
+            );
+    }
+}
 
+/// The devices of the players at this computer, and the camera
 pub struct ControlPlugin<T>(pub T);
 
 impl<T: crate::util::StateType> Plugin for ControlPlugin<T> {
     fn build(&self, app: &mut AppBuilder) {
         let set = SystemSet::on_update(self.0.clone())
-            .with_system(player_move.system())
-            .with_system(player_look.system())
-            .with_system(shoot.system())
+            .with_system(read_input.system().label(ReadInput))
+            .with_system(cycle_flight_assist.system())
             .with_system(transition_to_pause.system())
-            .with_system(cursor_unlock_gamepad.system());
+            .with_system(cursor_unlock_gamepad.system())
+            .with_system(pause_on_gamepad_lost.system());
         #[cfg(not(target_arch = "wasm32"))]
         let set = set.with_system(cursor_unlock.system());
 
-        app.init_resource::<MovementSettings>()
-            .init_resource::<BulletAssets>()
+        app.add_plugin(split_screen::SplitScreenPlugin(self.0.clone()));
+        app.init_resource::<StickSettings>()
+            .add_system(apply_settings.system())
+            .add_system(stick::apply_settings.system())
             .add_system_set(set)
-            .add_plugin(camera::CameraPlugin(self.0.clone()));
+            .add_plugin(camera::CameraPlugin(self.0.clone()))
+            .add_plugin(shake::ShakePlugin(self.0.clone()));
     }
 }
 
+/// The ship the main camera and the HUD follow. Starts on player one's ship, and moves to player
+/// two's if player one is destroyed.
 pub struct Controllable;
 
-struct BulletAssets {
-    mesh: Handle<Mesh>,
-    material: Handle<StandardMaterial>,
+/// Which player a ship belongs to
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Player(pub usize);
+
+/// The gamepad of whoever flies the [`Controllable`] ship
+pub fn lead_gamepad(
+    devices: &PlayerDevices,
+    lead: &Query<&Player, With<Controllable>>,
+) -> Option<Gamepad> {
+    lead.single()
+        .ok()
+        .and_then(|player| devices.gamepad(player.0))
 }
 
-impl FromWorld for BulletAssets {
-    fn from_world(world: &mut World) -> Self {
-        BulletAssets {
-            mesh: world
-                .get_resource_mut::<Assets<Mesh>>()
-                .unwrap()
-                .add(Mesh::from(shape::Capsule {
-                    ..Default::default()
-                })),
-            material: world
-                .get_resource_mut::<Assets<StandardMaterial>>()
-                .unwrap()
-                .add(StandardMaterial {
-                    base_color: Color::rgb(1., 0., 0.),
-                    emissive: Color::rgb(0.65, 0., 0.),
-                    ..Default::default()
-                }),
+/// Mouse sensitivity before the player's multiplier from [`Settings`]
+const BASE_SENSITIVITY: f32 = 0.003;
+
+/// Mouse sensitivity, and how the ship handles in each flight assist mode
+pub struct MovementSettings {
+    pub sensitivity: f32,
+    /// Pitch up when moving the mouse or stick down
+    pub invert_y: bool,
+    pub assisted: FlightTuning,
+    pub newtonian: FlightTuning,
+    pub arcade: FlightTuning,
+}
+
+impl Default for MovementSettings {
+    fn default() -> Self {
+        Self {
+            sensitivity: BASE_SENSITIVITY,
+            invert_y: false,
+            assisted: FlightTuning {
+                accel: 50.,
+                speed_limit: 2.,
+                linear_damping: 0.5,
+                angular_damping: 0.1,
+                turn_limit: 0.5,
+            },
+            newtonian: FlightTuning {
+                accel: 50.,
+                speed_limit: 10.,
+                linear_damping: 0.,
+                angular_damping: 0.,
+                turn_limit: 1.,
+            },
+            arcade: FlightTuning {
+                accel: 50.,
+                speed_limit: 4.,
+                linear_damping: 0.,
+                angular_damping: 1.,
+                turn_limit: 0.5,
+            },
         }
     }
 }
 
-/// Mouse sensitivity and movement speed
-pub struct MovementSettings {
-    pub sensitivity: f32,
+impl MovementSettings {
+    pub fn tuning(&self, assist: FlightAssist) -> &FlightTuning {
+        match assist {
+            FlightAssist::Assisted => &self.assisted,
+            FlightAssist::Newtonian => &self.newtonian,
+            FlightAssist::Arcade => &self.arcade,
+        }
+    }
+}
+
+fn apply_settings(settings: Res<Settings>, mut movement: ResMut<MovementSettings>) {
+    if settings.is_changed() {
+        movement.sensitivity = BASE_SENSITIVITY * settings.sensitivity;
+        movement.invert_y = settings.invert_y;
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct FlightTuning {
     pub accel: f32,
+    /// Top speed along each of the ship's axes
     pub speed_limit: f32,
+    /// Strongest counter-force used to stop drift the player isn't asking for
+    pub linear_damping: f32,
+    /// Strongest counter-torque used to stop spin the player isn't asking for
+    pub angular_damping: f32,
+    /// Top turn rate around each of the ship's axes
+    pub turn_limit: f32,
 }
 
-impl Default for MovementSettings {
-    fn default() -> Self {
+impl FlightTuning {
+    /// How the ship handles while boosting
+    pub fn boosted(&self, boost: &BoostSettings) -> Self {
         Self {
-            sensitivity: 0.003,
-            accel: 50.,
-            speed_limit: 2.,
+            accel: self.accel * boost.accel_multiplier,
+            speed_limit: self.speed_limit * boost.speed_multiplier,
+            ..*self
+        }
+    }
+}
+
+/// How much the ship helps with flying
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FlightAssist {
+    /// Unwanted drift and spin are damped out
+    Assisted,
+    /// Nothing is damped, the ship keeps drifting until it's stopped by thrust
+    Newtonian,
+    /// The ship moves exactly where the controls point it
+    Arcade,
+}
+
+impl Default for FlightAssist {
+    fn default() -> Self {
+        FlightAssist::Assisted
+    }
+}
+
+impl FlightAssist {
+    fn next(self) -> Self {
+        match self {
+            FlightAssist::Assisted => FlightAssist::Newtonian,
+            FlightAssist::Newtonian => FlightAssist::Arcade,
+            FlightAssist::Arcade => FlightAssist::Assisted,
         }
     }
 }
 
-/// Handles keyboard input and movement
-fn player_move(
-    gamepad: Res<Option<Gamepad>>,
-    axes: Res<Axis<GamepadAxis>>,
-    button_axes: Res<Axis<GamepadButton>>,
+fn cycle_flight_assist(
     keys: Res<Input<KeyCode>>,
-    time: Res<Time>,
-    settings: Res<MovementSettings>,
-    mut rigid_bodies: ResMut<RigidBodySet>,
-    query: Query<(&Transform, &RigidBodyHandleComponent), With<Controllable>>,
+    devices: Res<PlayerDevices>,
+    button_inputs: Res<Input<GamepadButton>>,
+    mut assist: ResMut<FlightAssist>,
+    lead: Query<&Player, With<Controllable>>,
 ) {
-    for (transform, rigid_body_component) in query.iter() {
-        let transform: &Transform = transform;
-        let rigid_body_component: &RigidBodyHandleComponent = rigid_body_component;
+    let gamepad_button_pressed =
+        |gamepad| button_inputs.just_pressed(GamepadButton(gamepad, GamepadButtonType::North));
+    if keys.just_pressed(KeyCode::V)
+        || lead_gamepad(&devices, &lead).map_or(false, gamepad_button_pressed)
+    {
+        *assist = assist.next();
+    }
+}
+
+/// What a ship's pilot asks of it this frame, whether they're at this computer or playing online
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct ShipInput {
+    /// Thrust along the ship's own axes, up to 2 long
+    pub thrust: Vec3,
+    /// Torque to pitch and yaw with, around the ship's own x and y axes. Up to 1 long.
+    pub turn: Vec2,
+    pub fire: Trigger,
+    pub boost: bool,
+    pub laser: bool,
+    pub missile: Trigger,
+}
+
+/// A button that does something once when pressed, and maybe keeps doing it while held
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct Trigger {
+    pub pressed: bool,
+    pub just_pressed: bool,
+}
+
+impl Trigger {
+    /// This trigger a frame later, when all that's known is whether it's held
+    pub fn then(self, pressed: bool) -> Self {
+        Trigger {
+            pressed,
+            just_pressed: pressed && !self.pressed,
+        }
+    }
+}
+
+/// Fills in every [`ShipInput`]. Anything that flies or fires a ship goes after it.
+#[derive(SystemLabel, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct ReadInput;
+
+/// The keyboard, mouse and gamepads, read into a [`ShipInput`] the same way for local and
+/// online play
+#[derive(SystemParam)]
+pub struct Controls<'a> {
+    keys: Res<'a, Input<KeyCode>>,
+    mouse_buttons: Res<'a, Input<MouseButton>>,
+    axes: Res<'a, Axis<GamepadAxis>>,
+    button_axes: Res<'a, Axis<GamepadButton>>,
+    buttons: Res<'a, Input<GamepadButton>>,
+    settings: Res<'a, MovementSettings>,
+    sticks: Res<'a, StickSettings>,
+    assist: Res<'a, FlightAssist>,
+}
+
+impl<'a> Controls<'a> {
+    /// What the devices of `input` ask of a ship over the last `delta` seconds. `mouse` is how
+    /// far the mouse moved, if it steers the ship, and `window` the size of the window it moved
+    /// in. Unless the controls `fly` the ship, they only fire its weapons.
+    pub fn read(
+        &self,
+        input: PlayerInput,
+        locked: bool,
+        mouse: Option<Vec2>,
+        window: Vec2,
+        fly: bool,
+        delta: f32,
+    ) -> ShipInput {
+        let tuning = self.settings.tuning(*self.assist);
+        let pitch = if self.settings.invert_y { -1. } else { 1. };
+        let flying_gamepad = input.gamepad.filter(|_| fly);
         let forward = Vec3::Z;
         let right = -Vec3::X;
         let up = Vec3::Y;
-        let mut force = {
+        let mut thrust = {
             let mut force = Vec3::default();
-            for key in keys.get_pressed() {
+            let keys = self
+                .keys
+                .get_pressed()
+                .filter(|_| input.keyboard_mouse && fly);
+            for key in keys {
                 match key {
                     KeyCode::W => force += forward,
                     KeyCode::S => force -= forward,
@@ -108,137 +295,224 @@ fn player_move(
             }
             force.normalize_or_zero()
         };
-
-        force += if let Some((x, y, z_up, z_down)) = gamepad.and_then(|gamepad| {
+        thrust += if let Some((x, y, z_up, z_down)) = flying_gamepad.and_then(|gamepad| {
             Some((
-                axes.get(GamepadAxis(gamepad, GamepadAxisType::LeftStickX))?,
-                axes.get(GamepadAxis(gamepad, GamepadAxisType::LeftStickY))?,
-                button_axes.get(GamepadButton(gamepad, GamepadButtonType::LeftTrigger2))?,
-                button_axes.get(GamepadButton(gamepad, GamepadButtonType::RightTrigger2))?,
+                self.axes
+                    .get(GamepadAxis(gamepad, GamepadAxisType::LeftStickX))?,
+                self.axes
+                    .get(GamepadAxis(gamepad, GamepadAxisType::LeftStickY))?,
+                self.button_axes
+                    .get(GamepadButton(gamepad, GamepadButtonType::LeftTrigger2))?,
+                self.button_axes
+                    .get(GamepadButton(gamepad, GamepadButtonType::RightTrigger2))?,
             ))
         }) {
-            forward * y + right * x + (z_up - z_down) * up
+            let stick = self.sticks.move_stick.apply(Vec2::new(x, y));
+            forward * stick.y + right * stick.x + (z_up - z_down) * up
         } else {
             Vec3::default()
         };
 
-        if force.length() > 2. {
-            force = force.normalize() * 2.;
-        }
-
-        if let Some(rb) = rigid_bodies.get_mut(rigid_body_component.handle()) {
-            let linvel: Vec3 = transform.rotation.inverse() * Vec3::from(rb.linvel().clone_owned());
-            {
-                for (&current, force) in linvel.as_ref().iter().zip(force.as_mut().iter_mut()) {
-                    if current > settings.speed_limit {
-                        *force = force.min(0.);
-                    }
-                    if current < -settings.speed_limit {
-                        *force = force.max(0.);
-                    }
-                }
-                rb.apply_force(
-                    Vector::from(
-                        transform.rotation * force * time.delta_seconds() * settings.accel,
-                    ),
-                    true,
-                );
+        let mut turn = match mouse.filter(|_| input.keyboard_mouse) {
+            Some(mouse) => {
+                Vec2::new(
+                    (self.settings.sensitivity * mouse.y * pitch * window.y).to_radians(),
+                    -(self.settings.sensitivity * mouse.x * window.x).to_radians(),
+                ) * delta
+                    * tuning.accel
             }
-            for (i, force) in force.as_ref().iter().enumerate() {
-                if force.abs() < 0.0005 {
-                    let mut v = Vec3::default();
+            None => Vec2::ZERO,
+        };
+        turn += if let Some((x, y)) = flying_gamepad.and_then(|gamepad| {
+            Some((
+                self.axes
+                    .get(GamepadAxis(gamepad, GamepadAxisType::RightStickX))?,
+                self.axes
+                    .get(GamepadAxis(gamepad, GamepadAxisType::RightStickY))?,
+            ))
+        }) {
+            let stick =
+                self.sticks.look_stick.apply(Vec2::new(x, y)) * self.sticks.look_sensitivity;
+            Vec2::new((stick.y * pitch).to_radians(), -stick.x.to_radians())
+        } else {
+            Vec2::ZERO
+        };
 
-                    v[i] = -linvel[i].clamp(-0.5, 0.5);
-                    rb.apply_force(Vector::from(transform.rotation * v), true);
-                }
-            }
-            {
-                let mut linvel = rb.linvel().clone_owned();
-                for vel in linvel.iter_mut() {
-                    if (*vel).abs() < 0.001 {
-                        *vel = 0.;
-                    }
-                }
-                rb.set_linvel(linvel, true);
-            }
+        let clicks = input.keyboard_mouse && locked;
+        let pad = |button| input.gamepad.map(|gamepad| GamepadButton(gamepad, button));
+        let held = |button| pad(button).map_or(false, |button| self.buttons.pressed(button));
+        let trigger = |mouse_button, button| Trigger {
+            pressed: clicks && self.mouse_buttons.pressed(mouse_button) || held(button),
+            just_pressed: clicks && self.mouse_buttons.just_pressed(mouse_button)
+                || pad(button).map_or(false, |button| self.buttons.just_pressed(button)),
+        };
+        ShipInput {
+            thrust: thrust.clamp_length_max(2.),
+            turn: turn.clamp_length_max(1.),
+            fire: trigger(MouseButton::Left, GamepadButtonType::RightTrigger),
+            boost: input.keyboard_mouse && self.keys.pressed(KeyCode::LControl)
+                || held(GamepadButtonType::LeftThumb),
+            laser: clicks
+                && (self.keys.pressed(KeyCode::F)
+                    || self.mouse_buttons.pressed(MouseButton::Middle))
+                || held(GamepadButtonType::West),
+            missile: trigger(MouseButton::Right, GamepadButtonType::LeftTrigger),
         }
     }
 }
 
-fn player_look(
-    gamepad: Res<Option<Gamepad>>,
-    axes: Res<Axis<GamepadAxis>>,
-    settings: Res<MovementSettings>,
+/// Every player's ship starts with its controls let go
+fn equip(mut commands: Commands, ships: Query<Entity, (With<Player>, Without<ShipInput>)>) {
+    for ship in ships.iter() {
+        commands.entity(ship).insert(ShipInput::default());
+    }
+}
+
+/// Reads the devices of every player at this computer
+fn read_input(
+    devices: Res<PlayerDevices>,
+    controls: Controls,
+    camera_mode: Res<CameraMode>,
     time: Res<Time>,
-    mut windows: ResMut<Windows>,
+    windows: Res<Windows>,
     #[cfg(target_arch = "wasm32")] winit_windows: Res<bevy::winit::WinitWindows>,
-    mut state: EventReader<MouseMotion>,
+    mut motion: EventReader<MouseMotion>,
+    mut ships: Query<(&Player, &mut ShipInput, Option<&Controllable>)>,
+) {
+    let window = windows.get_primary().unwrap();
+    let locked = cursor_locked(
+        window,
+        #[cfg(target_arch = "wasm32")]
+        &winit_windows,
+    );
+    let mouse = motion.iter().fold(Vec2::ZERO, |delta, ev| delta + ev.delta);
+    let size = Vec2::new(window.width(), window.height());
+    for (player, mut input, controllable) in ships.iter_mut() {
+        let lead = controllable.is_some();
+        // The lead's controls can fly the spectator camera instead
+        let mouse_steers = locked && (!lead || camera_mode.mouse_steers_ship());
+        *input = controls.read(
+            devices.input(player.0),
+            locked,
+            Some(mouse).filter(|_| mouse_steers),
+            size,
+            !lead || camera_mode.controls_ship(),
+            time.delta_seconds(),
+        );
+    }
+}
+
+/// Flies every player's ship the way its [`ShipInput`] asks
+fn fly_ships(
+    time: Res<Time>,
+    settings: Res<MovementSettings>,
+    assist: Res<FlightAssist>,
+    boost_settings: Res<BoostSettings>,
     mut rigid_bodies: ResMut<RigidBodySet>,
-    query: Query<(&Transform, &RigidBodyHandleComponent), With<Controllable>>,
+    mut query: Query<(
+        &ShipInput,
+        &Transform,
+        &RigidBodyHandleComponent,
+        Option<&Boost>,
+        Option<&mut Thrust>,
+    )>,
 ) {
-    let window = windows.get_primary_mut().unwrap();
-    for (transform, rigid_body_component) in query.iter() {
-        let mut torque: Vector<f32> = state
-            .iter()
-            .map(|ev| {
-                let mut torque = Vector::<f32>::zeros();
-                if cursor_locked(
-                    window,
-                    #[cfg(target_arch = "wasm32")]
-                    &winit_windows,
-                ) {
-                    torque += Vector::from(
-                        transform.local_x()
-                            * (settings.sensitivity * ev.delta.y * window.height()).to_radians(),
-                    );
-                    torque -= Vector::from(
-                        transform.local_y()
-                            * (settings.sensitivity * ev.delta.x * window.width()).to_radians(),
-                    );
-                }
-                torque * time.delta_seconds() * settings.accel
-            })
-            .sum();
-        torque += if let Some((x, y)) = gamepad.and_then(|gamepad| {
-            Some((
-                axes.get(GamepadAxis(gamepad, GamepadAxisType::RightStickX))?,
-                axes.get(GamepadAxis(gamepad, GamepadAxisType::RightStickY))?,
-            ))
-        }) {
-            Vector::from(
-                (transform.local_x() * (settings.sensitivity * y * window.height()).to_radians())
-                    - (transform.local_y()
-                        * (settings.sensitivity * x * window.width()).to_radians()),
-            )
+    let tuning = settings.tuning(*assist);
+    for (input, transform, rigid_body_component, boost, thrust) in query.iter_mut() {
+        if let Some(mut thrust) = thrust {
+            thrust.force = transform.rotation * input.thrust;
+        }
+        let tuning = if boost.map_or(false, |boost| boost.active) {
+            tuning.boosted(&boost_settings)
         } else {
-            Vector::zeros()
+            *tuning
         };
-        if torque.magnitude() > 1. {
-            torque = torque.normalize();
-        }
         if let Some(rb) = rigid_bodies.get_mut(rigid_body_component.handle()) {
-            {
-                let x = rb.angvel();
-                let mut torque = torque.clone_owned();
-                for i in 0..3 {
-                    if x[i] > 0.5 {
-                        torque[i] = torque[i].min(0.);
-                    }
-                    if x[i] < -0.5 {
-                        torque[i] = torque[i].max(0.);
-                    }
-                }
-                rb.apply_torque(torque, true);
+            fly(rb, input, &tuning, *assist, time.delta_seconds());
+        }
+    }
+}
+
+/// Push and turn a ship the way `input` asks, within the limits of `tuning`. Online clients run
+/// this ahead of the server, to predict their own ship.
+pub fn fly(
+    rb: &mut RigidBody,
+    input: &ShipInput,
+    tuning: &FlightTuning,
+    assist: FlightAssist,
+    delta: f32,
+) {
+    let rotation: Quat = rb.position().rotation.into();
+    push(rb, rotation, input.thrust, tuning, assist, delta);
+    steer(rb, rotation, input.turn, tuning);
+}
+
+fn push(
+    rb: &mut RigidBody,
+    rotation: Quat,
+    mut force: Vec3,
+    tuning: &FlightTuning,
+    assist: FlightAssist,
+    delta: f32,
+) {
+    if assist == FlightAssist::Arcade {
+        let linvel = rotation * force.clamp_length_max(1.) * tuning.speed_limit;
+        rb.set_linvel(Vector::from(linvel), true);
+        return;
+    }
+    let linvel: Vec3 = rotation.inverse() * Vec3::from(rb.linvel().clone_owned());
+    {
+        for (&current, force) in linvel.as_ref().iter().zip(force.as_mut().iter_mut()) {
+            if current > tuning.speed_limit {
+                *force = force.min(0.);
             }
+            if current < -tuning.speed_limit {
+                *force = force.max(0.);
+            }
+        }
+        rb.apply_force(Vector::from(rotation * force * delta * tuning.accel), true);
+    }
+    for (i, force) in force.as_ref().iter().enumerate() {
+        if force.abs() < 0.0005 && tuning.linear_damping > 0. {
+            let mut v = Vec3::default();
 
-            for i in 0..3 {
-                if torque[i].abs() < 0.0005 {
-                    let mut v = Vector::default();
-                    v[i] = -rb.angvel()[i].clamp(-0.1, 0.1);
-                    rb.apply_torque(v, true);
-                }
+            v[i] = -linvel[i].clamp(-tuning.linear_damping, tuning.linear_damping);
+            rb.apply_force(Vector::from(rotation * v), true);
+        }
+    }
+    {
+        let mut linvel = rb.linvel().clone_owned();
+        for vel in linvel.iter_mut() {
+            if (*vel).abs() < 0.001 {
+                *vel = 0.;
             }
         }
+        rb.set_linvel(linvel, true);
+    }
+}
+
+fn steer(rb: &mut RigidBody, rotation: Quat, turn: Vec2, tuning: &FlightTuning) {
+    let torque = Vector::from(rotation * Vec3::new(turn.x, turn.y, 0.));
+    {
+        let x = rb.angvel();
+        let mut torque = torque;
+        for i in 0..3 {
+            if x[i] > tuning.turn_limit {
+                torque[i] = torque[i].min(0.);
+            }
+            if x[i] < -tuning.turn_limit {
+                torque[i] = torque[i].max(0.);
+            }
+        }
+        rb.apply_torque(torque, true);
+    }
+
+    for i in 0..3 {
+        if torque[i].abs() < 0.0005 && tuning.angular_damping > 0. {
+            let mut v = Vector::default();
+            v[i] = -rb.angvel()[i].clamp(-tuning.angular_damping, tuning.angular_damping);
+            rb.apply_torque(v, true);
+        }
     }
 }
 
@@ -278,15 +552,19 @@ fn cursor_unlock(
     }
 }
 
+/// Any player can pause with Start
 fn cursor_unlock_gamepad(
-    gamepad: Res<Option<Gamepad>>,
+    devices: Res<PlayerDevices>,
     button_inputs: Res<Input<GamepadButton>>,
     mut windows: ResMut<Windows>,
     #[cfg(target_arch = "wasm32")] winit_windows: Res<bevy::winit::WinitWindows>,
 ) {
     let gamepad_button_pressed =
         |gamepad| button_inputs.just_pressed(GamepadButton(gamepad, GamepadButtonType::Start));
-    if gamepad.map_or(false, gamepad_button_pressed) {
+    if (0..devices.count())
+        .filter_map(|player| devices.gamepad(player))
+        .any(gamepad_button_pressed)
+    {
         let window = windows.get_primary_mut().unwrap();
         set_grab_cursor(
             window,
@@ -297,51 +575,50 @@ fn cursor_unlock_gamepad(
     }
 }
 
-fn shoot(
-    mut commands: Commands,
-    windows: Res<Windows>,
+/// Pause when someone's gamepad drops out, so they can reconnect it or take control with another
+fn pause_on_gamepad_lost(
+    mut lost: EventReader<GamepadLost>,
+    mut windows: ResMut<Windows>,
     #[cfg(target_arch = "wasm32")] winit_windows: Res<bevy::winit::WinitWindows>,
-    mouse_button_input: Res<Input<MouseButton>>,
-    gamepad: Res<Option<Gamepad>>,
-    button_inputs: Res<Input<GamepadButton>>,
-    resources: Res<BulletAssets>,
-    query: Query<&Transform, With<Controllable>>,
 ) {
-    let window = windows.get_primary().unwrap();
-    let gamepad_button_pressed = |gamepad| {
-        button_inputs.just_pressed(GamepadButton(gamepad, GamepadButtonType::RightTrigger))
-    };
-    if cursor_locked(
-        window,
-        #[cfg(target_arch = "wasm32")]
-        &winit_windows,
-    ) && mouse_button_input.just_pressed(MouseButton::Left)
-        || gamepad.map_or(false, gamepad_button_pressed)
-    {
-        if let Ok(ship) = query.single() {
-            let translation = ship.translation + ship.rotation * Vec3::new(0., 0., 5.);
-            let mut transform = Transform::from_translation(translation);
-            transform.rotation = ship.rotation * Quat::from_rotation_x(std::f32::consts::FRAC_PI_2);
-            let local_z = ship.local_z() * 45.;
-            commands
-                .spawn_bundle(PbrBundle {
-                    mesh: resources.mesh.clone(),
-                    material: resources.material.clone(),
-                    transform,
-                    ..Default::default()
-                })
-                .insert(super::Bullet)
-                .insert(TiedToGame)
-                .insert(
-                    RigidBodyBuilder::new_dynamic()
-                        .position(crate::util::nalgebra_pos(
-                            transform.translation,
-                            transform.rotation,
-                        ))
-                        .linvel(local_z.x, local_z.y, local_z.z),
-                )
-                .insert(ColliderBuilder::capsule_y(0.5, 0.1))
-                .insert(DespawnTimer(Timer::from_seconds(5., false)));
+    let mut any_lost = false;
+    for lost in lost.iter() {
+        info!("Player {}'s gamepad was disconnected", lost.player + 1);
+        any_lost = true;
+    }
+    if any_lost {
+        let window = windows.get_primary_mut().unwrap();
+        set_grab_cursor(
+            window,
+            false,
+            #[cfg(target_arch = "wasm32")]
+            &winit_windows,
+        );
+    }
+}
+
+/// Each player fires from their own ship, with a cooldown of their own
+fn shoot(
+    mut bullets: Bullets,
+    time: Res<Time>,
+    power_up_settings: Res<PowerUpSettings>,
+    active_power_ups: Query<&ActivePowerUp>,
+    query: Query<(Entity, &Player, &Transform, &ShipInput)>,
+    mut cues: EventWriter<AudioCue>,
+    mut cooldowns: Local<[f32; MAX_PLAYERS]>,
+) {
+    for cooldown in cooldowns.iter_mut() {
+        *cooldown = (*cooldown - time.delta_seconds()).max(0.);
+    }
+    for (ship_e, player, ship, input) in query.iter() {
+        let cooldown = &mut cooldowns[player.0];
+        // With rapid fire, holding the trigger keeps shooting
+        let rapid_fire = *cooldown <= 0.
+            && powerups::is_active(&active_power_ups, PowerUpKind::RapidFire, ship_e);
+        if input.fire.just_pressed || rapid_fire && input.fire.pressed {
+            *cooldown = power_up_settings.fire_interval;
+            bullets.fire(ship, Shooter::Player(player.0));
+            cues.send(AudioCue::Shoot);
         }
     }
 }
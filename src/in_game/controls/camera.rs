@@ -1,79 +1,446 @@
+use bevy::input::mouse::MouseMotion;
+use bevy::math::const_vec3;
 use bevy::prelude::*;
-use bevy::render::camera::Camera;
+use bevy::render::camera::{Camera, PerspectiveProjection};
 use bevy::render::render_graph::base::camera::CAMERA_3D;
-use bevy_rapier3d::rapier::geometry::{ColliderSet, Ray};
+use bevy_rapier3d::rapier::geometry::{Ball, ColliderSet};
+use bevy_rapier3d::rapier::math::Vector;
 use bevy_rapier3d::rapier::pipeline::QueryPipeline;
+use std::ops::{Add, Mul, Sub};
 
 use crate::in_game::asteroids::Asteroid;
 use crate::in_game::TiedToGame;
+use crate::players::PlayerDevices;
+use crate::settings::Settings;
 
-use super::Controllable;
+use super::shake::Shake;
+use super::{lead_gamepad, Controllable, MovementSettings, Player};
 
 pub struct CameraPlugin<T>(pub T);
 
 impl<T: crate::util::StateType> Plugin for CameraPlugin<T> {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_system_set(
-            SystemSet::on_update(self.0.clone())
-                .with_system(calc_camera_pos.system().chain(update_camera.system())),
-        )
-        .add_system_set(SystemSet::on_enter(self.0.clone()).with_system(startup.system()));
+        app.init_resource::<CameraSettings>()
+            .init_resource::<CameraMode>()
+            .init_resource::<CameraRig>()
+            .init_resource::<OrbitAngles>()
+            .init_resource::<Spectator>()
+            .add_system(apply_fov.system())
+            .add_system_set(
+                SystemSet::on_update(self.0.clone())
+                    .with_system(cycle_mode.system().label(CycleMode))
+                    .with_system(orbit_input.system().after(CycleMode))
+                    .with_system(spectator_input.system().after(CycleMode))
+                    .with_system(
+                        calc_camera_pos
+                            .system()
+                            .chain(update_camera.system())
+                            .label(UpdateCamera)
+                            .after(CycleMode),
+                    ),
+            )
+            .add_system_set(SystemSet::on_enter(self.0.clone()).with_system(startup.system()));
+    }
+}
+
+/// How quickly the camera follows the ship. Times are roughly how long the camera takes to
+/// catch up, so smaller is stiffer.
+pub struct CameraSettings {
+    pub position_smooth_time: f32,
+    /// Smoothing of the point the camera looks at
+    pub look_smooth_time: f32,
+    /// How quickly the arm shortens when an asteroid gets in the way
+    pub pull_in_time: f32,
+    /// How quickly the arm extends again once the obstruction clears
+    pub ease_out_time: f32,
+    /// Radius of the sphere cast along the arm, to keep the camera clear of asteroid surfaces
+    pub probe_radius: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            position_smooth_time: 0.15,
+            look_smooth_time: 0.1,
+            pull_in_time: 0.05,
+            ease_out_time: 0.5,
+            probe_radius: 1.5,
+        }
     }
 }
 
-fn startup(mut commands: Commands) {
+/// Where the camera is, relative to the ship
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CameraMode {
+    /// Behind and above the ship
+    Chase,
+    /// First person, from the ship's nose
+    Cockpit,
+    /// Circles around the ship with the mouse
+    Orbit,
+    /// Detached from the ship and flown around freely, for debugging
+    Spectator,
+}
+
+impl Default for CameraMode {
+    fn default() -> Self {
+        CameraMode::Chase
+    }
+}
+
+impl CameraMode {
+    fn next(self) -> Self {
+        match self {
+            CameraMode::Chase => CameraMode::Cockpit,
+            CameraMode::Cockpit => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::Spectator,
+            CameraMode::Spectator => CameraMode::Chase,
+        }
+    }
+
+    /// Whether the mouse turns the ship, rather than the camera
+    pub fn mouse_steers_ship(self) -> bool {
+        matches!(self, CameraMode::Chase | CameraMode::Cockpit)
+    }
+
+    /// Whether the ship can be flown at all
+    pub fn controls_ship(self) -> bool {
+        self != CameraMode::Spectator
+    }
+}
+
+#[derive(SystemLabel, Clone, Eq, PartialEq, Hash, Debug, Default)]
+struct CycleMode;
+
+/// Moves the camera to follow the ship
+#[derive(SystemLabel, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub(super) struct UpdateCamera;
+
+#[derive(Default)]
+struct OrbitAngles {
+    yaw: f32,
+    pitch: f32,
+}
+
+#[derive(Default)]
+struct Spectator {
+    transform: Transform,
+}
+
+/// Where the camera wants to be this frame
+struct CameraTarget {
+    /// Point the camera is kept in line of sight of
+    pivot: Vec3,
+    /// Offset from `pivot` to the camera, at full length
+    arm: Vec3,
+    /// How much of `arm` is clear of asteroids
+    clear: f32,
+    look_at: Vec3,
+    up: Vec3,
+    /// Whether to ease towards the target rather than snap to it
+    smooth: bool,
+}
+
+/// Current state of the springs easing the camera
+#[derive(Default)]
+struct CameraRig {
+    arm_length: f32,
+    arm_velocity: f32,
+    position: Vec3,
+    velocity: Vec3,
+    look_at: Vec3,
+    look_velocity: Vec3,
+    up: Vec3,
+    up_velocity: Vec3,
+}
+
+pub(super) const CHASE_OFFSET: Vec3 = const_vec3!([0., 9., -30.]);
+const COCKPIT_OFFSET: Vec3 = const_vec3!([0., 1., 3.]);
+const ORBIT_DISTANCE: f32 = 30.;
+const SPECTATOR_SPEED: f32 = 40.;
+
+fn startup(mut commands: Commands, mut mode: ResMut<CameraMode>, mut rig: ResMut<CameraRig>) {
+    *mode = Default::default();
+    *rig = Default::default();
     commands
         .spawn_bundle(PerspectiveCameraBundle::default())
+        .insert(Shake::default())
         .insert(TiedToGame);
 }
 
-/// Find the ideal camera position. If an asteroid is in the way, the camera will be placed
-/// closer.
+/// Keep the field of view in line with the settings, including for newly spawned cameras
+fn apply_fov(settings: Res<Settings>, mut cameras: Query<&mut PerspectiveProjection>) {
+    let fov = settings.fov.to_radians();
+    for mut projection in cameras.iter_mut() {
+        if (projection.fov - fov).abs() > f32::EPSILON {
+            projection.fov = fov;
+        }
+    }
+}
+
+fn cycle_mode(
+    keys: Res<Input<KeyCode>>,
+    devices: Res<PlayerDevices>,
+    button_inputs: Res<Input<GamepadButton>>,
+    mut mode: ResMut<CameraMode>,
+    mut spectator: ResMut<Spectator>,
+    cameras: Query<(&Transform, &Camera)>,
+    lead: Query<&Player, With<Controllable>>,
+) {
+    let gamepad_button_pressed =
+        |gamepad| button_inputs.just_pressed(GamepadButton(gamepad, GamepadButtonType::Select));
+    if keys.just_pressed(KeyCode::C)
+        || lead_gamepad(&devices, &lead).map_or(false, gamepad_button_pressed)
+    {
+        *mode = mode.next();
+        if *mode == CameraMode::Spectator {
+            // Start flying from wherever the camera was
+            if let Some((transform, _)) = cameras
+                .iter()
+                .find(|(_, camera)| camera.name.as_deref() == Some(CAMERA_3D))
+            {
+                spectator.transform = *transform;
+            }
+        }
+    }
+}
+
+fn orbit_input(
+    mode: Res<CameraMode>,
+    settings: Res<MovementSettings>,
+    mut mouse: EventReader<MouseMotion>,
+    mut angles: ResMut<OrbitAngles>,
+) {
+    let delta = mouse.iter().fold(Vec2::ZERO, |delta, ev| delta + ev.delta);
+    if *mode == CameraMode::Orbit {
+        angles.yaw -= delta.x * settings.sensitivity;
+        angles.pitch = (angles.pitch + delta.y * settings.sensitivity).clamp(-1.5, 1.5);
+    }
+}
+
+fn spectator_input(
+    mode: Res<CameraMode>,
+    settings: Res<MovementSettings>,
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mut mouse: EventReader<MouseMotion>,
+    mut spectator: ResMut<Spectator>,
+) {
+    let delta = mouse.iter().fold(Vec2::ZERO, |delta, ev| delta + ev.delta);
+    if *mode != CameraMode::Spectator {
+        return;
+    }
+    let transform = &mut spectator.transform;
+    transform.rotate(Quat::from_rotation_y(-delta.x * settings.sensitivity));
+    transform.rotate(Quat::from_rotation_x(-delta.y * settings.sensitivity));
+
+    let mut direction = Vec3::default();
+    for key in keys.get_pressed() {
+        match key {
+            KeyCode::W => direction -= Vec3::Z,
+            KeyCode::S => direction += Vec3::Z,
+            KeyCode::A => direction -= Vec3::X,
+            KeyCode::D => direction += Vec3::X,
+            KeyCode::Space => direction += Vec3::Y,
+            KeyCode::LShift => direction -= Vec3::Y,
+            _ => (),
+        }
+    }
+    transform.translation +=
+        transform.rotation * direction.normalize_or_zero() * SPECTATOR_SPEED * time.delta_seconds();
+}
+
+/// Critically damped spring towards `target`, which never overshoots. `velocity` carries the
+/// spring's state between frames.
+pub(super) fn smooth_damp<T>(
+    current: T,
+    target: T,
+    velocity: &mut T,
+    smooth_time: f32,
+    delta: f32,
+) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    let omega = 2. / smooth_time.max(0.0001);
+    let x = omega * delta;
+    let exp = 1. / (1. + x + 0.48 * x * x + 0.235 * x * x * x);
+    let change = current - target;
+    let temp = (*velocity + change * omega) * delta;
+    *velocity = (*velocity - temp * omega) * exp;
+    target + (change + temp) * exp
+}
+
+/// How far along `arm` (relative to `pivot`) a sphere can travel before it hits an asteroid
+fn clear_length(
+    query_pipeline: &QueryPipeline,
+    colliders: &ColliderSet,
+    asteroids: &Query<(), With<Asteroid>>,
+    radius: f32,
+    pivot: Vec3,
+    arm: Vec3,
+) -> f32 {
+    let length = arm.length();
+    let hit = query_pipeline.cast_shape(
+        colliders,
+        &crate::util::nalgebra_pos(pivot, Quat::IDENTITY),
+        &Vector::from(arm.normalize()),
+        &Ball::new(radius),
+        length,
+        Default::default(),
+        Some(&|_, c| asteroids.get(Entity::from_bits(c.user_data as u64)).is_ok()),
+    );
+    hit.map_or(length, |(_, toi)| toi.toi)
+}
+
+/// Find the ideal camera placement for the current mode, and how much of it is clear of
+/// asteroids.
 fn calc_camera_pos(
+    settings: Res<CameraSettings>,
+    mode: Res<CameraMode>,
+    angles: Res<OrbitAngles>,
+    spectator: Res<Spectator>,
     query_pipeline: Res<QueryPipeline>,
     colliders: Res<ColliderSet>,
     ship: Query<&Transform, With<Controllable>>,
     asteroids: Query<(), With<Asteroid>>,
-) -> Vec3 {
-    if let Ok(ship) = ship.single() {
-        let offset = Vec3::new(0., 9., -30.);
-        let vec = ship.rotation * offset.normalize();
-        let hit = query_pipeline.cast_ray_and_get_normal(
+) -> Option<CameraTarget> {
+    let arm_target = |ship: &Transform, arm: Vec3, look_at: Vec3, up: Vec3| CameraTarget {
+        pivot: ship.translation,
+        arm,
+        clear: clear_length(
+            &query_pipeline,
             &colliders,
-            &Ray::new(ship.translation.into(), vec.into()),
-            offset.length(),
-            true,
-            Default::default(),
-            Some(&|_, c| asteroids.get(Entity::from_bits(c.user_data as u64)).is_ok()),
-        );
-
-        if let Some(hit) = hit {
-            ship.translation + vec * hit.1.toi
-        } else {
-            ship.translation + ship.rotation * offset
+            &asteroids,
+            settings.probe_radius,
+            ship.translation,
+            arm,
+        ),
+        look_at,
+        up,
+        smooth: true,
+    };
+    match (*mode, ship.single()) {
+        (CameraMode::Spectator, _) => {
+            let transform = &spectator.transform;
+            Some(CameraTarget {
+                pivot: transform.translation,
+                arm: Vec3::ZERO,
+                clear: 0.,
+                look_at: transform.translation - transform.local_z(),
+                up: transform.local_y(),
+                smooth: false,
+            })
+        }
+        (_, Err(_)) => None,
+        (CameraMode::Chase, Ok(ship)) => Some(arm_target(
+            ship,
+            ship.rotation * CHASE_OFFSET,
+            ship.translation + ship.rotation * Vec3::new(0., 0., 20.),
+            ship.local_z(),
+        )),
+        (CameraMode::Cockpit, Ok(ship)) => {
+            let pivot = ship.translation + ship.rotation * COCKPIT_OFFSET;
+            Some(CameraTarget {
+                pivot,
+                arm: Vec3::ZERO,
+                clear: 0.,
+                look_at: pivot + ship.local_z(),
+                up: ship.local_y(),
+                smooth: false,
+            })
+        }
+        (CameraMode::Orbit, Ok(ship)) => {
+            let orbit = Quat::from_rotation_y(angles.yaw) * Quat::from_rotation_x(angles.pitch);
+            Some(arm_target(
+                ship,
+                ship.rotation * orbit * Vec3::new(0., 0., -ORBIT_DISTANCE),
+                ship.translation,
+                ship.local_y(),
+            ))
         }
-    } else {
-        Default::default()
     }
 }
 
+/// Ease the camera towards its target. The camera jumps in front of anything that gets in the
+/// way, and the arm extends slowly once it's clear, so it neither clips nor pops back out.
 fn update_camera(
-    In(camera_pos): In<Vec3>,
-    mut query: QuerySet<(
-        Query<&Transform, With<Controllable>>,
-        Query<(&mut Transform, &Camera)>,
-    )>,
+    In(target): In<Option<CameraTarget>>,
+    time: Res<Time>,
+    settings: Res<CameraSettings>,
+    mode: Res<CameraMode>,
+    mut rig: ResMut<CameraRig>,
+    mut query: Query<(&mut Transform, &Camera)>,
 ) {
-    if let Ok(&controllable) = query.q0().single() {
-        for (mut transform, camera) in query.q1_mut().iter_mut() {
-            if camera.name.as_deref() != Some(CAMERA_3D) {
-                continue;
-            }
-            transform.translation = camera_pos;
-            transform.look_at(
-                controllable.translation + controllable.rotation * Vec3::new(0., 0., 20.),
-                controllable.local_z(),
+    let target = match target {
+        Some(target) => target,
+        None => return,
+    };
+    let delta = time.delta_seconds();
+    let rig = &mut *rig;
+    if !target.smooth || mode.is_changed() {
+        *rig = CameraRig {
+            arm_length: target.clear,
+            position: target.pivot + target.arm.normalize_or_zero() * target.clear,
+            look_at: target.look_at,
+            up: target.up,
+            ..Default::default()
+        };
+    } else {
+        let blocked = target.clear < rig.arm_length;
+        let arm_smooth_time = if blocked {
+            settings.pull_in_time
+        } else {
+            settings.ease_out_time
+        };
+        rig.arm_length = smooth_damp(
+            rig.arm_length,
+            target.clear,
+            &mut rig.arm_velocity,
+            arm_smooth_time,
+            delta,
+        )
+        .min(target.arm.length());
+        let position = target.pivot + target.arm.normalize_or_zero() * rig.arm_length;
+        if blocked {
+            // Easing towards the pulled in arm would leave the camera inside whatever's in the
+            // way, so it jumps there instead
+            rig.position = position;
+            rig.velocity = Vec3::ZERO;
+        } else {
+            rig.position = smooth_damp(
+                rig.position,
+                position,
+                &mut rig.velocity,
+                settings.position_smooth_time,
+                delta,
             );
+            // Lagging behind mustn't take it any further out than the arm reaches
+            let offset = rig.position - target.pivot;
+            if offset.length() > rig.arm_length {
+                rig.position = target.pivot + offset.normalize_or_zero() * rig.arm_length;
+            }
+        }
+        rig.look_at = smooth_damp(
+            rig.look_at,
+            target.look_at,
+            &mut rig.look_velocity,
+            settings.look_smooth_time,
+            delta,
+        );
+        rig.up = smooth_damp(
+            rig.up,
+            target.up,
+            &mut rig.up_velocity,
+            settings.look_smooth_time,
+            delta,
+        );
+    }
+
+    for (mut transform, camera) in query.iter_mut() {
+        if camera.name.as_deref() != Some(CAMERA_3D) {
+            continue;
         }
+        *transform = Transform::from_translation(rig.position).looking_at(rig.look_at, rig.up);
     }
 }
@@ -0,0 +1,251 @@
+use bevy::prelude::*;
+use bevy_rapier3d::rapier::geometry::{Ball, ColliderSet};
+use bevy_rapier3d::rapier::pipeline::QueryPipeline;
+use std::collections::HashSet;
+
+use crate::in_game::asteroids::Asteroid;
+use crate::in_game::enemies::{Enemy, EnemyBullet};
+use crate::in_game::events::{AsteroidDestroyed, Contact, EventAdapter, HitAsteroid};
+use crate::in_game::game_area::GameAreaBound;
+use crate::settings::Settings;
+
+use super::camera::UpdateCamera;
+use super::Controllable;
+
+pub struct ShakePlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for ShakePlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ShakeSettings>()
+            .add_system(apply_settings.system())
+            .add_system_set(
+                SystemSet::on_update(self.0.clone())
+                    .with_system(impacts.system().after(EventAdapter))
+                    .with_system(near_misses.system())
+                    .with_system(apply.system().after(UpdateCamera)),
+            );
+    }
+}
+
+/// How hard the camera shakes. Shake can be turned off entirely, for players who find it
+/// uncomfortable.
+pub struct ShakeSettings {
+    pub enabled: bool,
+    /// Scales all shaking
+    pub intensity: f32,
+    /// Offset at full trauma
+    pub max_offset: f32,
+    /// Rotation at full trauma, in radians
+    pub max_angle: f32,
+    /// How quickly the shake wobbles
+    pub frequency: f32,
+    /// Trauma lost per second
+    pub decay: f32,
+    pub bullet_hit: f32,
+    pub asteroid_destroyed: f32,
+    /// Asteroids destroyed further away than this don't shake the camera
+    pub destroyed_range: f32,
+    pub collision: f32,
+    pub near_miss: f32,
+    pub near_miss_distance: f32,
+}
+
+impl Default for ShakeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            intensity: 1.,
+            max_offset: 0.6,
+            max_angle: 0.05,
+            frequency: 15.,
+            decay: 1.2,
+            bullet_hit: 0.1,
+            asteroid_destroyed: 0.35,
+            destroyed_range: 60.,
+            collision: 0.7,
+            near_miss: 0.3,
+            near_miss_distance: 8.,
+        }
+    }
+}
+
+fn apply_settings(settings: Res<Settings>, mut shake: ResMut<ShakeSettings>) {
+    if settings.is_changed() {
+        shake.enabled = settings.screen_shake;
+    }
+}
+
+/// Camera shake. Trauma goes from 0 to 1, and the shake grows with its square so small bumps
+/// stay subtle.
+#[derive(Default)]
+pub struct Shake {
+    pub trauma: f32,
+    time: f32,
+    /// Where the camera was before it was shaken
+    base: Transform,
+    /// Where shaking left it last frame, to tell whether anything moved it since
+    shaken: Option<Transform>,
+}
+
+impl Shake {
+    pub fn add_trauma(&mut self, trauma: f32) {
+        self.trauma = (self.trauma + trauma).min(1.);
+    }
+}
+
+fn hash(seed: u32, x: i32) -> f32 {
+    let mut n = (x as u32).wrapping_mul(0x27d4_eb2d) ^ seed.wrapping_mul(0x1656_67b1);
+    n = (n ^ (n >> 15)).wrapping_mul(0x85eb_ca6b);
+    n ^= n >> 13;
+    n as f32 / u32::MAX as f32 * 2. - 1.
+}
+
+/// Smooth 1D value noise between -1 and 1
+fn noise(seed: u32, t: f32) -> f32 {
+    let i = t.floor();
+    let f = t - i;
+    let f = f * f * (3. - 2. * f);
+    let a = hash(seed, i as i32);
+    let b = hash(seed, i as i32 + 1);
+    a + (b - a) * f
+}
+
+fn impacts(
+    settings: Res<ShakeSettings>,
+    mut contacts: EventReader<Contact>,
+    mut hits: EventReader<HitAsteroid>,
+    mut destroyed: EventReader<AsteroidDestroyed>,
+    ship: Query<(Entity, &Transform), With<Controllable>>,
+    obstacles: Query<
+        (),
+        Or<(
+            With<Asteroid>,
+            With<Enemy>,
+            With<EnemyBullet>,
+            With<GameAreaBound>,
+        )>,
+    >,
+    mut shakes: Query<&mut Shake>,
+) {
+    let mut trauma = 0.;
+    for _ in hits.iter() {
+        trauma += settings.bullet_hit;
+    }
+    let ship = ship.single().ok();
+    for event in destroyed.iter() {
+        if let Some((_, ship)) = ship {
+            let distance = ship.translation.distance(event.position);
+            trauma +=
+                settings.asteroid_destroyed * (1. - distance / settings.destroyed_range).max(0.);
+        }
+    }
+    for event in contacts.iter() {
+        if let (Contact::Started(a, b), Some((ship, _))) = (event, ship) {
+            if [(*a, *b), (*b, *a)]
+                .iter()
+                .any(|&(a, b)| a == ship && obstacles.get(b).is_ok())
+            {
+                trauma += settings.collision;
+            }
+        }
+    }
+    if trauma > 0. {
+        for mut shake in shakes.iter_mut() {
+            shake.add_trauma(trauma * settings.intensity);
+        }
+    }
+}
+
+/// Shake a little when an asteroid comes close to the ship
+fn near_misses(
+    settings: Res<ShakeSettings>,
+    query_pipeline: Res<QueryPipeline>,
+    colliders: Res<ColliderSet>,
+    ship: Query<&Transform, With<Controllable>>,
+    asteroids: Query<(), With<Asteroid>>,
+    mut shakes: Query<&mut Shake>,
+    mut near: Local<HashSet<Entity>>,
+) {
+    let ship = match ship.single() {
+        Ok(ship) => ship,
+        Err(_) => return,
+    };
+    let mut now_near = HashSet::new();
+    query_pipeline.intersections_with_shape(
+        &colliders,
+        &crate::util::nalgebra_pos(ship.translation, Quat::IDENTITY),
+        &Ball::new(settings.near_miss_distance),
+        Default::default(),
+        Some(&|_, c| asteroids.get(Entity::from_bits(c.user_data as u64)).is_ok()),
+        |_, c| {
+            now_near.insert(Entity::from_bits(c.user_data as u64));
+            true
+        },
+    );
+    let new = now_near.difference(&near).count();
+    if new > 0 {
+        for mut shake in shakes.iter_mut() {
+            shake.add_trauma(settings.near_miss * new as f32 * settings.intensity);
+        }
+    }
+    *near = now_near;
+}
+
+/// Offset and rotate the camera on top of wherever `update_camera` put it this frame. When
+/// nothing moved it, e.g. with no ship to follow, it shakes around the same spot as last frame
+/// rather than drifting off.
+fn apply(
+    time: Res<Time>,
+    settings: Res<ShakeSettings>,
+    mut query: Query<(&mut Transform, &mut Shake)>,
+) {
+    for (mut transform, mut shake) in query.iter_mut() {
+        if shake.shaken != Some(*transform) {
+            shake.base = *transform;
+        }
+        shake.time += time.delta_seconds();
+        let amount = shake.trauma * shake.trauma;
+        shake.trauma = (shake.trauma - settings.decay * time.delta_seconds()).max(0.);
+        *transform = shake.base;
+        if settings.enabled && amount > 0. {
+            let t = shake.time * settings.frequency;
+            let offset = Vec3::new(noise(0, t), noise(1, t), noise(2, t)) * settings.max_offset;
+            let rotation = Quat::from_rotation_y(noise(3, t) * settings.max_angle * amount)
+                * Quat::from_rotation_x(noise(4, t) * settings.max_angle * amount)
+                * Quat::from_rotation_z(noise(5, t) * settings.max_angle * amount);
+            let local_offset = transform.rotation * offset * amount;
+            transform.translation += local_offset;
+            transform.rotation *= rotation;
+        }
+        shake.shaken = Some(*transform);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shaking_a_still_camera_does_not_drift() {
+        let mut app = App::build();
+        app.init_resource::<Time>()
+            .init_resource::<ShakeSettings>()
+            .add_system(apply.system());
+        let start = Transform::from_xyz(1., 2., 3.);
+        let camera = app
+            .world_mut()
+            .spawn()
+            .insert(start)
+            .insert(Shake {
+                trauma: 1.,
+                ..Default::default()
+            })
+            .id();
+        for _ in 0..10 {
+            app.app.update();
+        }
+        let transform = app.world().get::<Transform>(camera).unwrap();
+        let max_offset = ShakeSettings::default().max_offset * 3f32.sqrt();
+        assert!(transform.translation.distance(start.translation) <= max_offset);
+    }
+}
@@ -0,0 +1,605 @@
+//! Local co-op split screen. While both players' ships are flying, the main camera draws into the
+//! top half of the window and player two's chase camera into the bottom half. Bevy 0.5's main pass
+//! can only draw a camera across the whole window, so [`SplitPassNode`] draws both halves with a
+//! viewport each, and the main camera is taken off the main pass for as long as the window is
+//! split.
+
+use bevy::ecs::query::QueryState;
+use bevy::ecs::system::SystemParam;
+use bevy::ecs::world::Mut;
+use bevy::prelude::*;
+use bevy::render::camera::{
+    ActiveCameras, Camera, CameraProjection, PerspectiveProjection, VisibleEntities,
+};
+use bevy::render::draw::RenderCommand;
+use bevy::render::pass::{
+    ClearColor, LoadOp, Operations, PassDescriptor, RenderPassDepthStencilAttachmentDescriptor,
+    TextureAttachment,
+};
+use bevy::render::pipeline::{IndexFormat, PipelineDescriptor};
+use bevy::render::render_graph::base::camera::CAMERA_3D;
+use bevy::render::render_graph::base::{node, MainPass};
+use bevy::render::render_graph::{
+    CameraNode, Node, RenderGraph, ResourceSlotInfo, ResourceSlots, WindowSwapChainNode,
+    WindowTextureNode,
+};
+use bevy::render::renderer::{
+    BindGroupId, BufferId, RenderContext, RenderResourceBindings, RenderResourceContext,
+    RenderResourceType,
+};
+use bevy::ui::node::UI_PASS;
+use bevy::utils::HashMap;
+
+use crate::in_game::TiedToGame;
+
+use super::camera::{smooth_damp, CameraSettings, CHASE_OFFSET};
+use super::Player;
+
+/// Active camera the main camera is handed to while the window is split
+const TOP: &str = "SplitTop";
+/// Player two's camera
+const BOTTOM: &str = "SplitBottom";
+const SPLIT_PASS: &str = "split_pass";
+
+pub struct SplitScreenPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for SplitScreenPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<SplitScreen>()
+            .add_startup_system(setup_graph.system())
+            .add_system_set(
+                SystemSet::on_update(self.0.clone())
+                    .with_system(split.system().label(Split))
+                    .with_system(follow.system().after(Split)),
+            )
+            .add_system_set(SystemSet::on_exit(self.0.clone()).with_system(join.system()))
+            .add_system_to_stage(CoreStage::Last, hand_out_cameras.system());
+    }
+}
+
+#[derive(SystemLabel, Clone, Hash, Debug, Eq, PartialEq)]
+struct Split;
+
+/// Whether the window is split between the two players
+#[derive(Default)]
+pub struct SplitScreen {
+    pub active: bool,
+}
+
+/// Where in the window the main camera is drawn, for placing the HUD over what it sees
+#[derive(SystemParam)]
+pub struct MainView<'a> {
+    windows: Res<'a, Windows>,
+    split: Res<'a, SplitScreen>,
+}
+
+impl<'a> MainView<'a> {
+    /// The bottom left corner and the size of the main camera's part of the window, in logical
+    /// pixels like the UI
+    pub fn rect(&self) -> Option<(Vec2, Vec2)> {
+        let window = self.windows.get_primary()?;
+        let size = Vec2::new(window.width(), window.height());
+        Some(if self.split.active {
+            (Vec2::new(0., size.y / 2.), Vec2::new(size.x, size.y / 2.))
+        } else {
+            (Vec2::ZERO, size)
+        })
+    }
+
+    /// [`Camera::world_to_screen`] for the main camera, but into its part of the window
+    pub fn world_to_screen(
+        &self,
+        camera: &Camera,
+        camera_transform: &GlobalTransform,
+        position: Vec3,
+    ) -> Option<Vec2> {
+        let window = self.windows.get_primary()?;
+        let screen = camera.world_to_screen(&self.windows, camera_transform, position)?;
+        let (corner, size) = self.rect()?;
+        Some(corner + screen / Vec2::new(window.width(), window.height()) * size)
+    }
+}
+
+/// Follows player two's ship, the same way the main camera follows in chase mode
+#[derive(Default)]
+struct SecondCamera {
+    velocity: Vec3,
+    look_at: Vec3,
+    look_velocity: Vec3,
+}
+
+/// Draw the split halves after the main pass, which is left with nothing to draw while split, and
+/// before the UI, which goes over the whole window either way
+fn setup_graph(
+    msaa: Res<Msaa>,
+    mut active_cameras: ResMut<ActiveCameras>,
+    mut render_graph: ResMut<RenderGraph>,
+) {
+    active_cameras.add(TOP);
+    active_cameras.add(BOTTOM);
+    render_graph.add_system_node("split_top_camera", CameraNode::new(TOP));
+    render_graph.add_system_node("split_bottom_camera", CameraNode::new(BOTTOM));
+    render_graph.add_node(
+        SPLIT_PASS,
+        SplitPassNode::new(
+            PassDescriptor {
+                color_attachments: vec![msaa.color_attachment_descriptor(
+                    TextureAttachment::Input("color_attachment".to_string()),
+                    TextureAttachment::Input("color_resolve_target".to_string()),
+                    Operations {
+                        load: LoadOp::Clear(Color::BLACK),
+                        store: true,
+                    },
+                )],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachmentDescriptor {
+                    attachment: TextureAttachment::Input("depth".to_string()),
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+                sample_count: msaa.samples,
+            },
+            [View::new(TOP, 0.), View::new(BOTTOM, 0.5)],
+        ),
+    );
+
+    if msaa.samples > 1 {
+        log_error!(render_graph.add_slot_edge(
+            node::PRIMARY_SWAP_CHAIN,
+            WindowSwapChainNode::OUT_TEXTURE,
+            SPLIT_PASS,
+            "color_resolve_target",
+        ));
+        log_error!(render_graph.add_slot_edge(
+            node::MAIN_SAMPLED_COLOR_ATTACHMENT,
+            WindowTextureNode::OUT_TEXTURE,
+            SPLIT_PASS,
+            "color_attachment",
+        ));
+    } else {
+        log_error!(render_graph.add_slot_edge(
+            node::PRIMARY_SWAP_CHAIN,
+            WindowSwapChainNode::OUT_TEXTURE,
+            SPLIT_PASS,
+            "color_attachment",
+        ));
+    }
+    log_error!(render_graph.add_slot_edge(
+        node::MAIN_DEPTH_TEXTURE,
+        WindowTextureNode::OUT_TEXTURE,
+        SPLIT_PASS,
+        "depth",
+    ));
+    log_error!(render_graph.add_node_edge("split_top_camera", SPLIT_PASS));
+    log_error!(render_graph.add_node_edge("split_bottom_camera", SPLIT_PASS));
+    log_error!(render_graph.add_node_edge(node::MAIN_PASS, SPLIT_PASS));
+    log_error!(render_graph.add_node_edge(SPLIT_PASS, UI_PASS));
+}
+
+/// Split the window while both ships are flying, with a camera of player two's own for the bottom
+/// half, and join it again as soon as either is gone
+fn split(
+    mut commands: Commands,
+    mut screen: ResMut<SplitScreen>,
+    ships: Query<(&Player, &Transform)>,
+    cameras: Query<Entity, With<SecondCamera>>,
+) {
+    let second = ships.iter().find(|(player, _)| player.0 == 1);
+    let active = second.is_some() && ships.iter().any(|(player, _)| player.0 == 0);
+    if screen.active != active {
+        screen.active = active;
+    }
+    match (second, cameras.iter().next()) {
+        (Some((_, ship)), None) if active => {
+            let look_at = ship.translation;
+            commands
+                .spawn_bundle(PerspectiveCameraBundle {
+                    camera: Camera {
+                        name: Some(BOTTOM.to_string()),
+                        ..Default::default()
+                    },
+                    transform: Transform::from_translation(
+                        ship.translation + ship.rotation * CHASE_OFFSET,
+                    )
+                    .looking_at(look_at, ship.local_z()),
+                    ..Default::default()
+                })
+                .insert(SecondCamera {
+                    look_at,
+                    ..Default::default()
+                })
+                .insert(TiedToGame);
+        }
+        (_, Some(camera)) if !active => commands.entity(camera).despawn(),
+        _ => {}
+    }
+}
+
+fn join(mut screen: ResMut<SplitScreen>) {
+    screen.active = false;
+}
+
+fn follow(
+    time: Res<Time>,
+    settings: Res<CameraSettings>,
+    ships: Query<(&Player, &Transform), Without<SecondCamera>>,
+    mut cameras: Query<(&mut Transform, &mut SecondCamera)>,
+) {
+    let ship = match ships.iter().find(|(player, _)| player.0 == 1) {
+        Some((_, ship)) => ship,
+        None => return,
+    };
+    let delta = time.delta_seconds();
+    for (mut transform, mut camera) in cameras.iter_mut() {
+        let camera: &mut SecondCamera = &mut camera;
+        let position = smooth_damp(
+            transform.translation,
+            ship.translation + ship.rotation * CHASE_OFFSET,
+            &mut camera.velocity,
+            settings.position_smooth_time,
+            delta,
+        );
+        camera.look_at = smooth_damp(
+            camera.look_at,
+            ship.translation + ship.rotation * Vec3::new(0., 0., 20.),
+            &mut camera.look_velocity,
+            settings.look_smooth_time,
+            delta,
+        );
+        *transform =
+            Transform::from_translation(position).looking_at(camera.look_at, ship.local_z());
+    }
+}
+
+/// Move the main camera between the main pass and the top half, and fit every camera's aspect to
+/// the part of the window it's drawn in. Bevy only refits projections when the window is resized.
+fn hand_out_cameras(
+    screen: Res<SplitScreen>,
+    windows: Res<Windows>,
+    mut active_cameras: ResMut<ActiveCameras>,
+    mut cameras: Query<(Entity, &mut Camera, &mut PerspectiveProjection)>,
+) {
+    let main = cameras
+        .iter_mut()
+        .find(|(_, camera, _)| camera.name.as_deref() == Some(CAMERA_3D))
+        .map(|(entity, _, _)| entity);
+    let (whole, top) = if screen.active {
+        (None, main)
+    } else {
+        (main, None)
+    };
+    if let Some(camera) = active_cameras.get_mut(CAMERA_3D) {
+        camera.entity = whole;
+    }
+    if let Some(camera) = active_cameras.get_mut(TOP) {
+        camera.entity = top;
+    }
+
+    let window = match windows.get_primary() {
+        Some(window) if window.height() > 0. => window,
+        _ => return,
+    };
+    let mut aspect_ratio = window.width() / window.height();
+    if screen.active {
+        aspect_ratio *= 2.;
+    }
+    for (_, mut camera, mut projection) in cameras.iter_mut() {
+        if camera.window != window.id() {
+            continue;
+        }
+        if (projection.aspect_ratio - aspect_ratio).abs() > f32::EPSILON {
+            projection.aspect_ratio = aspect_ratio;
+        }
+        let matrix = projection.get_projection_matrix();
+        if camera.projection_matrix != matrix {
+            camera.projection_matrix = matrix;
+        }
+    }
+}
+
+/// A camera of the split pass and the part of the window it's drawn in
+struct View {
+    camera: &'static str,
+    /// Where its top edge is, as a fraction of the window's height down from the top
+    top: f32,
+    commands: Vec<RenderCommand>,
+}
+
+impl View {
+    fn new(camera: &'static str, top: f32) -> Self {
+        View {
+            camera,
+            top,
+            commands: Vec::new(),
+        }
+    }
+}
+
+/// Bevy's `PassNode` for [`MainPass`] entities, but drawing each camera into its own half of the
+/// window, and only while the window is split
+struct SplitPassNode {
+    descriptor: PassDescriptor,
+    inputs: Vec<ResourceSlotInfo>,
+    views: [View; 2],
+    query_state: Option<QueryState<&'static MainPass>>,
+}
+
+impl SplitPassNode {
+    fn new(descriptor: PassDescriptor, views: [View; 2]) -> Self {
+        let mut inputs = vec![ResourceSlotInfo::new(
+            "color_attachment",
+            RenderResourceType::Texture,
+        )];
+        if descriptor.color_attachments[0].resolve_target.is_some() {
+            inputs.push(ResourceSlotInfo::new(
+                "color_resolve_target",
+                RenderResourceType::Texture,
+            ));
+        }
+        inputs.push(ResourceSlotInfo::new("depth", RenderResourceType::Texture));
+        SplitPassNode {
+            descriptor,
+            inputs,
+            views,
+            query_state: None,
+        }
+    }
+
+    fn active(world: &World) -> bool {
+        world
+            .get_resource::<SplitScreen>()
+            .map_or(false, |screen| screen.active)
+    }
+}
+
+impl Node for SplitPassNode {
+    fn input(&self) -> &[ResourceSlotInfo] {
+        &self.inputs
+    }
+
+    fn prepare(&mut self, world: &mut World) {
+        if !Self::active(world) {
+            return;
+        }
+        let query_state = self.query_state.get_or_insert_with(|| world.query());
+        let views = &mut self.views;
+        world.resource_scope(|world, mut active_cameras: Mut<ActiveCameras>| {
+            let pipelines = world.get_resource::<Assets<PipelineDescriptor>>().unwrap();
+            let render_resource_context = &**world
+                .get_resource::<Box<dyn RenderResourceContext>>()
+                .unwrap();
+
+            for view in views.iter_mut() {
+                let active_camera = match active_cameras.get_mut(view.camera) {
+                    Some(active_camera) => active_camera,
+                    None => continue,
+                };
+                let visible_entities = match active_camera
+                    .entity
+                    .and_then(|entity| world.get::<VisibleEntities>(entity))
+                {
+                    Some(visible_entities) => visible_entities,
+                    None => continue,
+                };
+                // The camera's bind groups differ between the halves, so these can't be shared
+                let mut pipeline_camera_commands = HashMap::default();
+                for visible_entity in visible_entities.iter() {
+                    if query_state.get(world, visible_entity.entity).is_err() {
+                        continue;
+                    }
+                    let draw = match world.get::<Draw>(visible_entity.entity) {
+                        Some(draw) => draw,
+                        None => continue,
+                    };
+                    if let Some(visible) = world.get::<Visible>(visible_entity.entity) {
+                        if !visible.is_visible {
+                            continue;
+                        }
+                    }
+                    for render_command in draw.render_commands.iter() {
+                        view.commands.push(render_command.clone());
+                        if let RenderCommand::SetPipeline { pipeline } = render_command {
+                            let bind_groups = pipeline_camera_commands
+                                .entry(pipeline.clone_weak())
+                                .or_insert_with(|| {
+                                    let layout =
+                                        pipelines.get(pipeline).unwrap().get_layout().unwrap();
+                                    layout
+                                        .bind_groups
+                                        .iter()
+                                        .filter_map(|descriptor| {
+                                            let bind_group =
+                                                active_camera.bindings.update_bind_group(
+                                                    descriptor,
+                                                    render_resource_context,
+                                                )?;
+                                            Some(RenderCommand::SetBindGroup {
+                                                index: descriptor.index,
+                                                bind_group: bind_group.id,
+                                                dynamic_uniform_indices: bind_group
+                                                    .dynamic_uniform_indices
+                                                    .clone(),
+                                            })
+                                        })
+                                        .collect::<Vec<_>>()
+                                });
+                            view.commands.extend(bind_groups.iter().cloned());
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    fn update(
+        &mut self,
+        world: &World,
+        render_context: &mut dyn RenderContext,
+        input: &ResourceSlots,
+        _output: &mut ResourceSlots,
+    ) {
+        if !Self::active(world) {
+            return;
+        }
+        let window = match world.get_resource::<Windows>().unwrap().get_primary() {
+            Some(window) => window,
+            None => {
+                self.views.iter_mut().for_each(|view| view.commands.clear());
+                return;
+            }
+        };
+        let (width, height) = (
+            window.physical_width() as f32,
+            window.physical_height() as f32,
+        );
+
+        let texture =
+            |index| TextureAttachment::Id(input.get(index).unwrap().get_texture().unwrap());
+        let color_attachment = &mut self.descriptor.color_attachments[0];
+        if let Some(clear_color) = world.get_resource::<ClearColor>() {
+            color_attachment.ops.load = LoadOp::Clear(clear_color.0);
+        }
+        color_attachment.attachment = texture(0);
+        if color_attachment.resolve_target.is_some() {
+            color_attachment.resolve_target = Some(texture(1));
+        }
+        self.descriptor
+            .depth_stencil_attachment
+            .as_mut()
+            .unwrap()
+            .attachment = texture(self.inputs.len() - 1);
+
+        let render_resource_bindings = world.get_resource::<RenderResourceBindings>().unwrap();
+        let pipelines = world.get_resource::<Assets<PipelineDescriptor>>().unwrap();
+        let views = &mut self.views;
+        render_context.begin_pass(
+            &self.descriptor,
+            render_resource_bindings,
+            &mut |render_pass| {
+                for view in views.iter_mut() {
+                    let top = view.top * height;
+                    // WebGL counts viewports up from the bottom of the canvas, wgpu down from the
+                    // top
+                    #[cfg(target_arch = "wasm32")]
+                    let top = height / 2. - top;
+                    render_pass.set_viewport(0., top, width, height / 2., 0., 1.);
+
+                    let mut draw_state = DrawState::default();
+                    for render_command in view.commands.drain(..) {
+                        match render_command {
+                            RenderCommand::SetPipeline { pipeline } => {
+                                if draw_state.is_pipeline_set(&pipeline) {
+                                    continue;
+                                }
+                                render_pass.set_pipeline(&pipeline);
+                                draw_state
+                                    .set_pipeline(&pipeline, pipelines.get(&pipeline).unwrap());
+                            }
+                            RenderCommand::DrawIndexed {
+                                base_vertex,
+                                indices,
+                                instances,
+                            } => {
+                                if draw_state.can_draw_indexed() {
+                                    render_pass.draw_indexed(indices, base_vertex, instances);
+                                }
+                            }
+                            RenderCommand::Draw {
+                                vertices,
+                                instances,
+                            } => {
+                                if draw_state.can_draw() {
+                                    render_pass.draw(vertices, instances);
+                                }
+                            }
+                            RenderCommand::SetVertexBuffer {
+                                buffer,
+                                offset,
+                                slot,
+                            } => {
+                                if draw_state.vertex_buffers[slot as usize]
+                                    == Some((buffer, offset))
+                                {
+                                    continue;
+                                }
+                                render_pass.set_vertex_buffer(slot, buffer, offset);
+                                draw_state.vertex_buffers[slot as usize] = Some((buffer, offset));
+                            }
+                            RenderCommand::SetIndexBuffer {
+                                buffer,
+                                offset,
+                                index_format,
+                            } => {
+                                let index_buffer = Some((buffer, offset, index_format));
+                                if draw_state.index_buffer == index_buffer {
+                                    continue;
+                                }
+                                render_pass.set_index_buffer(buffer, offset, index_format);
+                                draw_state.index_buffer = index_buffer;
+                            }
+                            RenderCommand::SetBindGroup {
+                                index,
+                                bind_group,
+                                dynamic_uniform_indices,
+                            } => {
+                                if dynamic_uniform_indices.is_none()
+                                    && draw_state.bind_groups[index as usize] == Some(bind_group)
+                                {
+                                    continue;
+                                }
+                                let pipeline = draw_state.pipeline.as_ref().unwrap();
+                                let layout = pipelines.get(pipeline).unwrap().get_layout().unwrap();
+                                render_pass.set_bind_group(
+                                    index,
+                                    layout.get_bind_group(index).unwrap().id,
+                                    bind_group,
+                                    dynamic_uniform_indices.as_deref(),
+                                );
+                                draw_state.bind_groups[index as usize] = Some(bind_group);
+                            }
+                        }
+                    }
+                }
+            },
+        );
+    }
+}
+
+/// What's bound so far in a pass, to skip rebinding and to skip draws that would be invalid. Bevy
+/// keeps its own copy of this private to its `PassNode`.
+#[derive(Default)]
+struct DrawState {
+    pipeline: Option<Handle<PipelineDescriptor>>,
+    bind_groups: Vec<Option<BindGroupId>>,
+    vertex_buffers: Vec<Option<(BufferId, u64)>>,
+    index_buffer: Option<(BufferId, u64, IndexFormat)>,
+}
+
+impl DrawState {
+    fn is_pipeline_set(&self, pipeline: &Handle<PipelineDescriptor>) -> bool {
+        self.pipeline.as_ref() == Some(pipeline)
+    }
+
+    fn set_pipeline(
+        &mut self,
+        handle: &Handle<PipelineDescriptor>,
+        descriptor: &PipelineDescriptor,
+    ) {
+        let layout = descriptor.get_layout().unwrap();
+        self.pipeline = Some(handle.clone_weak());
+        self.bind_groups = vec![None; layout.bind_groups.len()];
+        self.vertex_buffers = vec![None; layout.vertex_buffer_descriptors.len()];
+        self.index_buffer = None;
+    }
+
+    fn can_draw(&self) -> bool {
+        self.bind_groups.iter().all(Option::is_some)
+            && self.vertex_buffers.iter().all(Option::is_some)
+    }
+
+    fn can_draw_indexed(&self) -> bool {
+        self.can_draw() && self.index_buffer.is_some()
+    }
+}
@@ -0,0 +1,198 @@
+use bevy::prelude::*;
+
+use crate::settings::Settings;
+
+/// How raw stick positions are turned into movement and turning
+pub struct StickSettings {
+    pub move_stick: StickTuning,
+    pub look_stick: StickTuning,
+    /// Turning at full deflection of the look stick. The same units as the mouse's sensitivity
+    /// times the window size, so the default roughly matches the mouse in a 1000 pixel window.
+    pub look_sensitivity: f32,
+}
+
+impl Default for StickSettings {
+    fn default() -> Self {
+        Self {
+            move_stick: StickTuning::default(),
+            look_stick: StickTuning {
+                exponent: 2.,
+                ..Default::default()
+            },
+            look_sensitivity: 3.,
+        }
+    }
+}
+
+pub struct StickTuning {
+    /// Distance from the center, from 0 to 1, that's ignored so drift doesn't creep
+    pub inner_deadzone: f32,
+    /// Distance from the edge that counts as full deflection, since sticks rarely reach 1
+    pub outer_deadzone: f32,
+    /// Each axis is ignored while it's this close to 0, so pushing straight up doesn't also
+    /// drift sideways
+    pub axial_deadzone: f32,
+    /// Response curve. Above 1 gives finer control near the center.
+    pub exponent: f32,
+    pub invert_x: bool,
+    pub invert_y: bool,
+}
+
+impl Default for StickTuning {
+    fn default() -> Self {
+        Self {
+            inner_deadzone: 0.15,
+            outer_deadzone: 0.05,
+            axial_deadzone: 0.05,
+            exponent: 1.5,
+            invert_x: false,
+            invert_y: false,
+        }
+    }
+}
+
+impl StickTuning {
+    /// Shape a raw stick position into one with a length from 0 to 1
+    pub fn apply(&self, raw: Vec2) -> Vec2 {
+        let axial = |value: f32| {
+            if value.abs() < self.axial_deadzone {
+                0.
+            } else {
+                value.signum() * (value.abs() - self.axial_deadzone) / (1. - self.axial_deadzone)
+            }
+        };
+        let stick = Vec2::new(axial(raw.x), axial(raw.y));
+        let length = stick.length();
+        if length <= self.inner_deadzone {
+            return Vec2::ZERO;
+        }
+        let range = (1. - self.inner_deadzone - self.outer_deadzone).max(f32::EPSILON);
+        let scaled = ((length - self.inner_deadzone) / range).min(1.);
+        let mut stick = stick / length * scaled.powf(self.exponent);
+        if self.invert_x {
+            stick.x = -stick.x;
+        }
+        if self.invert_y {
+            stick.y = -stick.y;
+        }
+        stick
+    }
+}
+
+pub(super) fn apply_settings(settings: Res<Settings>, mut sticks: ResMut<StickSettings>) {
+    if settings.is_changed() {
+        let sticks = &mut *sticks;
+        sticks.look_sensitivity = settings.stick_sensitivity;
+        for stick in [&mut sticks.move_stick, &mut sticks.look_stick].iter_mut() {
+            stick.inner_deadzone = settings.stick_deadzone;
+            stick.outer_deadzone = settings.stick_outer_deadzone;
+            stick.axial_deadzone = settings.stick_axial_deadzone;
+        }
+        sticks.look_stick.exponent = settings.stick_curve;
+        sticks.look_stick.invert_x = settings.look_stick_invert_x;
+        sticks.look_stick.invert_y = settings.look_stick_invert_y;
+        sticks.move_stick.invert_x = settings.move_stick_invert_x;
+        sticks.move_stick.invert_y = settings.move_stick_invert_y;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// No deadzones and a linear response, so each test only turns on what it checks
+    fn linear() -> StickTuning {
+        StickTuning {
+            inner_deadzone: 0.,
+            outer_deadzone: 0.,
+            axial_deadzone: 0.,
+            exponent: 1.,
+            invert_x: false,
+            invert_y: false,
+        }
+    }
+
+    fn assert_near(actual: Vec2, expected: Vec2) {
+        assert!(
+            (actual - expected).length() < 1e-5,
+            "expected {:?}, got {:?}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn linear_passes_through() {
+        let raw = Vec2::new(0.3, -0.4);
+        assert_near(linear().apply(raw), raw);
+    }
+
+    #[test]
+    fn inner_deadzone_ignores_drift() {
+        let tuning = StickTuning {
+            inner_deadzone: 0.2,
+            ..linear()
+        };
+        assert_eq!(tuning.apply(Vec2::new(0.1, 0.1)), Vec2::ZERO);
+        // Movement starts from 0 at the edge of the deadzone instead of jumping
+        assert_near(tuning.apply(Vec2::new(0.6, 0.)), Vec2::new(0.5, 0.));
+    }
+
+    #[test]
+    fn outer_deadzone_reaches_full_deflection() {
+        let tuning = StickTuning {
+            outer_deadzone: 0.1,
+            ..linear()
+        };
+        assert_near(tuning.apply(Vec2::new(0., 0.9)), Vec2::new(0., 1.));
+        assert_near(tuning.apply(Vec2::new(0., 1.)), Vec2::new(0., 1.));
+        assert_near(tuning.apply(Vec2::new(0., 0.45)), Vec2::new(0., 0.5));
+    }
+
+    #[test]
+    fn axial_deadzone_keeps_straight_pushes_straight() {
+        let tuning = StickTuning {
+            axial_deadzone: 0.1,
+            ..linear()
+        };
+        let stick = tuning.apply(Vec2::new(0.05, 1.));
+        assert_eq!(stick.x, 0.);
+        assert_near(stick, Vec2::new(0., 1.));
+        assert_near(tuning.apply(Vec2::new(-0.55, 0.)), Vec2::new(-0.5, 0.));
+    }
+
+    #[test]
+    fn exponent_curves_the_response() {
+        let tuning = StickTuning {
+            exponent: 2.,
+            ..linear()
+        };
+        assert_near(tuning.apply(Vec2::new(0.5, 0.)), Vec2::new(0.25, 0.));
+        // Only the length is curved, not the direction
+        let stick = tuning.apply(Vec2::new(0.3, 0.4));
+        assert_near(stick, Vec2::new(0.3, 0.4) * 0.5);
+    }
+
+    #[test]
+    fn inverts_each_axis() {
+        let raw = Vec2::new(0.3, 0.4);
+        let x = StickTuning {
+            invert_x: true,
+            ..linear()
+        };
+        let y = StickTuning {
+            invert_y: true,
+            ..linear()
+        };
+        assert_near(x.apply(raw), Vec2::new(-0.3, 0.4));
+        assert_near(y.apply(raw), Vec2::new(0.3, -0.4));
+    }
+
+    #[test]
+    fn defaults_stay_in_range() {
+        let tuning = StickTuning::default();
+        assert_eq!(tuning.apply(Vec2::ZERO), Vec2::ZERO);
+        assert_near(tuning.apply(Vec2::new(1., 0.)), Vec2::new(1., 0.));
+        assert!(tuning.apply(Vec2::new(1., 1.)).length() <= 1. + 1e-5);
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// A calendar day in UTC, so everyone gets the same daily challenge at the same time
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UtcDate {
+    pub year: u32,
+    /// From 1 to 12
+    pub month: u32,
+    /// From 1 to 31
+    pub day: u32,
+}
+
+impl UtcDate {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn today() -> Self {
+        let seconds = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        Self::from_days(seconds / 86_400)
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn today() -> Self {
+        let now = js_sys::Date::new_0();
+        UtcDate {
+            year: now.get_utc_full_year(),
+            month: now.get_utc_month() + 1,
+            day: now.get_utc_date(),
+        }
+    }
+
+    /// The date `days` days after 1970-01-01, from Howard Hinnant's `civil_from_days`
+    #[cfg(not(target_arch = "wasm32"))]
+    fn from_days(days: u64) -> Self {
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let day_of_era = z % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        // Counting from March, so the leap day is at the end
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+        let month = if month_from_march < 10 {
+            month_from_march + 3
+        } else {
+            month_from_march - 9
+        };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        UtcDate {
+            year: year as u32,
+            month: month as u32,
+            day: day as u32,
+        }
+    }
+
+    /// The daily challenge's seed. Different for every day, and scrambled so that neighbouring
+    /// days don't get neighbouring seeds.
+    pub fn seed(self) -> u64 {
+        splitmix64((self.year * 10_000 + self.month * 100 + self.day) as u64)
+    }
+}
+
+/// One step of splitmix64, which spreads close inputs far apart
+pub fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl fmt::Display for UtcDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: u32, month: u32, day: u32) -> UtcDate {
+        UtcDate { year, month, day }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn counts_days_from_the_epoch() {
+        assert_eq!(UtcDate::from_days(0), date(1970, 1, 1));
+        assert_eq!(UtcDate::from_days(11_016), date(2000, 2, 29));
+        assert_eq!(UtcDate::from_days(11_017), date(2000, 3, 1));
+        // Not a leap year, so straight from February 28th
+        assert_eq!(UtcDate::from_days(47_540), date(2100, 2, 28));
+        assert_eq!(UtcDate::from_days(47_541), date(2100, 3, 1));
+    }
+
+    #[test]
+    fn neighbouring_days_get_far_apart_seeds() {
+        let today = date(2024, 5, 17).seed();
+        let tomorrow = date(2024, 5, 18).seed();
+        assert_ne!(today, tomorrow);
+        assert!((today ^ tomorrow).count_ones() > 16);
+    }
+}
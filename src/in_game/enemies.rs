@@ -0,0 +1,316 @@
+use bevy::prelude::*;
+use bevy_rapier3d::physics::RigidBodyHandleComponent;
+use bevy_rapier3d::rapier::dynamics::RigidBodySet;
+use bevy_rapier3d::rapier::geometry::{ColliderSet, Ray};
+use bevy_rapier3d::rapier::math::Vector;
+use bevy_rapier3d::rapier::pipeline::QueryPipeline;
+use rand::prelude::*;
+
+use crate::in_game::points::AddPoints;
+
+use super::asteroids::Asteroid;
+use super::bullets::{Bullets, FiredBy, RemoveBullet, Shooter};
+use super::controls::Player;
+use super::events::{Contact, EventAdapter};
+use super::game_area::{HEIGHT, LENGTH, WIDTH};
+use super::run::RunRng;
+
+pub struct EnemiesPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for EnemiesPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<EnemySettings>()
+            .init_resource::<EnemyAssets>()
+            .init_resource::<SpawnTimer>()
+            .add_system_set(SystemSet::on_enter(self.0.clone()).with_system(reset_timer.system()))
+            .add_system_set(
+                SystemSet::on_update(self.0.clone())
+                    .with_system(spawn.system())
+                    .with_system(steer.system())
+                    .with_system(shoot.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(self.0.clone())
+                    .after(EventAdapter)
+                    .with_system(bullet_enemy_contact.system())
+                    .with_system(enemy_bullet_contact.system()),
+            );
+    }
+}
+
+/// Tuning for the UFOs that hunt the player
+pub struct EnemySettings {
+    pub spawn_interval: f32,
+    pub max_enemies: usize,
+    pub hits: u8,
+    pub points: u64,
+    pub max_speed: f32,
+    pub max_force: f32,
+    pub turn_rate: f32,
+    /// Enemies try to stay this far from the player rather than ramming it
+    pub preferred_distance: f32,
+    /// How far ahead enemies look for asteroids to avoid
+    pub look_ahead: f32,
+    pub avoid_strength: f32,
+    pub fire_interval: f32,
+    /// Enemies only shoot when the player is within this angle of their nose, in radians
+    pub fire_cone: f32,
+    pub fire_range: f32,
+}
+
+impl Default for EnemySettings {
+    fn default() -> Self {
+        Self {
+            spawn_interval: 20.,
+            max_enemies: 2,
+            hits: 3,
+            points: 10,
+            max_speed: 8.,
+            max_force: 10.,
+            turn_rate: 1.5,
+            preferred_distance: 25.,
+            look_ahead: 20.,
+            avoid_strength: 2.,
+            fire_interval: 1.5,
+            fire_cone: 0.2,
+            fire_range: 80.,
+        }
+    }
+}
+
+pub struct EnemyAssets {
+    pub(super) material: Handle<StandardMaterial>,
+}
+
+impl FromWorld for EnemyAssets {
+    fn from_world(world: &mut World) -> Self {
+        EnemyAssets {
+            material: world
+                .get_resource_mut::<Assets<StandardMaterial>>()
+                .unwrap()
+                .add(StandardMaterial {
+                    base_color: Color::rgb(0.3, 0.8, 0.3),
+                    emissive: Color::rgb(0., 0.2, 0.),
+                    ..Default::default()
+                }),
+        }
+    }
+}
+
+pub struct Enemy {
+    pub hits: u8,
+    fire_timer: Timer,
+}
+
+pub struct EnemyBullet;
+
+#[derive(Default)]
+struct SpawnTimer(Timer);
+
+/// Every run starts with the same wait, so runs on the same seed match up
+fn reset_timer(mut timer: ResMut<SpawnTimer>, settings: Res<EnemySettings>) {
+    *timer = SpawnTimer(Timer::from_seconds(settings.spawn_interval, true));
+}
+
+fn spawn(
+    mut commands: Commands,
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    settings: Res<EnemySettings>,
+    assets: Res<EnemyAssets>,
+    enemies: Query<(), With<Enemy>>,
+    ships: Query<&Transform, With<Player>>,
+    mut rng: ResMut<RunRng>,
+    mut timer: ResMut<SpawnTimer>,
+) {
+    if !timer.0.tick(time.delta()).just_finished() || enemies.iter().count() >= settings.max_enemies
+    {
+        return;
+    }
+    if ships.iter().next().is_none() {
+        return;
+    }
+    let rng = &mut rng.enemies;
+    // Pick a spot just inside one of the six walls
+    let half = Vec3::new(WIDTH, HEIGHT, LENGTH) * 0.45;
+    let mut position = Vec3::new(
+        rng.gen_range(-half.x..half.x),
+        rng.gen_range(-half.y..half.y),
+        rng.gen_range(-half.z..half.z),
+    );
+    let axis = rng.gen_range(0..3);
+    position[axis] = if rng.gen() { half[axis] } else { -half[axis] };
+
+    // Ships fly along their local z, so look away from the nearest player to face them
+    let ship = nearest_ship(&ships, position).unwrap();
+    let transform =
+        Transform::from_translation(position).looking_at(position * 2. - ship.translation, Vec3::Y);
+    super::spawn_ship(
+        &mut commands,
+        &asset_server,
+        assets.material.clone(),
+        transform,
+    )
+    .insert(Enemy {
+        hits: settings.hits,
+        fire_timer: Timer::from_seconds(settings.fire_interval, false),
+    });
+}
+
+/// The player's ship closest to `position`
+fn nearest_ship<'a>(
+    ships: &'a Query<&Transform, With<Player>>,
+    position: Vec3,
+) -> Option<&'a Transform> {
+    ships.iter().min_by(|a, b| {
+        let a = a.translation.distance_squared(position);
+        let b = b.translation.distance_squared(position);
+        a.partial_cmp(&b).unwrap()
+    })
+}
+
+/// Seek the nearest player while steering around asteroids
+fn steer(
+    settings: Res<EnemySettings>,
+    query_pipeline: Res<QueryPipeline>,
+    colliders: Res<ColliderSet>,
+    mut rigid_bodies: ResMut<RigidBodySet>,
+    ships: Query<&Transform, With<Player>>,
+    enemies: Query<(&Transform, &RigidBodyHandleComponent), With<Enemy>>,
+    asteroids: Query<(), With<Asteroid>>,
+) {
+    for (transform, rigid_body_component) in enemies.iter() {
+        let ship = match nearest_ship(&ships, transform.translation) {
+            Some(ship) => ship,
+            None => return,
+        };
+        let rb = match rigid_bodies.get_mut(rigid_body_component.handle()) {
+            Some(rb) => rb,
+            None => continue,
+        };
+        let linvel: Vec3 = rb.linvel().clone_owned().into();
+        let to_ship = ship.translation - transform.translation;
+        let distance = to_ship.length();
+
+        // Seek, but slow down and hold position once close enough
+        let mut desired = to_ship.normalize_or_zero()
+            * settings.max_speed
+            * ((distance - settings.preferred_distance) / settings.preferred_distance)
+                .clamp(-1., 1.);
+
+        // Avoid: feel ahead along the direction of travel and a few whiskers around it
+        let heading = if linvel.length() > 0.1 {
+            linvel.normalize()
+        } else {
+            transform.local_z()
+        };
+        let whiskers = [
+            heading,
+            (heading + transform.local_x() * 0.5).normalize(),
+            (heading - transform.local_x() * 0.5).normalize(),
+            (heading + transform.local_y() * 0.5).normalize(),
+            (heading - transform.local_y() * 0.5).normalize(),
+        ];
+        for whisker in whiskers.iter() {
+            let hit = query_pipeline.cast_ray_and_get_normal(
+                &colliders,
+                &Ray::new(transform.translation.into(), (*whisker).into()),
+                settings.look_ahead,
+                true,
+                Default::default(),
+                Some(&|_, c| asteroids.get(Entity::from_bits(c.user_data as u64)).is_ok()),
+            );
+            if let Some((_, hit)) = hit {
+                let urgency = 1. - hit.toi / settings.look_ahead;
+                let normal: Vec3 = hit.normal.into();
+                desired += normal * settings.max_speed * settings.avoid_strength * urgency;
+            }
+        }
+
+        let force = (desired - linvel).clamp_length_max(settings.max_force);
+        rb.apply_force(Vector::from(force), true);
+
+        // Turn the nose towards the player
+        let axis = transform.local_z().cross(to_ship.normalize_or_zero());
+        rb.set_angvel(Vector::from(axis * settings.turn_rate), true);
+    }
+}
+
+fn shoot(
+    mut bullets: Bullets,
+    time: Res<Time>,
+    settings: Res<EnemySettings>,
+    ships: Query<&Transform, With<Player>>,
+    mut enemies: Query<(&Transform, &mut Enemy)>,
+) {
+    for (transform, mut enemy) in enemies.iter_mut() {
+        if !enemy.fire_timer.tick(time.delta()).finished() {
+            continue;
+        }
+        let ship = match nearest_ship(&ships, transform.translation) {
+            Some(ship) => ship,
+            None => return,
+        };
+        let to_ship = ship.translation - transform.translation;
+        if to_ship.length() < settings.fire_range
+            && transform.local_z().angle_between(to_ship) < settings.fire_cone
+        {
+            enemy.fire_timer.reset();
+            bullets.fire(transform, Shooter::Enemy);
+        }
+    }
+}
+
+fn bullet_enemy_contact(
+    mut commands: Commands,
+    mut events: EventReader<Contact>,
+    mut removed: EventWriter<RemoveBullet>,
+    mut points: EventWriter<AddPoints>,
+    settings: Res<EnemySettings>,
+    bullet_query: Query<(Option<&super::Damage>, Option<&FiredBy>), With<super::Bullet>>,
+    mut enemy_query: Query<&mut Enemy>,
+) {
+    for event in events.iter() {
+        if let Contact::Started(a, b) = *event {
+            for (bullet, enemy_e) in [(a, b), (b, a)].iter().copied() {
+                if let (Ok((damage, fired_by)), Ok(mut enemy)) =
+                    (bullet_query.get(bullet), enemy_query.get_mut(enemy_e))
+                {
+                    removed.send(RemoveBullet(bullet));
+                    // If two bullets hit at the same time, only the first one destroys it
+                    if enemy.hits == 0 {
+                        continue;
+                    }
+                    enemy.hits = enemy
+                        .hits
+                        .saturating_sub(damage.map_or(1, |damage| damage.0));
+                    if enemy.hits == 0 {
+                        points.send(AddPoints {
+                            points: settings.points,
+                            player: fired_by.map_or(0, |fired_by| fired_by.0),
+                        });
+                        commands.entity(enemy_e).despawn_recursive();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Enemy bullets are spent on whatever they touch
+fn enemy_bullet_contact(
+    mut events: EventReader<Contact>,
+    mut removed: EventWriter<RemoveBullet>,
+    bullet_query: Query<(), With<EnemyBullet>>,
+    enemy_query: Query<(), With<Enemy>>,
+) {
+    for event in events.iter() {
+        if let Contact::Started(a, b) = *event {
+            for (bullet, other) in [(a, b), (b, a)].iter().copied() {
+                if bullet_query.get(bullet).is_ok() && enemy_query.get(other).is_err() {
+                    removed.send(RemoveBullet(bullet));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use bevy::prelude::*;
+
+use crate::in_game::{HudElement, TiedToGame};
+
+use super::controls::{Controllable, Player};
+
+pub struct EnergyPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for EnergyPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<EnergySettings>().add_system_set(
+            SystemSet::on_update(self.0.clone())
+                .with_system(equip.system())
+                .with_system(regenerate.system()),
+        );
+    }
+}
+
+/// The energy bar of the ship the HUD follows
+pub struct EnergyHudPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for EnergyHudPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(SystemSet::on_enter(self.0.clone()).with_system(setup.system()))
+            .add_system_set(SystemSet::on_update(self.0.clone()).with_system(update_hud.system()));
+    }
+}
+
+pub struct EnergySettings {
+    pub max: f32,
+    /// Energy regained per second
+    pub regen_rate: f32,
+    /// Seconds after energy was last used before it starts coming back
+    pub regen_delay: f32,
+}
+
+impl Default for EnergySettings {
+    fn default() -> Self {
+        Self {
+            max: 100.,
+            regen_rate: 20.,
+            regen_delay: 0.5,
+        }
+    }
+}
+
+/// Each ship's energy, shared by its boost and its laser
+pub struct Energy {
+    pub current: f32,
+    since_drain: f32,
+}
+
+impl Energy {
+    /// Use up `amount` of energy, if there's that much left
+    pub fn drain(&mut self, amount: f32) -> bool {
+        if self.current < amount {
+            return false;
+        }
+        self.current -= amount;
+        self.since_drain = 0.;
+        true
+    }
+}
+
+struct EnergyBar;
+
+fn setup(mut commands: Commands, mut color_materials: ResMut<Assets<ColorMaterial>>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(200.), Val::Px(10.)),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(24.),
+                    left: Val::Percent(50.),
+                    ..Default::default()
+                },
+                margin: Rect {
+                    left: Val::Px(-100.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            material: color_materials.add(Color::rgba(0., 0., 0., 0.6).into()),
+            ..Default::default()
+        })
+        .insert(TiedToGame)
+        .insert(HudElement)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                        ..Default::default()
+                    },
+                    material: color_materials.add(Color::rgb(0.2, 0.7, 1.).into()),
+                    ..Default::default()
+                })
+                .insert(EnergyBar);
+        });
+}
+
+/// Every ship starts full
+fn equip(
+    mut commands: Commands,
+    settings: Res<EnergySettings>,
+    ships: Query<Entity, (With<Player>, Without<Energy>)>,
+) {
+    for ship in ships.iter() {
+        commands.entity(ship).insert(Energy {
+            current: settings.max,
+            since_drain: 0.,
+        });
+    }
+}
+
+fn regenerate(time: Res<Time>, settings: Res<EnergySettings>, mut query: Query<&mut Energy>) {
+    for mut energy in query.iter_mut() {
+        energy.since_drain += time.delta_seconds();
+        if energy.since_drain >= settings.regen_delay && energy.current < settings.max {
+            energy.current =
+                (energy.current + settings.regen_rate * time.delta_seconds()).min(settings.max);
+        }
+    }
+}
+
+/// The bar shows the energy of the ship the HUD follows
+fn update_hud(
+    settings: Res<EnergySettings>,
+    energy: Query<&Energy, With<Controllable>>,
+    mut bar: Query<&mut Style, With<EnergyBar>>,
+) {
+    if let (Ok(energy), Ok(mut style)) = (energy.single(), bar.single_mut()) {
+        let width = Val::Percent(energy.current / settings.max * 100.);
+        if style.size.width != width {
+            style.size.width = width;
+        }
+    }
+}
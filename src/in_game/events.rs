@@ -1,20 +1,29 @@
 use bevy::prelude::*;
 use bevy_rapier3d::physics::{EventQueue, RigidBodyHandleComponent};
 use bevy_rapier3d::rapier::dynamics::RigidBodySet;
-use bevy_rapier3d::rapier::geometry::{ColliderHandle, ColliderSet, ContactEvent};
+use bevy_rapier3d::rapier::geometry::{
+    ColliderHandle, ColliderSet, ContactEvent, IntersectionEvent,
+};
 
+use crate::audio::AudioCue;
 use crate::in_game::points::AddPoints;
-use crate::util::set_grab_cursor;
 
 use super::asteroids::{Asteroid, AsteroidBundle};
 use super::bounds::ColliderProps;
-use super::controls::Controllable;
+use super::bullets::{FiredBy, RemoveBullet};
+use super::controls::{Controllable, Player};
+use super::enemies::{Enemy, EnemyBullet};
+use super::powerups::{self, ActivePowerUp, PowerUpAssets, PowerUpSettings, Shield};
+use super::run::RunRng;
 
 pub struct EventsPlugin<T>(pub T);
 
 impl<T: crate::util::StateType> Plugin for EventsPlugin<T> {
     fn build(&self, app: &mut AppBuilder) {
-        app.add_event::<Contact>()
+        app.init_resource::<VersusSettings>()
+            .add_event::<Contact>()
+            .add_event::<HitAsteroid>()
+            .add_event::<AsteroidDestroyed>()
             .add_system_set(
                 SystemSet::on_update(self.0.clone())
                     .label(EventAdapter)
@@ -23,21 +32,53 @@ impl<T: crate::util::StateType> Plugin for EventsPlugin<T> {
             .add_system_set(
                 SystemSet::on_update(self.0.clone())
                     .after(EventAdapter)
-                    .with_system(ship_asteroid_contact.system())
+                    .with_system(ship_hazard_contact.system())
                     .with_system(bullet_asteroid_contact.system())
-                    .with_system(bullet_wall_contact.system()),
+                    .with_system(bullet_wall_contact.system())
+                    .with_system(damage_asteroids.system()),
             );
     }
 }
 
 #[derive(SystemLabel, Clone, Eq, PartialEq, Hash, Debug, Default)]
-struct EventAdapter;
+pub(super) struct EventAdapter;
 
-enum Contact {
+/// Whether players can shoot each other's ships down, for playing against each other online
+pub struct VersusSettings {
+    pub enabled: bool,
+    /// Points for destroying someone else's ship
+    pub points: u64,
+}
+
+impl Default for VersusSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            points: 25,
+        }
+    }
+}
+
+/// Contact between two entities, including sensors
+pub(super) enum Contact {
     Started(Entity, Entity),
     Stopped(Entity, Entity),
 }
 
+/// Damage dealt to an asteroid by any weapon
+pub(super) struct HitAsteroid {
+    pub asteroid: Entity,
+    pub damage: u8,
+    /// Who gets the points if it's destroyed
+    pub player: usize,
+}
+
+/// An asteroid was blown apart
+pub(super) struct AsteroidDestroyed {
+    pub position: Vec3,
+    pub linvel: Vec3,
+}
+
 fn events_adapter(
     mut contact_events: EventWriter<Contact>,
     events: Res<EventQueue>,
@@ -62,42 +103,115 @@ fn events_adapter(
             }
         }
     }
+
+    while let Ok(IntersectionEvent {
+        collider1,
+        collider2,
+        intersecting,
+    }) = events.intersection_events.pop()
+    {
+        if let (Some(a), Some(b)) = (get_entity(collider1), get_entity(collider2)) {
+            contact_events.send(if intersecting {
+                Contact::Started(a, b)
+            } else {
+                Contact::Stopped(a, b)
+            });
+        }
+    }
 }
 
-fn ship_asteroid_contact(
+/// Destroy a ship when it runs into an asteroid, an enemy, or an enemy's bullet, or in versus
+/// another player's bullet, unless its [`Shield`] takes the hit. End the game once every ship is
+/// gone.
+fn ship_hazard_contact(
+    mut commands: Commands,
     mut events: EventReader<Contact>,
     mut state: ResMut<State<crate::AppState>>,
-    ship_query: Query<(), With<Controllable>>,
-    asteroid_query: Query<(), With<Asteroid>>,
-    mut windows: ResMut<Windows>,
-    #[cfg(target_arch = "wasm32")] winit_windows: Res<bevy::winit::WinitWindows>,
+    versus: Res<VersusSettings>,
+    ship_query: Query<(Entity, &Player, Option<&Controllable>)>,
+    hazard_query: Query<(), Or<(With<Asteroid>, With<Enemy>, With<EnemyBullet>)>>,
+    bullet_query: Query<&FiredBy, With<super::Bullet>>,
+    mut shields: Query<(Entity, &ActivePowerUp, &mut Shield)>,
+    mut removed: EventWriter<RemoveBullet>,
+    mut points: EventWriter<AddPoints>,
+    mut cues: EventWriter<AudioCue>,
 ) {
+    let mut destroyed = Vec::new();
     for event in events.iter() {
         match *event {
             Contact::Started(a, b) => {
-                if [(a, b), (b, a)]
-                    .iter()
-                    .copied()
-                    .any(|(a, b)| (ship_query.get(a).is_ok() && asteroid_query.get(b).is_ok()))
+                let (ship, hazard, attacker) =
+                    match [(a, b), (b, a)].iter().copied().find_map(|(a, b)| {
+                        let (_, player, _) = ship_query.get(a).ok()?;
+                        if hazard_query.get(b).is_ok() {
+                            return Some((a, b, None));
+                        }
+                        let fired_by = bullet_query
+                            .get(b)
+                            .ok()
+                            .filter(|fired_by| versus.enabled && fired_by.0 != player.0)?;
+                        Some((a, b, Some(fired_by.0)))
+                    }) {
+                        Some(hit) => hit,
+                        None => continue,
+                    };
+                cues.send(AudioCue::ShipHit);
+                if attacker.is_some() {
+                    removed.send(RemoveBullet(hazard));
+                }
+                if destroyed.contains(&ship) {
+                    continue;
+                }
+                match shields
+                    .iter_mut()
+                    .find(|(_, active, shield)| active.applies_to(ship) && shield.0 > 0)
                 {
-                    let window = windows.get_primary_mut().unwrap();
-                    set_grab_cursor(
-                        window,
-                        false,
-                        #[cfg(target_arch = "wasm32")]
-                        &winit_windows,
-                    );
-                    log_error!(state.replace(crate::AppState::End));
+                    Some((shield_e, _, mut shield)) => {
+                        shield.0 -= 1;
+                        if shield.0 == 0 {
+                            commands.entity(shield_e).despawn_recursive();
+                        }
+                    }
+                    None => {
+                        destroyed.push(ship);
+                        if let Some(player) = attacker {
+                            points.send(AddPoints {
+                                points: versus.points,
+                                player,
+                            });
+                        }
+                    }
                 }
             }
             _ => {}
         }
     }
+    if destroyed.is_empty() {
+        return;
+    }
+    let lead_destroyed = destroyed
+        .iter()
+        .any(|&ship| matches!(ship_query.get(ship), Ok((_, _, Some(_)))));
+    let survivor = ship_query
+        .iter()
+        .map(|(ship, _, _)| ship)
+        .find(|ship| !destroyed.contains(ship));
+    for &ship in destroyed.iter() {
+        commands.entity(ship).despawn_recursive();
+    }
+    match survivor {
+        // The main camera and HUD move over to whoever is left
+        Some(survivor) if lead_destroyed => {
+            commands.entity(survivor).insert(Controllable);
+        }
+        Some(_) => {}
+        None => log_error!(state.replace(crate::AppState::End)),
+    }
 }
 
 fn bullet_wall_contact(
-    mut commands: Commands,
     mut events: EventReader<Contact>,
+    mut removed: EventWriter<RemoveBullet>,
     bullet_query: Query<(), With<super::Bullet>>,
     asteroid_query: Query<(), With<super::game_area::GameAreaBound>>,
 ) {
@@ -109,7 +223,7 @@ fn bullet_wall_contact(
                     asteroid_query.get(b).ok()?;
                     Some(a)
                 }) {
-                    commands.entity(bullet).despawn_recursive();
+                    removed.send(RemoveBullet(bullet));
                 }
             }
             _ => {}
@@ -118,47 +232,25 @@ fn bullet_wall_contact(
 }
 
 fn bullet_asteroid_contact(
-    mut commands: Commands,
     mut events: EventReader<Contact>,
-    mut points: EventWriter<AddPoints>,
-    bullet_query: Query<(), With<super::Bullet>>,
-    rigid_bodies: Res<RigidBodySet>,
-    mut asteroid_query: Query<
-        (&Transform, &mut Asteroid, &RigidBodyHandleComponent),
-        With<Asteroid>,
-    >,
+    mut removed: EventWriter<RemoveBullet>,
+    mut hits: EventWriter<HitAsteroid>,
+    bullet_query: Query<(Option<&super::Damage>, Option<&FiredBy>), With<super::Bullet>>,
+    asteroid_query: Query<(), With<Asteroid>>,
 ) {
     for event in events.iter() {
         match *event {
             Contact::Started(a, b) => {
-                for (bullet, asteroid_e) in [(a, b), (b, a)].iter().copied() {
-                    if let (Ok(_), Ok((asteroid_transform, asteroid, rigid_body_component))) =
-                        (bullet_query.get(bullet), asteroid_query.get_mut(asteroid_e))
+                for (bullet, asteroid) in [(a, b), (b, a)].iter().copied() {
+                    if let (Ok((damage, fired_by)), Ok(_)) =
+                        (bullet_query.get(bullet), asteroid_query.get(asteroid))
                     {
-                        commands.entity(bullet).despawn_recursive();
-
-                        let rigid_body = match rigid_bodies.get(rigid_body_component.handle()) {
-                            Some(r) => r,
-                            None => continue,
-                        };
-
-                        let mut asteroid: Mut<Asteroid> = asteroid;
-                        let asteroid_transform: &Transform = asteroid_transform;
-                        // Wrapping so if two bullets hit at the same time, it doesn't panic
-                        asteroid.hits = asteroid.hits.wrapping_sub(1);
-                        if asteroid.hits == 0 {
-                            points.send(AddPoints(asteroid.points));
-                            commands.entity(asteroid_e).despawn_recursive();
-                            for child in asteroid.children.iter() {
-                                commands.spawn_bundle(AsteroidBundle {
-                                    collider_props: ColliderProps {
-                                        linvel: rigid_body.linvel().clone_owned().into(),
-                                        angvel: rigid_body.angvel().clone_owned().into(),
-                                    },
-                                    ..AsteroidBundle::new(&child, *asteroid_transform)
-                                });
-                            }
-                        }
+                        removed.send(RemoveBullet(bullet));
+                        hits.send(HitAsteroid {
+                            asteroid,
+                            damage: damage.map_or(1, |damage| damage.0),
+                            player: fired_by.map_or(0, |fired_by| fired_by.0),
+                        });
                     }
                 }
             }
@@ -166,3 +258,68 @@ fn bullet_asteroid_contact(
         }
     }
 }
+
+fn damage_asteroids(
+    mut commands: Commands,
+    mut hits: EventReader<HitAsteroid>,
+    mut points: EventWriter<AddPoints>,
+    mut destroyed: EventWriter<AsteroidDestroyed>,
+    mut cues: EventWriter<AudioCue>,
+    rigid_bodies: Res<RigidBodySet>,
+    power_up_settings: Res<PowerUpSettings>,
+    power_up_assets: Res<PowerUpAssets>,
+    mut rng: ResMut<RunRng>,
+    mut asteroid_query: Query<
+        (&Transform, &mut Asteroid, &RigidBodyHandleComponent),
+        With<Asteroid>,
+    >,
+) {
+    for hit in hits.iter() {
+        if let Ok((asteroid_transform, asteroid, rigid_body_component)) =
+            asteroid_query.get_mut(hit.asteroid)
+        {
+            let rigid_body = match rigid_bodies.get(rigid_body_component.handle()) {
+                Some(r) => r,
+                None => continue,
+            };
+
+            let mut asteroid: Mut<Asteroid> = asteroid;
+            let asteroid_transform: &Transform = asteroid_transform;
+            // If two bullets hit at the same time, only the first one destroys it
+            if asteroid.hits == 0 {
+                continue;
+            }
+            asteroid.hits = asteroid.hits.saturating_sub(hit.damage);
+            if asteroid.hits == 0 {
+                let linvel: Vec3 = rigid_body.linvel().clone_owned().into();
+                points.send(AddPoints {
+                    points: asteroid.points,
+                    player: hit.player,
+                });
+                destroyed.send(AsteroidDestroyed {
+                    position: asteroid_transform.translation,
+                    linvel,
+                });
+                cues.send(AudioCue::AsteroidSplit);
+                commands.entity(hit.asteroid).despawn_recursive();
+                powerups::maybe_drop(
+                    &mut commands,
+                    &power_up_settings,
+                    &power_up_assets,
+                    &mut rng.power_ups,
+                    asteroid_transform.translation,
+                    linvel,
+                );
+                for child in asteroid.children.iter() {
+                    commands.spawn_bundle(AsteroidBundle {
+                        collider_props: ColliderProps {
+                            linvel,
+                            angvel: rigid_body.angvel().clone_owned().into(),
+                        },
+                        ..AsteroidBundle::new(&child, *asteroid_transform)
+                    });
+                }
+            }
+        }
+    }
+}
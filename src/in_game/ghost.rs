@@ -0,0 +1,240 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::in_game::{HudText, TiedToGame};
+use crate::storage;
+
+use super::controls::Controllable;
+use super::points::{Points, PointsSystem};
+use super::run::{Run, RunKind, RunSetup};
+
+/// How often the ship is sampled, per second
+const SAMPLE_RATE: f32 = 20.;
+
+/// In ghost races, records the lead ship every tick and replays the best recorded run next to it,
+/// keeping the best run saved between games
+pub struct GhostPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for GhostPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Ghost>()
+            .add_system_set(
+                SystemSet::on_enter(self.0.clone()).with_system(setup.system().after(RunSetup)),
+            )
+            .add_system_set(
+                SystemSet::on_update(self.0.clone()).with_system(race.system().after(PointsSystem)),
+            )
+            // Only finished runs, so a race quit from the pause menu never becomes the best
+            .add_system_set(SystemSet::on_enter(crate::AppState::End).with_system(finish.system()));
+    }
+}
+
+/// Where the ship was at one tick, and the score by then
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+struct Sample {
+    position: [f32; 3],
+    rotation: [f32; 4],
+    score: u64,
+}
+
+/// A whole recorded run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Trajectory {
+    seed: u64,
+    score: u64,
+    samples: Vec<Sample>,
+}
+
+/// The best run so far, and the one being recorded
+pub struct Ghost {
+    best: Option<Trajectory>,
+    recording: Vec<Sample>,
+    /// Seconds since the run started, not counting pauses
+    elapsed: f32,
+}
+
+impl Ghost {
+    fn load() -> anyhow::Result<Option<Trajectory>> {
+        match storage::read("ghost")? {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save(trajectory: &Trajectory) -> anyhow::Result<()> {
+        storage::write("ghost", &serde_json::to_string(trajectory)?)
+    }
+
+    /// The seed the best run was played on, so a race can be played on it too
+    pub fn best_seed(&self) -> Option<u64> {
+        self.best.as_ref().map(|best| best.seed)
+    }
+}
+
+impl FromWorld for Ghost {
+    fn from_world(_world: &mut World) -> Self {
+        let best = Ghost::load().unwrap_or_else(|e| {
+            warn!("Couldn't load the best run: {:?}", e);
+            None
+        });
+        Ghost {
+            best,
+            recording: Vec::new(),
+            elapsed: 0.,
+        }
+    }
+}
+
+/// The translucent ship that replays the best run
+struct GhostShip;
+
+/// How far ahead of or behind the ghost the score is
+struct GhostLabel;
+
+fn setup(
+    mut commands: Commands,
+    run: Res<Run>,
+    mut ghost: ResMut<Ghost>,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    ghost.recording.clear();
+    ghost.elapsed = 0.;
+    if run.kind != RunKind::Ghost {
+        return;
+    }
+    if ghost.best.is_some() {
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: asset_server.load(asset!("ship.glb", "Mesh0/Primitive0")),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::rgba(0.5, 0.8, 1., 0.35),
+                    ..Default::default()
+                }),
+                transform: Transform::from_scale(Vec3::splat(0.1)),
+                visible: Visible {
+                    is_visible: false,
+                    is_transparent: true,
+                },
+                ..Default::default()
+            })
+            .insert(GhostShip)
+            .insert(TiedToGame);
+    }
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(65.0),
+                    left: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                if ghost.best.is_some() {
+                    "Ghost: 0"
+                } else {
+                    "No ghost yet, set a score to race"
+                },
+                TextStyle {
+                    font: asset_server.load(asset!("RobotoCondensed-Regular.ttf")),
+                    font_size: 30.0,
+                    color: Color::rgb(0.5, 0.8, 1.0),
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(GhostLabel)
+        .insert(HudText { font_size: 30. })
+        .insert(TiedToGame);
+}
+
+/// Record this tick, and move the ghost to where it was at the same time in the best run
+fn race(
+    time: Res<Time>,
+    run: Res<Run>,
+    points: Res<Points>,
+    mut ghost: ResMut<Ghost>,
+    ship: Query<&Transform, With<Controllable>>,
+    mut ghost_ship: Query<(&mut Transform, &mut Visible), (With<GhostShip>, Without<Controllable>)>,
+    mut label: Query<&mut Text, With<GhostLabel>>,
+) {
+    if run.kind != RunKind::Ghost {
+        return;
+    }
+    let ghost = &mut *ghost;
+    ghost.elapsed += time.delta_seconds();
+    let tick = ghost.elapsed * SAMPLE_RATE;
+    if let Ok(ship) = ship.single() {
+        while (ghost.recording.len() as f32) <= tick {
+            ghost.recording.push(Sample {
+                position: ship.translation.into(),
+                rotation: ship.rotation.into(),
+                score: points.total(),
+            });
+        }
+    }
+
+    let best = match &ghost.best {
+        Some(best) => best,
+        None => return,
+    };
+    let index = tick as usize;
+    if let Ok((mut transform, mut visible)) = ghost_ship.single_mut() {
+        match (best.samples.get(index), best.samples.get(index + 1)) {
+            (Some(from), Some(to)) => {
+                let alpha = tick.fract();
+                let rotation = |sample: &Sample| {
+                    let [x, y, z, w] = sample.rotation;
+                    Quat::from_xyzw(x, y, z, w)
+                };
+                transform.translation =
+                    Vec3::from(from.position).lerp(Vec3::from(to.position), alpha);
+                transform.rotation = rotation(from).slerp(rotation(to), alpha);
+                visible.is_visible = true;
+            }
+            // The best run was over by now
+            _ => visible.is_visible = false,
+        }
+    }
+    let ghost_score = best
+        .samples
+        .get(index)
+        .map_or(best.score, |sample| sample.score);
+    let difference = points.total() as i64 - ghost_score as i64;
+    if let Ok(mut text) = label.single_mut() {
+        text.sections[0].value = format!("Ghost: {:+}", difference);
+        text.sections[0].style.color = if difference >= 0 {
+            Color::rgb(0.3, 1., 0.4)
+        } else {
+            Color::rgb(1., 0.4, 0.3)
+        };
+    }
+}
+
+/// Keep the run if it beat the best one
+fn finish(run: Res<Run>, points: Res<Points>, mut ghost: ResMut<Ghost>) {
+    if run.kind != RunKind::Ghost {
+        return;
+    }
+    let score = points.total();
+    if ghost
+        .best
+        .as_ref()
+        .map_or(false, |best| best.score >= score)
+    {
+        return;
+    }
+    let trajectory = Trajectory {
+        seed: run.seed,
+        score,
+        samples: std::mem::take(&mut ghost.recording),
+    };
+    if let Err(e) = Ghost::save(&trajectory) {
+        error!("Couldn't save the best run: {:?}", e);
+    }
+    ghost.best = Some(trajectory);
+}
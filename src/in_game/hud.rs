@@ -0,0 +1,171 @@
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+use bevy::render::render_graph::base::camera::CAMERA_3D;
+use bevy_rapier3d::physics::RigidBodyHandleComponent;
+use bevy_rapier3d::rapier::dynamics::RigidBodySet;
+
+use crate::in_game::{HudElement, HudText, TiedToGame};
+
+use super::bullets;
+use super::controls::{Controllable, FlightAssist, MainView};
+
+pub struct HudPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for HudPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<HudAssets>()
+            .add_system_set(SystemSet::on_enter(self.0.clone()).with_system(setup.system()))
+            .add_system_set(SystemSet::on_update(self.0.clone()).with_system(update_hud.system()));
+    }
+}
+
+struct HudAssets {
+    crosshair: Handle<ColorMaterial>,
+    prograde: Handle<ColorMaterial>,
+}
+
+impl FromWorld for HudAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mut textures = world.get_resource_mut::<Assets<Texture>>().unwrap();
+        let crosshair = textures.add(crate::util::ring_texture(64, 0.8));
+        let prograde = textures.add(crate::util::ring_texture(64, 0.6));
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        HudAssets {
+            crosshair: materials.add(ColorMaterial::modulated_texture(
+                crosshair,
+                Color::rgba(1., 1., 1., 0.8),
+            )),
+            prograde: materials.add(ColorMaterial::modulated_texture(
+                prograde,
+                Color::rgba(0.3, 1., 0.4, 0.8),
+            )),
+        }
+    }
+}
+
+/// Where bullets will be a second after firing
+struct Crosshair;
+
+/// Where the ship is drifting
+struct Prograde;
+
+struct FlightLabel;
+
+fn spawn_marker(commands: &mut Commands, size: f32, material: Handle<ColorMaterial>) -> Entity {
+    commands
+        .spawn_bundle(ImageBundle {
+            style: Style {
+                size: Size::new(Val::Px(size), Val::Px(size)),
+                position_type: PositionType::Absolute,
+                display: Display::None,
+                ..Default::default()
+            },
+            material,
+            ..Default::default()
+        })
+        .insert(TiedToGame)
+        .insert(HudElement)
+        .id()
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>, assets: Res<HudAssets>) {
+    let crosshair = spawn_marker(&mut commands, 24., assets.crosshair.clone());
+    commands.entity(crosshair).insert(Crosshair);
+    let prograde = spawn_marker(&mut commands, 16., assets.prograde.clone());
+    commands.entity(prograde).insert(Prograde);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(45.0),
+                    right: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(asset!("RobotoCondensed-Regular.ttf")),
+                    font_size: 24.0,
+                    color: Color::rgb(0.3, 1., 0.4),
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(FlightLabel)
+        .insert(HudText { font_size: 24. })
+        .insert(TiedToGame);
+}
+
+/// Place `style` centered on `position` on screen, or hide it
+fn place(style: &mut Style, node: &Node, position: Option<Vec2>) {
+    match position {
+        Some(position) => {
+            style.display = Display::Flex;
+            style.position = Rect {
+                left: Val::Px(position.x - node.size.x / 2.),
+                bottom: Val::Px(position.y - node.size.y / 2.),
+                ..Default::default()
+            };
+        }
+        None => style.display = Display::None,
+    }
+}
+
+fn update_hud(
+    view: MainView,
+    rigid_bodies: Res<RigidBodySet>,
+    assist: Res<FlightAssist>,
+    ship: Query<(&GlobalTransform, &RigidBodyHandleComponent), With<Controllable>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut crosshair: Query<(&mut Style, &Node), (With<Crosshair>, Without<Prograde>)>,
+    mut prograde: Query<(&mut Style, &Node), (With<Prograde>, Without<Crosshair>)>,
+    mut label: Query<&mut Text, With<FlightLabel>>,
+) {
+    let camera = cameras
+        .iter()
+        .find(|(camera, _)| camera.name.as_deref() == Some(CAMERA_3D));
+    let ship = ship
+        .single()
+        .ok()
+        .and_then(|(transform, rb)| Some((transform, rigid_bodies.get(rb.handle())?)));
+    let (ship, rb, camera, camera_transform) = match (ship, camera) {
+        (Some((ship, rb)), Some((camera, camera_transform))) => {
+            (ship, rb, camera, camera_transform)
+        }
+        _ => return,
+    };
+    let to_screen = |position| view.world_to_screen(camera, camera_transform, position);
+    let linvel: Vec3 = rb.linvel().clone_owned().into();
+    let angvel: Vec3 = rb.angvel().clone_owned().into();
+
+    if let Ok((mut style, node)) = crosshair.single_mut() {
+        let muzzle = ship.translation + ship.rotation * bullets::MUZZLE_OFFSET;
+        let forward = ship.rotation * Vec3::Z;
+        place(
+            &mut style,
+            node,
+            to_screen(muzzle + forward * bullets::SPEED),
+        );
+    }
+    if let Ok((mut style, node)) = prograde.single_mut() {
+        // Far enough out that it lines up with where the ship is heading, not just next to it
+        let position = if linvel.length() > 0.1 {
+            to_screen(ship.translation + linvel.normalize() * 100.)
+        } else {
+            None
+        };
+        place(&mut style, node, position);
+    }
+    if let Ok(mut text) = label.single_mut() {
+        text.sections[0].value = format!(
+            "SPEED {:.1}\nTURN {:.0}°/s\nASSIST {}",
+            linvel.length(),
+            angvel.length().to_degrees(),
+            format!("{:?}", *assist).to_uppercase()
+        );
+    }
+}
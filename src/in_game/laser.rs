@@ -0,0 +1,295 @@
+use bevy::prelude::*;
+use bevy_rapier3d::rapier::geometry::{ColliderSet, Ray};
+use bevy_rapier3d::rapier::pipeline::QueryPipeline;
+
+use crate::in_game::{HudElement, TiedToGame};
+
+use super::asteroids::Asteroid;
+use super::bullets::MUZZLE_OFFSET;
+use super::controls::{Controllable, Player, ReadInput, ShipInput};
+use super::energy::Energy;
+use super::events::HitAsteroid;
+
+pub struct LaserPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for LaserPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<LaserSettings>().add_system_set(
+            SystemSet::on_update(self.0.clone())
+                .with_system(equip.system())
+                .with_system(fire.system().label(LaserFire).after(ReadInput)),
+        );
+    }
+}
+
+/// The beams, and the heat bar of the ship the HUD follows
+pub struct LaserHudPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for LaserHudPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<LaserAssets>()
+            .add_system_set(SystemSet::on_enter(self.0.clone()).with_system(setup.system()))
+            .add_system_set(
+                SystemSet::on_update(self.0.clone())
+                    .with_system(add_beams.system())
+                    .with_system(draw_beams.system().after(LaserFire))
+                    .with_system(update_hud.system()),
+            );
+    }
+}
+
+/// Range, damage and heat build-up of the laser
+pub struct LaserSettings {
+    pub range: f32,
+    /// Asteroid hits dealt per second of continuous contact
+    pub damage_per_second: f32,
+    /// Heat gained per second of firing. The laser overheats at 1.
+    pub heat_rate: f32,
+    pub cool_rate: f32,
+    /// Once overheated, the laser can't fire again until it cools down to this
+    pub cooled_down: f32,
+    /// Drawn from the energy shared with the boost
+    pub energy_per_second: f32,
+}
+
+impl Default for LaserSettings {
+    fn default() -> Self {
+        Self {
+            range: 120.,
+            damage_per_second: 2.,
+            heat_rate: 0.3,
+            cool_rate: 0.25,
+            cooled_down: 0.25,
+            energy_per_second: 15.,
+        }
+    }
+}
+
+#[derive(SystemLabel, Clone, Eq, PartialEq, Hash, Debug, Default)]
+struct LaserFire;
+
+pub struct LaserAssets {
+    pub(super) mesh: Handle<Mesh>,
+    pub(super) material: Handle<StandardMaterial>,
+}
+
+impl FromWorld for LaserAssets {
+    fn from_world(world: &mut World) -> Self {
+        LaserAssets {
+            // One unit long along z, so it can be scaled to the length of the beam
+            mesh: world
+                .get_resource_mut::<Assets<Mesh>>()
+                .unwrap()
+                .add(Mesh::from(shape::Box {
+                    min_x: -0.08,
+                    max_x: 0.08,
+                    min_y: -0.08,
+                    max_y: 0.08,
+                    min_z: 0.,
+                    max_z: 1.,
+                })),
+            material: world
+                .get_resource_mut::<Assets<StandardMaterial>>()
+                .unwrap()
+                .add(StandardMaterial {
+                    base_color: Color::rgb(0.3, 0.8, 1.),
+                    emissive: Color::rgb(0.3, 0.8, 1.),
+                    unlit: true,
+                    ..Default::default()
+                }),
+        }
+    }
+}
+
+/// Each ship's laser
+#[derive(Default)]
+pub struct Laser {
+    pub heat: f32,
+    pub overheated: bool,
+    target: Option<Entity>,
+    /// Damage dealt to `target` that hasn't added up to a full hit yet
+    damage: f32,
+    /// How far the beam reaches, while it's firing
+    pub beam: Option<f32>,
+}
+
+/// The beam drawn for the laser of this ship
+struct Beam(Entity);
+
+struct HeatBar;
+
+fn setup(mut commands: Commands, mut color_materials: ResMut<Assets<ColorMaterial>>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(200.), Val::Px(10.)),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(10.),
+                    left: Val::Percent(50.),
+                    ..Default::default()
+                },
+                margin: Rect {
+                    left: Val::Px(-100.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            material: color_materials.add(Color::rgba(0., 0., 0., 0.6).into()),
+            ..Default::default()
+        })
+        .insert(TiedToGame)
+        .insert(HudElement)
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(0.), Val::Percent(100.)),
+                        ..Default::default()
+                    },
+                    material: color_materials.add(Color::WHITE.into()),
+                    ..Default::default()
+                })
+                .insert(HeatBar);
+        });
+}
+
+/// Give every ship a laser
+fn equip(mut commands: Commands, ships: Query<Entity, (With<Player>, Without<Laser>)>) {
+    for ship in ships.iter() {
+        commands.entity(ship).insert(Laser::default());
+    }
+}
+
+/// Give every ship's laser a beam to draw it with
+fn add_beams(mut commands: Commands, assets: Res<LaserAssets>, ships: Query<Entity, Added<Laser>>) {
+    for ship in ships.iter() {
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: assets.mesh.clone(),
+                material: assets.material.clone(),
+                visible: Visible {
+                    is_visible: false,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Beam(ship))
+            .insert(TiedToGame);
+    }
+}
+
+fn fire(
+    time: Res<Time>,
+    settings: Res<LaserSettings>,
+    query_pipeline: Res<QueryPipeline>,
+    colliders: Res<ColliderSet>,
+    mut hits: EventWriter<HitAsteroid>,
+    mut ships: Query<(&Transform, &Player, &ShipInput, &mut Laser, &mut Energy)>,
+    asteroids: Query<(), With<Asteroid>>,
+) {
+    let delta = time.delta_seconds();
+    for (ship, player, input, mut laser, mut energy) in ships.iter_mut() {
+        if laser.overheated && laser.heat <= settings.cooled_down {
+            laser.overheated = false;
+        }
+        let firing =
+            input.laser && !laser.overheated && energy.drain(settings.energy_per_second * delta);
+        if firing {
+            laser.heat += settings.heat_rate * delta;
+            if laser.heat >= 1. {
+                laser.heat = 1.;
+                laser.overheated = true;
+            }
+        } else {
+            laser.heat = (laser.heat - settings.cool_rate * delta).max(0.);
+            laser.target = None;
+            laser.beam = None;
+            continue;
+        }
+
+        let origin = ship.translation + ship.rotation * MUZZLE_OFFSET;
+        let direction = ship.local_z();
+        let hit = query_pipeline.cast_ray_and_get_normal(
+            &colliders,
+            &Ray::new(origin.into(), direction.into()),
+            settings.range,
+            true,
+            Default::default(),
+            Some(&|_, c| asteroids.get(Entity::from_bits(c.user_data as u64)).is_ok()),
+        );
+        let length = match hit {
+            Some((handle, intersection)) => {
+                let target = colliders
+                    .get(handle)
+                    .map(|c| Entity::from_bits(c.user_data as u64));
+                if target != laser.target {
+                    laser.target = target;
+                    laser.damage = 0.;
+                }
+                laser.damage += settings.damage_per_second * delta;
+                if laser.damage >= 1. {
+                    if let Some(asteroid) = target {
+                        let damage = laser.damage.floor();
+                        laser.damage -= damage;
+                        hits.send(HitAsteroid {
+                            asteroid,
+                            damage: damage as u8,
+                            player: player.0,
+                        });
+                    }
+                }
+                intersection.toi
+            }
+            None => {
+                laser.target = None;
+                settings.range
+            }
+        };
+        laser.beam = Some(length);
+    }
+}
+
+/// Stretch each beam out of its ship's nose while it's firing, and clear up the beams of
+/// destroyed ships
+fn draw_beams(
+    mut commands: Commands,
+    ships: Query<(&Transform, &Laser), Without<Beam>>,
+    mut beams: Query<(Entity, &Beam, &mut Transform, &mut Visible)>,
+) {
+    for (entity, beam, mut transform, mut visible) in beams.iter_mut() {
+        let (ship, laser) = match ships.get(beam.0) {
+            Ok(ship) => ship,
+            Err(_) => {
+                commands.entity(entity).despawn_recursive();
+                continue;
+            }
+        };
+        match laser.beam {
+            Some(length) => {
+                visible.is_visible = true;
+                *transform = beam_transform(ship, length);
+            }
+            None => visible.is_visible = false,
+        }
+    }
+}
+
+/// Where a beam `length` long out of `ship`'s nose is drawn
+pub fn beam_transform(ship: &Transform, length: f32) -> Transform {
+    Transform {
+        translation: ship.translation + ship.rotation * MUZZLE_OFFSET,
+        rotation: ship.rotation,
+        scale: Vec3::new(1., 1., length),
+    }
+}
+
+/// The bar shows the heat of the laser on the ship the HUD follows
+fn update_hud(laser: Query<&Laser, With<Controllable>>, mut bar: Query<&mut Style, With<HeatBar>>) {
+    if let (Ok(laser), Ok(mut style)) = (laser.single(), bar.single_mut()) {
+        let width = Val::Percent(laser.heat * 100.);
+        if style.size.width != width {
+            style.size.width = width;
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+use bevy::render::render_graph::base::camera::CAMERA_3D;
+use bevy_rapier3d::physics::RigidBodyHandleComponent;
+use bevy_rapier3d::rapier::dynamics::{RigidBodyBuilder, RigidBodySet};
+use bevy_rapier3d::rapier::geometry::{Ball, ColliderBuilder, ColliderSet};
+use bevy_rapier3d::rapier::math::Vector;
+use bevy_rapier3d::rapier::pipeline::QueryPipeline;
+
+use crate::in_game::{HudElement, HudText, TiedToGame};
+use crate::util::DespawnTimer;
+
+use super::asteroids::Asteroid;
+use super::bullets::FiredBy;
+use super::controls::{Controllable, MainView, Player, ReadInput, ShipInput};
+use super::run::{Run, StartingWeapon};
+
+pub struct MissilesPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for MissilesPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<MissileSettings>()
+            .init_resource::<MissileAssets>()
+            .add_system_set(
+                SystemSet::on_update(self.0.clone())
+                    .with_system(equip.system())
+                    .with_system(lock_on.system())
+                    .with_system(refill.system())
+                    .with_system(fire.system().after(ReadInput))
+                    .with_system(guide.system()),
+            );
+    }
+}
+
+/// The ammo and lock-on reticle of the ship the HUD follows
+pub struct MissilesHudPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for MissilesHudPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ReticleAssets>()
+            .add_system_set(SystemSet::on_enter(self.0.clone()).with_system(setup.system()))
+            .add_system_set(SystemSet::on_update(self.0.clone()).with_system(update_hud.system()));
+    }
+}
+
+/// Lock-on, guidance and ammo for homing missiles
+pub struct MissileSettings {
+    pub lock_range: f32,
+    /// Half-angle of the lock-on cone around the ship's nose, in radians
+    pub lock_cone: f32,
+    pub max_ammo: u8,
+    /// Seconds to refill one missile
+    pub refill_time: f32,
+    pub speed: f32,
+    pub max_force: f32,
+    pub lifetime: f32,
+    /// Asteroid hits dealt by a single missile
+    pub damage: u8,
+}
+
+impl Default for MissileSettings {
+    fn default() -> Self {
+        Self {
+            lock_range: 150.,
+            lock_cone: 0.35,
+            max_ammo: 4,
+            refill_time: 5.,
+            speed: 30.,
+            max_force: 60.,
+            lifetime: 8.,
+            damage: 3,
+        }
+    }
+}
+
+pub struct MissileAssets {
+    pub(super) mesh: Handle<Mesh>,
+    pub(super) material: Handle<StandardMaterial>,
+}
+
+impl FromWorld for MissileAssets {
+    fn from_world(world: &mut World) -> Self {
+        MissileAssets {
+            mesh: world
+                .get_resource_mut::<Assets<Mesh>>()
+                .unwrap()
+                .add(Mesh::from(shape::Capsule {
+                    radius: 0.3,
+                    depth: 1.5,
+                    ..Default::default()
+                })),
+            material: world
+                .get_resource_mut::<Assets<StandardMaterial>>()
+                .unwrap()
+                .add(StandardMaterial {
+                    base_color: Color::rgb(1., 0.6, 0.),
+                    emissive: Color::rgb(0.8, 0.4, 0.),
+                    ..Default::default()
+                }),
+        }
+    }
+}
+
+struct ReticleAssets {
+    material: Handle<ColorMaterial>,
+}
+
+impl FromWorld for ReticleAssets {
+    fn from_world(world: &mut World) -> Self {
+        let texture = world
+            .get_resource_mut::<Assets<Texture>>()
+            .unwrap()
+            .add(crate::util::ring_texture(64, 0.85));
+        ReticleAssets {
+            material: world
+                .get_resource_mut::<Assets<ColorMaterial>>()
+                .unwrap()
+                .add(ColorMaterial::modulated_texture(
+                    texture,
+                    Color::rgb(1., 0.3, 0.2),
+                )),
+        }
+    }
+}
+
+/// Missiles left on a ship
+pub struct MissileAmmo {
+    pub count: u8,
+    refill: Timer,
+}
+
+/// The asteroid that a ship's missiles will home in on
+#[derive(Default)]
+pub struct LockTarget(pub Option<Entity>);
+
+pub struct Missile {
+    target: Option<Entity>,
+}
+
+struct Reticle;
+
+struct AmmoLabel;
+
+/// Every ship starts with a full rack, or two with [`StartingWeapon::Missiles`]
+fn equip(
+    mut commands: Commands,
+    settings: Res<MissileSettings>,
+    run: Res<Run>,
+    ships: Query<Entity, (With<Player>, Without<MissileAmmo>)>,
+) {
+    for ship in ships.iter() {
+        let count = if run.weapon == StartingWeapon::Missiles {
+            settings.max_ammo * 2
+        } else {
+            settings.max_ammo
+        };
+        commands
+            .entity(ship)
+            .insert(MissileAmmo {
+                count,
+                refill: Timer::from_seconds(settings.refill_time, true),
+            })
+            .insert(LockTarget::default());
+    }
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>, assets: Res<ReticleAssets>) {
+    commands
+        .spawn_bundle(ImageBundle {
+            style: Style {
+                size: Size::new(Val::Px(48.), Val::Px(48.)),
+                position_type: PositionType::Absolute,
+                display: Display::None,
+                ..Default::default()
+            },
+            material: assets.material.clone(),
+            ..Default::default()
+        })
+        .insert(Reticle)
+        .insert(HudElement)
+        .insert(TiedToGame);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(10.0),
+                    right: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(asset!("RobotoCondensed-Regular.ttf")),
+                    font_size: 30.0,
+                    color: Color::rgb(1., 0.6, 0.),
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(AmmoLabel)
+        .insert(HudText { font_size: 30. })
+        .insert(TiedToGame);
+}
+
+/// Lock each ship on to the asteroid closest to its nose, if any are within the cone
+fn lock_on(
+    settings: Res<MissileSettings>,
+    query_pipeline: Res<QueryPipeline>,
+    colliders: Res<ColliderSet>,
+    mut ships: Query<(&Transform, &mut LockTarget)>,
+    asteroids: Query<&Transform, With<Asteroid>>,
+) {
+    for (ship, mut lock) in ships.iter_mut() {
+        let mut best: Option<(Entity, f32)> = None;
+        query_pipeline.intersections_with_shape(
+            &colliders,
+            &crate::util::nalgebra_pos(ship.translation, Quat::IDENTITY),
+            &Ball::new(settings.lock_range),
+            Default::default(),
+            Some(&|_, c| asteroids.get(Entity::from_bits(c.user_data as u64)).is_ok()),
+            |_, c| {
+                let entity = Entity::from_bits(c.user_data as u64);
+                if let Ok(asteroid) = asteroids.get(entity) {
+                    let angle = ship
+                        .local_z()
+                        .angle_between(asteroid.translation - ship.translation);
+                    if angle < settings.lock_cone && best.map_or(true, |(_, best)| angle < best) {
+                        best = Some((entity, angle));
+                    }
+                }
+                true
+            },
+        );
+        let target = best.map(|(entity, _)| entity);
+        if lock.0 != target {
+            lock.0 = target;
+        }
+    }
+}
+
+fn refill(time: Res<Time>, settings: Res<MissileSettings>, mut query: Query<&mut MissileAmmo>) {
+    for mut ammo in query.iter_mut() {
+        if ammo.count >= settings.max_ammo {
+            ammo.refill.reset();
+        } else if ammo.refill.tick(time.delta()).just_finished() {
+            ammo.count += 1;
+        }
+    }
+}
+
+fn fire(
+    mut commands: Commands,
+    settings: Res<MissileSettings>,
+    assets: Res<MissileAssets>,
+    mut query: Query<(
+        &Transform,
+        &Player,
+        &ShipInput,
+        &LockTarget,
+        &mut MissileAmmo,
+    )>,
+) {
+    for (ship, player, input, lock, mut ammo) in query.iter_mut() {
+        if !input.missile.just_pressed || ammo.count == 0 {
+            continue;
+        }
+        ammo.count -= 1;
+        let translation = ship.translation + ship.rotation * Vec3::new(0., -1., 3.);
+        let rotation = ship.rotation * Quat::from_rotation_x(std::f32::consts::FRAC_PI_2);
+        let linvel = ship.local_z() * settings.speed * 0.5;
+        commands
+            .spawn_bundle(PbrBundle {
+                mesh: assets.mesh.clone(),
+                material: assets.material.clone(),
+                transform: Transform {
+                    translation,
+                    rotation,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Missile { target: lock.0 })
+            .insert(super::Bullet)
+            .insert(FiredBy(player.0))
+            .insert(super::Damage(settings.damage))
+            .insert(TiedToGame)
+            .insert(
+                RigidBodyBuilder::new_dynamic()
+                    .position(crate::util::nalgebra_pos(translation, rotation))
+                    .linvel(linvel.x, linvel.y, linvel.z),
+            )
+            .insert(ColliderBuilder::capsule_y(0.75, 0.3))
+            .insert(DespawnTimer(Timer::from_seconds(settings.lifetime, false)));
+    }
+}
+
+/// Steer missiles towards their targets and point them where they're going
+fn guide(
+    settings: Res<MissileSettings>,
+    mut rigid_bodies: ResMut<RigidBodySet>,
+    mut missiles: Query<(&Transform, &mut Missile, &RigidBodyHandleComponent)>,
+    targets: Query<&Transform, With<Asteroid>>,
+) {
+    for (transform, mut missile, rigid_body_component) in missiles.iter_mut() {
+        let rb = match rigid_bodies.get_mut(rigid_body_component.handle()) {
+            Some(rb) => rb,
+            None => continue,
+        };
+        let linvel: Vec3 = rb.linvel().clone_owned().into();
+        let target = missile.target.and_then(|target| targets.get(target).ok());
+        if target.is_none() {
+            missile.target = None;
+        }
+        // Without a target, keep accelerating along the current heading
+        let direction = target.map_or_else(
+            || linvel.normalize_or_zero(),
+            |target| (target.translation - transform.translation).normalize_or_zero(),
+        );
+        let force = (direction * settings.speed - linvel).clamp_length_max(settings.max_force);
+        rb.apply_force(Vector::from(force), true);
+
+        if linvel.length() > 0.1 {
+            let rotation = Quat::from_rotation_arc(Vec3::Y, linvel.normalize());
+            rb.set_position(
+                crate::util::nalgebra_pos(transform.translation, rotation),
+                true,
+            );
+            rb.set_angvel(Vector::zeros(), true);
+        }
+    }
+}
+
+/// Show the ammo and lock of the ship the HUD follows
+fn update_hud(
+    view: MainView,
+    settings: Res<MissileSettings>,
+    ship: Query<(&MissileAmmo, &LockTarget), With<Controllable>>,
+    targets: Query<&GlobalTransform, With<Asteroid>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut reticle: Query<(&mut Style, &Node), With<Reticle>>,
+    mut label: Query<&mut Text, With<AmmoLabel>>,
+) {
+    let (ammo, lock) = match ship.single() {
+        Ok((ammo, lock)) => (Some(ammo), lock.0),
+        Err(_) => (None, None),
+    };
+    if let (Some(ammo), Ok(mut text)) = (ammo, label.single_mut()) {
+        text.sections[0].value = format!("MISSILES {}/{}", ammo.count, settings.max_ammo);
+    }
+    if let Ok((mut style, node)) = reticle.single_mut() {
+        let position = lock
+            .and_then(|target| targets.get(target).ok())
+            .and_then(|target| {
+                let (camera, camera_transform) = cameras
+                    .iter()
+                    .find(|(camera, _)| camera.name.as_deref() == Some(CAMERA_3D))?;
+                view.world_to_screen(camera, camera_transform, target.translation)
+            });
+        match position {
+            Some(position) => {
+                style.display = Display::Flex;
+                style.position = Rect {
+                    left: Val::Px(position.x - node.size.x / 2.),
+                    bottom: Val::Px(position.y - node.size.y / 2.),
+                    ..Default::default()
+                };
+            }
+            None => style.display = Display::None,
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use rand::prelude::*;
+
+use crate::in_game::TiedToGame;
+
+use super::asteroids::Asteroid;
+use super::boost::Boost;
+use super::controls::{Controllable, Player};
+use super::enemies::{Enemy, EnemyBullet};
+use super::events::{AsteroidDestroyed, Contact, EventAdapter};
+use super::game_area::GameAreaBound;
+
+pub struct ParticlesPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for ParticlesPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ParticleSettings>()
+            .init_resource::<ParticleAssets>()
+            .add_system_set(
+                SystemSet::on_update(self.0.clone())
+                    .with_system(equip.system())
+                    .with_system(simulate.system())
+                    .with_system(thrust.system())
+                    .with_system(impacts.system().after(EventAdapter))
+                    .with_system(debris.system()),
+            );
+    }
+}
+
+pub struct ParticleSettings {
+    /// Thrust puffs per second at full thrust
+    pub thrust_rate: f32,
+    pub thrust_speed: f32,
+    pub impact_count: usize,
+    pub impact_speed: f32,
+    pub debris_count: usize,
+    /// Random speed added to debris on top of the asteroid's velocity
+    pub debris_speed: f32,
+}
+
+impl Default for ParticleSettings {
+    fn default() -> Self {
+        Self {
+            thrust_rate: 40.,
+            thrust_speed: 12.,
+            impact_count: 6,
+            impact_speed: 10.,
+            debris_count: 24,
+            debris_speed: 8.,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum ParticleKind {
+    Thrust,
+    Boost,
+    Spark,
+    Debris,
+    Fire,
+}
+
+impl ParticleKind {
+    /// Size, fraction of velocity lost per second, and lifetime
+    fn look(self) -> (f32, f32, f32) {
+        match self {
+            ParticleKind::Thrust => (0.25, 2., 0.4),
+            ParticleKind::Boost => (0.4, 2., 0.4),
+            ParticleKind::Spark => (0.12, 4., 0.3),
+            ParticleKind::Debris => (0.4, 0.3, 1.5),
+            ParticleKind::Fire => (0.8, 1.5, 0.75),
+        }
+    }
+}
+
+pub struct ParticleAssets {
+    mesh: Handle<Mesh>,
+    thrust: Handle<StandardMaterial>,
+    boost: Handle<StandardMaterial>,
+    spark: Handle<StandardMaterial>,
+    debris: Handle<StandardMaterial>,
+    fire: Handle<StandardMaterial>,
+}
+
+impl FromWorld for ParticleAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mesh = world
+            .get_resource_mut::<Assets<Mesh>>()
+            .unwrap()
+            .add(Mesh::from(shape::Icosphere {
+                radius: 1.,
+                subdivisions: 0,
+            }));
+        let mut materials = world
+            .get_resource_mut::<Assets<StandardMaterial>>()
+            .unwrap();
+        let mut glowing = |color: Color| {
+            materials.add(StandardMaterial {
+                base_color: color,
+                emissive: color,
+                unlit: true,
+                ..Default::default()
+            })
+        };
+        ParticleAssets {
+            mesh,
+            thrust: glowing(Color::rgb(0.4, 0.7, 1.)),
+            boost: glowing(Color::rgb(1., 0.6, 0.2)),
+            spark: glowing(Color::rgb(1., 0.95, 0.6)),
+            fire: glowing(Color::rgb(1., 0.4, 0.1)),
+            debris: materials.add(StandardMaterial {
+                base_color: Color::rgb(0.35, 0.3, 0.25),
+                ..Default::default()
+            }),
+        }
+    }
+}
+
+/// Force a ship's engines are putting out this frame, in world space. Set by `player_move`.
+#[derive(Default)]
+pub struct Thrust {
+    pub force: Vec3,
+    /// Puffs due but not emitted yet, as there's less than one a frame
+    owed: f32,
+}
+
+/// A short-lived speck that drifts, slows down and shrinks away
+struct Particle {
+    velocity: Vec3,
+    /// Fraction of velocity lost per second
+    drag: f32,
+    size: f32,
+    lifetime: Timer,
+}
+
+/// Spawns particles
+#[derive(SystemParam)]
+pub struct Particles<'a> {
+    commands: Commands<'a>,
+    assets: Res<'a, ParticleAssets>,
+}
+
+impl<'a> Particles<'a> {
+    pub fn emit(&mut self, kind: ParticleKind, position: Vec3, velocity: Vec3) {
+        let mut rng = rand::thread_rng();
+        let (size, drag, lifetime) = kind.look();
+        let size = size * rng.gen_range(0.5..1.5);
+        let material = match kind {
+            ParticleKind::Thrust => &self.assets.thrust,
+            ParticleKind::Boost => &self.assets.boost,
+            ParticleKind::Spark => &self.assets.spark,
+            ParticleKind::Debris => &self.assets.debris,
+            ParticleKind::Fire => &self.assets.fire,
+        };
+        self.commands
+            .spawn_bundle(PbrBundle {
+                mesh: self.assets.mesh.clone(),
+                material: material.clone(),
+                transform: Transform {
+                    translation: position,
+                    scale: Vec3::splat(size),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(Particle {
+                velocity,
+                drag,
+                size,
+                lifetime: Timer::from_seconds(lifetime * rng.gen_range(0.6..1.), false),
+            })
+            .insert(TiedToGame);
+    }
+
+    /// Emit `count` particles flying out of `position` in random directions, on top of `velocity`
+    pub fn burst(
+        &mut self,
+        kind: ParticleKind,
+        count: usize,
+        position: Vec3,
+        velocity: Vec3,
+        speed: f32,
+    ) {
+        let mut rng = rand::thread_rng();
+        for _ in 0..count {
+            let direction = random_direction(&mut rng);
+            self.emit(
+                kind,
+                position,
+                velocity + direction * speed * rng.gen_range(0.3..1.),
+            );
+        }
+    }
+}
+
+fn random_direction(rng: &mut impl Rng) -> Vec3 {
+    Vec3::new(
+        rng.gen_range(-1.0..1.),
+        rng.gen_range(-1.0..1.),
+        rng.gen_range(-1.0..1.),
+    )
+    .normalize_or_zero()
+}
+
+/// Every ship gets exhaust
+fn equip(mut commands: Commands, ships: Query<Entity, (With<Player>, Without<Thrust>)>) {
+    for ship in ships.iter() {
+        commands.entity(ship).insert(Thrust::default());
+    }
+}
+
+fn simulate(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut Transform, &mut Particle)>,
+) {
+    let delta = time.delta_seconds();
+    for (entity, mut transform, mut particle) in query.iter_mut() {
+        if particle.lifetime.tick(time.delta()).finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        transform.translation += particle.velocity * delta;
+        let drag = (1. - particle.drag * delta).max(0.);
+        particle.velocity *= drag;
+        transform.scale = Vec3::splat(particle.size * (1. - particle.lifetime.percent()));
+    }
+}
+
+/// Puff exhaust out of the opposite side of each ship to where it's thrusting
+fn thrust(
+    mut particles: Particles,
+    time: Res<Time>,
+    settings: Res<ParticleSettings>,
+    mut ships: Query<(&Transform, &mut Thrust, Option<&Boost>)>,
+) {
+    let mut rng = rand::thread_rng();
+    for (ship, mut thrust, boost) in ships.iter_mut() {
+        let amount = thrust.force.length().min(1.);
+        let (kind, rate) = if boost.map_or(false, |boost| boost.active) {
+            (ParticleKind::Boost, settings.thrust_rate * 2.)
+        } else {
+            (ParticleKind::Thrust, settings.thrust_rate * amount)
+        };
+        thrust.owed += rate * time.delta_seconds();
+        if thrust.owed < 1. {
+            continue;
+        }
+        // Boosting without steering still pushes out the back
+        let direction = if amount > 0.01 {
+            thrust.force.normalize()
+        } else {
+            ship.local_z()
+        };
+        while thrust.owed >= 1. {
+            thrust.owed -= 1.;
+            let jitter = random_direction(&mut rng) * 0.3;
+            particles.emit(
+                kind,
+                ship.translation - direction * 3. + jitter,
+                (-direction + jitter) * settings.thrust_speed,
+            );
+        }
+    }
+}
+
+/// Sparks where bullets hit something solid
+fn impacts(
+    mut particles: Particles,
+    settings: Res<ParticleSettings>,
+    mut events: EventReader<Contact>,
+    bullets: Query<&Transform, Or<(With<super::Bullet>, With<EnemyBullet>)>>,
+    solids: Query<
+        (),
+        Or<(
+            With<Asteroid>,
+            With<Enemy>,
+            With<GameAreaBound>,
+            With<Controllable>,
+        )>,
+    >,
+) {
+    for event in events.iter() {
+        if let Contact::Started(a, b) = *event {
+            for (bullet, other) in [(a, b), (b, a)].iter().copied() {
+                if let (Ok(transform), Ok(_)) = (bullets.get(bullet), solids.get(other)) {
+                    particles.burst(
+                        ParticleKind::Spark,
+                        settings.impact_count,
+                        transform.translation,
+                        Vec3::ZERO,
+                        settings.impact_speed,
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Rocks and fire flying out of destroyed asteroids, carried along with the asteroid's velocity
+fn debris(
+    mut particles: Particles,
+    settings: Res<ParticleSettings>,
+    mut events: EventReader<AsteroidDestroyed>,
+) {
+    for event in events.iter() {
+        particles.burst(
+            ParticleKind::Debris,
+            settings.debris_count,
+            event.position,
+            event.linvel,
+            settings.debris_speed,
+        );
+        particles.burst(
+            ParticleKind::Fire,
+            settings.debris_count / 2,
+            event.position,
+            event.linvel,
+            settings.debris_speed * 0.5,
+        );
+    }
+}
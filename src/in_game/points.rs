@@ -1,13 +1,19 @@
 use bevy::prelude::*;
 
+use crate::audio::AudioCue;
+use crate::in_game::HudText;
+use crate::players::{PlayerDevices, MAX_PLAYERS};
+
+use super::controls::Player;
+use super::powerups::{self, ActivePowerUp, PowerUpKind, PowerUpSettings};
+
 pub struct PointsPlugin<T>(pub T);
 
 impl<T: crate::util::StateType> Plugin for PointsPlugin<T> {
     fn build(&self, app: &mut AppBuilder) {
         app.init_resource::<Points>()
             .add_event::<AddPoints>()
-            .add_system_set(SystemSet::on_enter(self.0.clone()).with_system(setup.system()))
-            .add_system_set(SystemSet::on_exit(self.0.clone()).with_system(leave.system()))
+            .add_system_set(SystemSet::on_enter(self.0.clone()).with_system(reset.system()))
             .add_system_set(
                 SystemSet::on_update(self.0.clone())
                     .with_system(update_points.system().label(PointsSystem)),
@@ -15,26 +21,69 @@ impl<T: crate::util::StateType> Plugin for PointsPlugin<T> {
     }
 }
 
+/// The score at the top left
+pub struct PointsHudPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for PointsHudPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(SystemSet::on_enter(self.0.clone()).with_system(setup.system()))
+            .add_system_set(SystemSet::on_exit(self.0.clone()).with_system(leave.system()))
+            .add_system_set(
+                SystemSet::on_update(self.0.clone())
+                    .with_system(update_label.system().after(PointsSystem)),
+            );
+    }
+}
+
 struct PartOfUi;
 
 struct ScoreLabel;
 
+/// Each player's score
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
-pub struct Points(pub u64);
+pub struct Points {
+    pub players: [u64; MAX_PLAYERS],
+}
 
+impl Points {
+    pub fn total(&self) -> u64 {
+        self.players.iter().sum()
+    }
+
+    /// The team's score, and in co-op how much each player scored
+    pub fn label(&self, players: usize) -> String {
+        if players > 1 {
+            let each: Vec<_> = self.players[..players]
+                .iter()
+                .enumerate()
+                .map(|(player, points)| format!("P{} {}", player + 1, points))
+                .collect();
+            format!("Score: {} ({})", self.total(), each.join(" / "))
+        } else {
+            format!("Score: {}", self.total())
+        }
+    }
+}
+
+/// Points scored by one player
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
-pub struct AddPoints(pub u64);
+pub struct AddPoints {
+    pub points: u64,
+    pub player: usize,
+}
 
 #[derive(SystemLabel, Clone, Eq, PartialEq, Hash, Debug, Default)]
 pub struct PointsSystem;
 
+fn reset(mut points: ResMut<Points>) {
+    *points = Points::default();
+}
+
 fn setup(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<ColorMaterial>>,
-    mut points: ResMut<Points>,
 ) {
-    points.0 = 0;
     commands
         .spawn_bundle(NodeBundle {
             style: Style {
@@ -64,7 +113,8 @@ fn setup(
                     ),
                     ..Default::default()
                 })
-                .insert(ScoreLabel);
+                .insert(ScoreLabel)
+                .insert(HudText { font_size: 40. });
         })
         .insert(PartOfUi);
 }
@@ -76,10 +126,37 @@ fn leave(mut commands: Commands, query: Query<Entity, With<PartOfUi>>) {
 fn update_points(
     mut points: ResMut<Points>,
     mut e: EventReader<AddPoints>,
+    mut cues: EventWriter<AudioCue>,
+    power_up_settings: Res<PowerUpSettings>,
+    active_power_ups: Query<&ActivePowerUp>,
+    ships: Query<(Entity, &Player)>,
+) {
+    let mut added = false;
+    for add in e.iter() {
+        // Only the player whose ship picked up the multiplier scores double
+        let multiplied = ships.iter().any(|(ship, player)| {
+            player.0 == add.player
+                && powerups::is_active(&active_power_ups, PowerUpKind::ScoreMultiplier, ship)
+        });
+        let multiplier = if multiplied {
+            power_up_settings.score_multiplier
+        } else {
+            1
+        };
+        points.players[add.player] += add.points * multiplier;
+        added |= add.points > 0;
+    }
+    if added {
+        cues.send(AudioCue::Points);
+    }
+}
+
+fn update_label(
+    points: Res<Points>,
+    devices: Res<PlayerDevices>,
     mut query: Query<&mut Text, With<ScoreLabel>>,
 ) {
-    points.0 += e.iter().map(|p| p.0).sum::<u64>();
     if let Ok(mut text) = query.single_mut() {
-        text.sections[0].value = format!("Score: {}", points.0);
+        text.sections[0].value = points.label(devices.count());
     }
 }
@@ -0,0 +1,384 @@
+use bevy::prelude::*;
+use bevy_rapier3d::rapier::dynamics::{IntegrationParameters, RigidBodyBuilder};
+use bevy_rapier3d::rapier::geometry::ColliderBuilder;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::in_game::{HudText, TiedToGame};
+
+use super::controls::{Controllable, Player};
+use super::events::{Contact, EventAdapter};
+use super::run::{Run, RunSetup, StartingWeapon, OPENING_RAPID_FIRE};
+
+pub struct PowerUpsPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for PowerUpsPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<PowerUpSettings>()
+            .init_resource::<PowerUpAssets>()
+            .add_system_set(
+                SystemSet::on_enter(self.0.clone()).with_system(setup.system().after(RunSetup)),
+            )
+            .add_system_set(SystemSet::on_exit(self.0.clone()).with_system(leave.system()))
+            .add_system_set(
+                SystemSet::on_update(self.0.clone())
+                    .with_system(expire.system())
+                    .with_system(time_slow.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(self.0.clone())
+                    .after(EventAdapter)
+                    .with_system(pick_up.system()),
+            );
+    }
+}
+
+/// What's going on the ship the HUD follows
+pub struct PowerUpsHudPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for PowerUpsHudPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.add_system_set(SystemSet::on_enter(self.0.clone()).with_system(setup_hud.system()))
+            .add_system_set(SystemSet::on_update(self.0.clone()).with_system(update_hud.system()));
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum PowerUpKind {
+    RapidFire,
+    ShieldRecharge,
+    ScoreMultiplier,
+    TimeSlow,
+}
+
+impl PowerUpKind {
+    pub const ALL: [PowerUpKind; 4] = [
+        PowerUpKind::RapidFire,
+        PowerUpKind::ShieldRecharge,
+        PowerUpKind::ScoreMultiplier,
+        PowerUpKind::TimeSlow,
+    ];
+
+    fn label(self, settings: &PowerUpSettings) -> String {
+        match self {
+            PowerUpKind::RapidFire => "RAPID FIRE".to_string(),
+            PowerUpKind::ShieldRecharge => "SHIELD".to_string(),
+            PowerUpKind::ScoreMultiplier => format!("SCORE x{}", settings.score_multiplier),
+            PowerUpKind::TimeSlow => "TIME SLOW".to_string(),
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            PowerUpKind::RapidFire => Color::rgb(1., 0.5, 0.),
+            PowerUpKind::ShieldRecharge => Color::rgb(0.2, 0.6, 1.),
+            PowerUpKind::ScoreMultiplier => Color::rgb(1., 0.85, 0.),
+            PowerUpKind::TimeSlow => Color::rgb(0.7, 0.3, 1.),
+        }
+    }
+}
+
+/// Drop rates and strengths of power-ups
+pub struct PowerUpSettings {
+    /// Chance, from 0 to 1, that a destroyed asteroid drops a power-up
+    pub drop_chance: f64,
+    /// How long a dropped power-up floats around before disappearing
+    pub pickup_lifetime: f32,
+    /// How long a power-up lasts once picked up
+    pub duration: f32,
+    /// How many hits a fully charged shield takes before the ship can be destroyed
+    pub shield_hits: u8,
+    /// Seconds between shots while rapid fire is active
+    pub fire_interval: f32,
+    pub score_multiplier: u64,
+    /// Physics timestep multiplier while time slow is active
+    pub time_scale: f32,
+}
+
+impl Default for PowerUpSettings {
+    fn default() -> Self {
+        Self {
+            drop_chance: 0.2,
+            pickup_lifetime: 15.,
+            duration: 10.,
+            shield_hits: 3,
+            fire_interval: 0.1,
+            score_multiplier: 2,
+            time_scale: 0.5,
+        }
+    }
+}
+
+pub struct PowerUpAssets {
+    pub(super) mesh: Handle<Mesh>,
+    materials: Vec<(PowerUpKind, Handle<StandardMaterial>)>,
+}
+
+impl FromWorld for PowerUpAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mesh = world
+            .get_resource_mut::<Assets<Mesh>>()
+            .unwrap()
+            .add(Mesh::from(shape::Icosphere {
+                radius: 1.,
+                subdivisions: 2,
+            }));
+        let mut materials = world
+            .get_resource_mut::<Assets<StandardMaterial>>()
+            .unwrap();
+        PowerUpAssets {
+            mesh,
+            materials: PowerUpKind::ALL
+                .iter()
+                .map(|&kind| {
+                    let material = materials.add(StandardMaterial {
+                        base_color: kind.color(),
+                        emissive: kind.color(),
+                        ..Default::default()
+                    });
+                    (kind, material)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl PowerUpAssets {
+    pub(super) fn material(&self, kind: PowerUpKind) -> Handle<StandardMaterial> {
+        self.materials
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, material)| material.clone())
+            .unwrap()
+    }
+}
+
+/// A power-up floating in space, waiting to be picked up
+pub struct PowerUp(pub PowerUpKind);
+
+/// A power-up that has been picked up. Expires with its [`PowerUpTimer`].
+pub struct ActivePowerUp {
+    pub kind: PowerUpKind,
+    /// The ship that picked it up, or `None` for all of them
+    pub ship: Option<Entity>,
+}
+
+impl ActivePowerUp {
+    pub fn applies_to(&self, ship: Entity) -> bool {
+        self.ship.map_or(true, |owner| owner == ship)
+    }
+}
+
+/// Despawns a power-up when it runs out. Unlike [`crate::util::DespawnTimer`], it only ticks
+/// while the game is running, so pausing doesn't use it up.
+pub struct PowerUpTimer(pub Timer);
+
+/// Hits left before the ship is destroyed, on the [`ActivePowerUp`] of a
+/// [`PowerUpKind::ShieldRecharge`]. The shield goes when it runs out of hits or of time.
+pub struct Shield(pub u8);
+
+/// Whether `ship` has a power-up of `kind` going
+pub fn is_active(active: &Query<&ActivePowerUp>, kind: PowerUpKind, ship: Entity) -> bool {
+    active.iter().any(|p| p.kind == kind && p.applies_to(ship))
+}
+
+/// Possibly drop a power-up where an asteroid was destroyed, keeping its velocity.
+pub(super) fn maybe_drop(
+    commands: &mut Commands,
+    settings: &PowerUpSettings,
+    assets: &PowerUpAssets,
+    rng: &mut impl Rng,
+    translation: Vec3,
+    linvel: Vec3,
+) {
+    if !rng.gen_bool(settings.drop_chance) {
+        return;
+    }
+    let kind = *PowerUpKind::ALL.choose(rng).unwrap();
+    commands
+        .spawn_bundle(PbrBundle {
+            mesh: assets.mesh.clone(),
+            material: assets.material(kind),
+            transform: Transform::from_translation(translation),
+            ..Default::default()
+        })
+        .insert(PowerUp(kind))
+        .insert(TiedToGame)
+        .insert(
+            RigidBodyBuilder::new_dynamic()
+                .position(crate::util::nalgebra_pos(translation, Quat::IDENTITY))
+                .linvel(linvel.x, linvel.y, linvel.z),
+        )
+        .insert(ColliderBuilder::ball(1.5).sensor(true))
+        .insert(PowerUpTimer(Timer::from_seconds(
+            settings.pickup_lifetime,
+            false,
+        )));
+}
+
+struct PowerUpLabel;
+
+fn setup(mut commands: Commands, run: Res<Run>) {
+    if run.weapon == StartingWeapon::RapidFire {
+        commands
+            .spawn()
+            .insert(ActivePowerUp {
+                kind: PowerUpKind::RapidFire,
+                ship: None,
+            })
+            .insert(TiedToGame)
+            .insert(PowerUpTimer(Timer::from_seconds(OPENING_RAPID_FIRE, false)));
+    }
+}
+
+fn setup_hud(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(10.0),
+                    right: Val::Px(10.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(asset!("RobotoCondensed-Regular.ttf")),
+                    font_size: 30.0,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(PowerUpLabel)
+        .insert(HudText { font_size: 30. })
+        .insert(TiedToGame);
+}
+
+fn leave(mut integration_parameters: ResMut<IntegrationParameters>) {
+    integration_parameters.dt = IntegrationParameters::default().dt;
+}
+
+fn pick_up(
+    mut commands: Commands,
+    mut events: EventReader<Contact>,
+    settings: Res<PowerUpSettings>,
+    ship_query: Query<(), With<Player>>,
+    power_up_query: Query<&PowerUp>,
+    mut active_query: Query<(&ActivePowerUp, &mut PowerUpTimer, Option<&mut Shield>)>,
+) {
+    for event in events.iter() {
+        if let Contact::Started(a, b) = *event {
+            if let Some((ship, power_up_e, power_up)) =
+                [(a, b), (b, a)].iter().copied().find_map(|(a, b)| {
+                    ship_query.get(a).ok()?;
+                    Some((a, b, power_up_query.get(b).ok()?))
+                })
+            {
+                commands.entity(power_up_e).despawn_recursive();
+                // Picking up a power-up that's already active restarts it instead of stacking
+                let mut refreshed = false;
+                for (active, mut timer, shield) in active_query.iter_mut() {
+                    if active.kind == power_up.0 && active.ship == Some(ship) {
+                        timer.0.reset();
+                        if let Some(mut shield) = shield {
+                            shield.0 = settings.shield_hits;
+                        }
+                        refreshed = true;
+                    }
+                }
+                if !refreshed {
+                    let mut active = commands.spawn();
+                    active
+                        .insert(ActivePowerUp {
+                            kind: power_up.0,
+                            ship: Some(ship),
+                        })
+                        .insert(TiedToGame)
+                        .insert(PowerUpTimer(Timer::from_seconds(settings.duration, false)));
+                    if power_up.0 == PowerUpKind::ShieldRecharge {
+                        active.insert(Shield(settings.shield_hits));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Despawn power-ups that ran out, and those of ships that were destroyed
+fn expire(
+    mut commands: Commands,
+    time: Res<Time>,
+    ships: Query<(), With<Player>>,
+    mut query: Query<(Entity, &mut PowerUpTimer, Option<&ActivePowerUp>)>,
+) {
+    for (entity, mut timer, active) in query.iter_mut() {
+        let orphaned = active
+            .and_then(|active| active.ship)
+            .map_or(false, |ship| ships.get(ship).is_err());
+        if timer.0.tick(time.delta()).finished() || orphaned {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn time_slow(
+    settings: Res<PowerUpSettings>,
+    active: Query<&ActivePowerUp>,
+    mut integration_parameters: ResMut<IntegrationParameters>,
+) {
+    let mut dt = IntegrationParameters::default().dt;
+    // It slows the whole field, so it doesn't matter who picked it up
+    if active.iter().any(|p| p.kind == PowerUpKind::TimeSlow) {
+        dt *= settings.time_scale;
+    }
+    if integration_parameters.dt != dt {
+        integration_parameters.dt = dt;
+    }
+}
+
+/// The lead ship's power-ups
+fn update_hud(
+    settings: Res<PowerUpSettings>,
+    active: Query<(&ActivePowerUp, &PowerUpTimer, Option<&Shield>)>,
+    lead: Query<Entity, With<Controllable>>,
+    mut query: Query<&mut Text, With<PowerUpLabel>>,
+) {
+    if let Ok(mut text) = query.single_mut() {
+        let style = text.sections[0].style.clone();
+        let lead = lead.single().ok();
+        let mut sections: Vec<TextSection> = active
+            .iter()
+            .filter(|(active, _, _)| lead.map_or(false, |lead| active.applies_to(lead)))
+            .map(|(active, timer, shield)| {
+                let left = (timer.0.duration().as_secs_f32() - timer.0.elapsed_secs()).ceil();
+                TextSection {
+                    value: match shield {
+                        Some(shield) => format!(
+                            "{} x{} {:.0}\n",
+                            active.kind.label(&settings),
+                            shield.0,
+                            left
+                        ),
+                        None => format!("{} {:.0}\n", active.kind.label(&settings), left),
+                    },
+                    style: TextStyle {
+                        color: active.kind.color(),
+                        ..style.clone()
+                    },
+                }
+            })
+            .collect();
+        if sections.is_empty() {
+            sections.push(TextSection {
+                value: String::new(),
+                style,
+            });
+        }
+        text.sections = sections;
+    }
+}
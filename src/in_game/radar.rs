@@ -0,0 +1,267 @@
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+
+use crate::in_game::{HudElement, TiedToGame};
+
+use super::asteroids::Asteroid;
+use super::controls::Controllable;
+use super::enemies::Enemy;
+
+/// Size of the radar disc on screen. It's squashed vertically so it reads as a disc seen at an
+/// angle, leaving room for the stalks.
+const WIDTH: f32 = 200.;
+const HEIGHT: f32 = 100.;
+
+pub struct RadarPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for RadarPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<RadarSettings>()
+            .init_resource::<RadarAssets>()
+            .add_system_set(SystemSet::on_enter(self.0.clone()).with_system(setup.system()))
+            .add_system_set(
+                SystemSet::on_update(self.0.clone()).with_system(update_blips.system()),
+            );
+    }
+}
+
+pub struct RadarSettings {
+    /// Anything further from the ship than this isn't shown
+    pub range: f32,
+}
+
+impl Default for RadarSettings {
+    fn default() -> Self {
+        Self { range: 100. }
+    }
+}
+
+struct RadarAssets {
+    disc: Handle<ColorMaterial>,
+    rim: Handle<ColorMaterial>,
+    ship: Handle<ColorMaterial>,
+    stalk: Handle<ColorMaterial>,
+    /// Indexed by the number of hits left, so tougher asteroids look more dangerous
+    asteroids: [Handle<ColorMaterial>; 3],
+    enemy: Handle<ColorMaterial>,
+}
+
+impl FromWorld for RadarAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mut textures = world.get_resource_mut::<Assets<Texture>>().unwrap();
+        let disc = textures.add(crate::util::ring_texture(128, 0.));
+        let rim = textures.add(crate::util::ring_texture(128, 0.97));
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        RadarAssets {
+            disc: materials.add(ColorMaterial::modulated_texture(
+                disc,
+                Color::rgba(0., 0.15, 0.1, 0.6),
+            )),
+            rim: materials.add(ColorMaterial::modulated_texture(
+                rim,
+                Color::rgb(0.3, 0.9, 0.6),
+            )),
+            ship: materials.add(Color::WHITE.into()),
+            stalk: materials.add(Color::rgba(0.6, 0.9, 0.7, 0.6).into()),
+            asteroids: [
+                materials.add(Color::rgb(1., 0.9, 0.4).into()),
+                materials.add(Color::rgb(1., 0.6, 0.2).into()),
+                materials.add(Color::rgb(1., 0.25, 0.2).into()),
+            ],
+            enemy: materials.add(Color::rgb(0.9, 0.3, 1.).into()),
+        }
+    }
+}
+
+struct Radar;
+
+/// Marks where `target` is on the radar. Blips without a target are hidden, and get reused.
+struct Blip {
+    target: Option<Entity>,
+    stalk: Entity,
+}
+
+/// Line from the radar plane up or down to a blip, showing its elevation
+struct Stalk;
+
+fn setup(mut commands: Commands, assets: Res<RadarAssets>) {
+    commands
+        .spawn_bundle(ImageBundle {
+            style: Style {
+                size: Size::new(Val::Px(WIDTH), Val::Px(HEIGHT)),
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    bottom: Val::Px(40.),
+                    left: Val::Px(10.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            material: assets.disc.clone(),
+            ..Default::default()
+        })
+        .insert(Radar)
+        .insert(TiedToGame)
+        .insert(HudElement)
+        .with_children(|parent| {
+            parent.spawn_bundle(ImageBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(100.), Val::Percent(100.)),
+                    position_type: PositionType::Absolute,
+                    ..Default::default()
+                },
+                material: assets.rim.clone(),
+                ..Default::default()
+            });
+            parent.spawn_bundle(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Px(4.), Val::Px(6.)),
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        left: Val::Px(WIDTH / 2. - 2.),
+                        bottom: Val::Px(HEIGHT / 2. - 3.),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                material: assets.ship.clone(),
+                ..Default::default()
+            });
+        });
+}
+
+/// Project asteroids and enemies into the ship's frame and move their blips to match. Forward
+/// is up on the radar, and height above or below the ship is drawn as a stalk.
+fn update_blips(
+    mut commands: Commands,
+    settings: Res<RadarSettings>,
+    assets: Res<RadarAssets>,
+    radar: Query<Entity, With<Radar>>,
+    ship: Query<&Transform, With<Controllable>>,
+    targets: Query<(Entity, &Transform, Option<&Asteroid>, Option<&Enemy>)>,
+    mut blips: Query<
+        (
+            &mut Blip,
+            &mut Style,
+            &mut Visible,
+            &mut Handle<ColorMaterial>,
+        ),
+        Without<Stalk>,
+    >,
+    mut stalks: Query<(&mut Style, &mut Visible), With<Stalk>>,
+) {
+    let (radar, ship) = match (radar.single(), ship.single()) {
+        (Ok(radar), Ok(ship)) => (radar, ship),
+        _ => return,
+    };
+    let to_local = ship.rotation.inverse();
+
+    // Hand targets that don't have a blip yet to blips that lost theirs
+    let tracked: HashSet<Entity> = blips.iter_mut().filter_map(|blip| blip.0.target).collect();
+    let mut untracked = targets
+        .iter()
+        .filter(|(entity, _, asteroid, enemy)| {
+            (asteroid.is_some() || enemy.is_some()) && !tracked.contains(entity)
+        })
+        .map(|(entity, ..)| entity);
+
+    for (mut blip, mut style, mut visible, mut material) in blips.iter_mut() {
+        let target = blip.target.and_then(|target| targets.get(target).ok());
+        let target = match target {
+            Some(target) => Some(target),
+            None => {
+                blip.target = untracked.next();
+                blip.target.and_then(|target| targets.get(target).ok())
+            }
+        };
+        let (mut stalk_style, mut stalk_visible) = match stalks.get_mut(blip.stalk) {
+            Ok(stalk) => stalk,
+            Err(_) => continue,
+        };
+        let (hits, new_material, local) = match target {
+            Some((_, transform, asteroid, enemy)) => {
+                let local = to_local * (transform.translation - ship.translation) / settings.range;
+                match (asteroid, enemy) {
+                    (Some(asteroid), _) => (
+                        asteroid.hits,
+                        &assets.asteroids[(asteroid.hits.max(1) as usize - 1).min(2)],
+                        local,
+                    ),
+                    (_, Some(enemy)) => (enemy.hits, &assets.enemy, local),
+                    // The entity was reused for something else
+                    _ => {
+                        blip.target = None;
+                        (0, &assets.enemy, Vec3::splat(f32::INFINITY))
+                    }
+                }
+            }
+            None => (0, &assets.enemy, Vec3::splat(f32::INFINITY)),
+        };
+        let in_range = local.length() <= 1.;
+        visible.is_visible = in_range;
+        stalk_visible.is_visible = in_range;
+        if !in_range {
+            continue;
+        }
+
+        // The ship's right is -x
+        let base = Vec2::new(
+            WIDTH / 2. - local.x * WIDTH / 2.,
+            HEIGHT / 2. + local.z * HEIGHT / 2.,
+        );
+        let height = local.y * HEIGHT / 2.;
+        if *material != *new_material {
+            *material = new_material.clone();
+        }
+        let size = 3. + 2. * hits as f32;
+        style.size = Size::new(Val::Px(size), Val::Px(size));
+        style.position = Rect {
+            left: Val::Px(base.x - size / 2.),
+            bottom: Val::Px(base.y + height - size / 2.),
+            ..Default::default()
+        };
+        stalk_style.size = Size::new(Val::Px(1.), Val::Px(height.abs()));
+        stalk_style.position = Rect {
+            left: Val::Px(base.x),
+            bottom: Val::Px(base.y + height.min(0.)),
+            ..Default::default()
+        };
+    }
+
+    // Not enough blips to go around, so make more. They're placed next frame.
+    for target in untracked {
+        let hidden = Visible {
+            is_visible: false,
+            is_transparent: true,
+        };
+        let stalk = commands
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    ..Default::default()
+                },
+                material: assets.stalk.clone(),
+                visible: hidden.clone(),
+                ..Default::default()
+            })
+            .insert(Stalk)
+            .id();
+        let blip = commands
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    ..Default::default()
+                },
+                material: assets.enemy.clone(),
+                visible: hidden,
+                ..Default::default()
+            })
+            .insert(Blip {
+                target: Some(target),
+                stalk,
+            })
+            .id();
+        commands.entity(radar).push_children(&[stalk, blip]);
+    }
+}
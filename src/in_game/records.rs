@@ -0,0 +1,108 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::storage;
+
+use super::daily::UtcDate;
+use super::points::Points;
+use super::run::{Run, RunKind};
+
+/// Keeps the best score between games. Daily challenges are kept apart from it, since their
+/// fields aren't like a normal run's. Only runs that make it to the end screen count, so quitting
+/// from the pause menu isn't an attempt.
+pub struct RecordsPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for RecordsPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Records>()
+            .add_system_set(SystemSet::on_enter(crate::AppState::End).with_system(finish.system()));
+    }
+}
+
+/// The best score of one day's challenge
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct DailyRecord {
+    pub date: UtcDate,
+    pub score: u64,
+    pub attempts: u32,
+}
+
+pub struct Records {
+    /// Best score outside of daily challenges
+    pub high_score: u64,
+    /// The latest daily challenge played
+    pub daily: Option<DailyRecord>,
+}
+
+impl Records {
+    fn load<D: for<'de> Deserialize<'de>>(name: &str) -> Option<D> {
+        match storage::read(name).and_then(|json| match json {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }) {
+            Ok(record) => record,
+            Err(e) => {
+                warn!("Couldn't load {}: {:?}", name, e);
+                None
+            }
+        }
+    }
+
+    fn save(name: &str, record: &impl Serialize) {
+        if let Err(e) = serde_json::to_string(record)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| storage::write(name, &json))
+        {
+            error!("Couldn't save {}: {:?}", name, e);
+        }
+    }
+
+    /// The best to compare the last run against, for the end screen
+    pub fn summary(&self, kind: RunKind) -> String {
+        match (kind, self.daily) {
+            (RunKind::Daily, Some(daily)) => format!(
+                "Daily {} best: {} ({} tries)",
+                daily.date, daily.score, daily.attempts
+            ),
+            _ => format!("Best: {}", self.high_score),
+        }
+    }
+}
+
+impl FromWorld for Records {
+    fn from_world(_world: &mut World) -> Self {
+        Records {
+            high_score: Records::load("high_score").unwrap_or(0),
+            daily: Records::load("daily"),
+        }
+    }
+}
+
+fn finish(run: Res<Run>, points: Res<Points>, mut records: ResMut<Records>) {
+    let score = points.total();
+    match (run.kind, run.date) {
+        (RunKind::Daily, Some(date)) => {
+            let daily = match records.daily {
+                // Only one day is kept, so yesterday's is replaced
+                Some(daily) if daily.date == date => DailyRecord {
+                    score: daily.score.max(score),
+                    attempts: daily.attempts + 1,
+                    ..daily
+                },
+                _ => DailyRecord {
+                    date,
+                    score,
+                    attempts: 1,
+                },
+            };
+            records.daily = Some(daily);
+            Records::save("daily", &daily);
+        }
+        _ => {
+            if score > records.high_score {
+                records.high_score = score;
+                Records::save("high_score", &score);
+            }
+        }
+    }
+}
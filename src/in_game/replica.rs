@@ -0,0 +1,198 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_rapier3d::physics::RigidBodyHandleComponent;
+use bevy_rapier3d::rapier::dynamics::RigidBodySet;
+use serde::{Deserialize, Serialize};
+
+use super::asteroids::{Asteroid, Asteroids};
+use super::boost::Boost;
+use super::bullets::{BulletAssets, PooledBullet};
+use super::controls::Player;
+use super::enemies::{Enemy, EnemyAssets, EnemyBullet};
+use super::energy::Energy;
+use super::laser::{Laser, LaserAssets};
+use super::missiles::{Missile, MissileAssets};
+use super::powerups::{PowerUp, PowerUpAssets, PowerUpKind};
+
+/// Gathers a [`Replica`] of the game at the end of every frame, for a server to send out
+pub struct ReplicaPlugin;
+
+impl Plugin for ReplicaPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Replica>()
+            .add_system_to_stage(CoreStage::Last, replicate.system());
+    }
+}
+
+/// Everything in play that can be seen, as of the end of the last frame
+#[derive(Default)]
+pub struct Replica {
+    pub ships: Vec<ShipReplica>,
+    pub bodies: Vec<BodyReplica>,
+}
+
+pub struct ShipReplica {
+    pub player: usize,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub linvel: Vec3,
+    pub angvel: Vec3,
+    pub energy: f32,
+    pub boosting: bool,
+    /// How far its laser reaches, while it's firing
+    pub beam: Option<f32>,
+}
+
+/// Anything in play other than a player's ship
+pub struct BodyReplica {
+    /// The same for as long as it stays in play
+    pub id: u64,
+    pub kind: BodyKind,
+    pub translation: Vec3,
+    pub rotation: Quat,
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum BodyKind {
+    /// Drawn like the plan at this index of [`Asteroids`]
+    Asteroid(u8),
+    Enemy,
+    Bullet,
+    EnemyBullet,
+    Missile,
+    PowerUp(PowerUpKind),
+}
+
+fn replicate(
+    mut replica: ResMut<Replica>,
+    plans: Res<Asteroids>,
+    rigid_bodies: Res<RigidBodySet>,
+    ships: Query<(
+        &Player,
+        &Transform,
+        Option<&RigidBodyHandleComponent>,
+        Option<&Energy>,
+        Option<&Boost>,
+        Option<&Laser>,
+    )>,
+    asteroids: Query<(Entity, &Transform, &Handle<Mesh>), With<Asteroid>>,
+    enemies: Query<(Entity, &Transform), With<Enemy>>,
+    bullets: Query<(Entity, &Transform, &PooledBullet, Option<&EnemyBullet>)>,
+    missiles: Query<(Entity, &Transform), With<Missile>>,
+    power_ups: Query<(Entity, &Transform, &PowerUp)>,
+) {
+    let replica = &mut *replica;
+    replica.ships.clear();
+    for (player, transform, handle, energy, boost, laser) in ships.iter() {
+        let body = handle.and_then(|handle| rigid_bodies.get(handle.handle()));
+        replica.ships.push(ShipReplica {
+            player: player.0,
+            translation: transform.translation,
+            rotation: transform.rotation,
+            linvel: body.map_or(Vec3::ZERO, |body| (*body.linvel()).into()),
+            angvel: body.map_or(Vec3::ZERO, |body| (*body.angvel()).into()),
+            energy: energy.map_or(0., |energy| energy.current),
+            boosting: boost.map_or(false, |boost| boost.active),
+            beam: laser.and_then(|laser| laser.beam),
+        });
+    }
+
+    replica.bodies.clear();
+    let mut push = |entity: Entity, kind, transform: &Transform| {
+        replica.bodies.push(BodyReplica {
+            id: entity.to_bits(),
+            kind,
+            translation: transform.translation,
+            rotation: transform.rotation,
+        })
+    };
+    for (entity, transform, mesh) in asteroids.iter() {
+        if let Some(index) = plans.0.iter().position(|plan| plan.pbr.mesh == *mesh) {
+            push(entity, BodyKind::Asteroid(index as u8), transform);
+        }
+    }
+    for (entity, transform) in enemies.iter() {
+        push(entity, BodyKind::Enemy, transform);
+    }
+    for (entity, transform, bullet, enemy) in bullets.iter() {
+        if bullet.active {
+            let kind = match enemy {
+                Some(_) => BodyKind::EnemyBullet,
+                None => BodyKind::Bullet,
+            };
+            push(entity, kind, transform);
+        }
+    }
+    for (entity, transform) in missiles.iter() {
+        push(entity, BodyKind::Missile, transform);
+    }
+    for (entity, transform, power_up) in power_ups.iter() {
+        push(entity, BodyKind::PowerUp(power_up.0), transform);
+    }
+}
+
+/// What ships, bodies and beams are drawn with, to draw a [`Replica`] sent from elsewhere. Only
+/// translation and rotation need setting on the bundles handed out.
+#[derive(SystemParam)]
+pub struct Looks<'a> {
+    asset_server: Res<'a, AssetServer>,
+    asteroids: Res<'a, Asteroids>,
+    bullets: Res<'a, BulletAssets>,
+    enemies: Res<'a, EnemyAssets>,
+    lasers: Res<'a, LaserAssets>,
+    missiles: Res<'a, MissileAssets>,
+    power_ups: Res<'a, PowerUpAssets>,
+}
+
+impl<'a> Looks<'a> {
+    pub fn ship(&self) -> PbrBundle {
+        self.ship_with(self.asset_server.load(asset!("ship.glb", "Material0")))
+    }
+
+    pub fn body(&self, kind: BodyKind) -> PbrBundle {
+        let (mesh, material) = match kind {
+            BodyKind::Asteroid(index) => {
+                let plan = &self.asteroids.0[index as usize];
+                return PbrBundle {
+                    mesh: plan.pbr.mesh.clone(),
+                    material: plan.pbr.material.clone(),
+                    transform: Transform::from_scale(plan.pbr.transform.scale),
+                    ..Default::default()
+                };
+            }
+            BodyKind::Enemy => return self.ship_with(self.enemies.material.clone()),
+            BodyKind::Bullet => (self.bullets.mesh.clone(), self.bullets.material.clone()),
+            BodyKind::EnemyBullet => (
+                self.bullets.mesh.clone(),
+                self.bullets.enemy_material.clone(),
+            ),
+            BodyKind::Missile => (self.missiles.mesh.clone(), self.missiles.material.clone()),
+            BodyKind::PowerUp(kind) => (self.power_ups.mesh.clone(), self.power_ups.material(kind)),
+        };
+        PbrBundle {
+            mesh,
+            material,
+            ..Default::default()
+        }
+    }
+
+    /// A laser beam, placed with [`super::beam_transform`]
+    pub fn beam(&self) -> PbrBundle {
+        PbrBundle {
+            mesh: self.lasers.mesh.clone(),
+            material: self.lasers.material.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn ship_with(&self, material: Handle<StandardMaterial>) -> PbrBundle {
+        PbrBundle {
+            mesh: self
+                .asset_server
+                .load(asset!("ship.glb", "Mesh0/Primitive0")),
+            material,
+            transform: Transform::from_scale(Vec3::splat(0.1)),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use bevy::prelude::*;
+use rand::prelude::*;
+
+use super::daily::{splitmix64, UtcDate};
+use super::ghost::Ghost;
+
+/// Picks each run's seed as it starts. Anything that shapes the field, like where asteroids and
+/// enemies come from, draws from [`RunRng`], so the same seed plays out the same way.
+pub struct RunPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for RunPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<Run>()
+            .insert_resource(RunRng::new(0))
+            .add_system_set(
+                SystemSet::on_enter(self.0.clone()).with_system(begin.system().label(RunSetup)),
+            );
+    }
+}
+
+/// Picks the seed, waves and weapon. Systems that need them when a run starts go after this.
+#[derive(SystemLabel, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct RunSetup;
+
+/// The kinds of run that can be started from the Home screen
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RunKind {
+    Normal,
+    /// Race the recorded best run, on its seed
+    Ghost,
+    /// Everything comes from today's date, so everyone plays the same run
+    Daily,
+}
+
+impl Default for RunKind {
+    fn default() -> Self {
+        RunKind::Normal
+    }
+}
+
+/// How asteroids come in
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Waves {
+    /// Seconds between asteroids, picked between these
+    pub interval: (f32, f32),
+    /// How fast asteroids fly in, picked between these
+    pub speed: (f32, f32),
+}
+
+impl Default for Waves {
+    fn default() -> Self {
+        Self {
+            interval: (1., 3.),
+            speed: (5., 8.),
+        }
+    }
+}
+
+/// What the ship has on top of its cannon at the start of a run
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StartingWeapon {
+    Cannon,
+    /// Rapid fire for the first [`OPENING_RAPID_FIRE`] seconds
+    RapidFire,
+    /// A second rack of missiles
+    Missiles,
+}
+
+impl StartingWeapon {
+    pub const ALL: [StartingWeapon; 3] = [
+        StartingWeapon::Cannon,
+        StartingWeapon::RapidFire,
+        StartingWeapon::Missiles,
+    ];
+}
+
+impl Default for StartingWeapon {
+    fn default() -> Self {
+        StartingWeapon::Cannon
+    }
+}
+
+/// How long [`StartingWeapon::RapidFire`] lasts
+pub const OPENING_RAPID_FIRE: f32 = 30.;
+
+/// The run being played, or about to be
+#[derive(Debug, Default)]
+pub struct Run {
+    pub kind: RunKind,
+    pub seed: u64,
+    pub waves: Waves,
+    pub weapon: StartingWeapon,
+    /// The day of a daily challenge
+    pub date: Option<UtcDate>,
+}
+
+/// A separate stream for each thing that draws from it, so that when one draws doesn't change
+/// what the others get
+pub struct RunRng {
+    pub asteroids: StdRng,
+    pub enemies: StdRng,
+    pub power_ups: StdRng,
+}
+
+impl RunRng {
+    fn new(seed: u64) -> Self {
+        Self {
+            asteroids: StdRng::seed_from_u64(stream(seed, ASTEROIDS_STREAM)),
+            enemies: StdRng::seed_from_u64(stream(seed, ENEMIES_STREAM)),
+            power_ups: StdRng::seed_from_u64(stream(seed, POWER_UPS_STREAM)),
+        }
+    }
+}
+
+// Far apart, so one run's streams never line up with another's
+const ASTEROIDS_STREAM: u64 = 0x243f_6a88_85a3_08d3;
+const ENEMIES_STREAM: u64 = 0x1319_8a2e_0370_7344;
+const POWER_UPS_STREAM: u64 = 0xa409_3822_299f_31d0;
+const DAY_STREAM: u64 = 0x082e_fa98_ec4e_6c89;
+
+/// The seed of one of the run's streams
+fn stream(seed: u64, stream: u64) -> u64 {
+    splitmix64(seed ^ stream)
+}
+
+fn begin(mut run: ResMut<Run>, mut rng: ResMut<RunRng>, ghost: Option<Res<Ghost>>) {
+    run.waves = Waves::default();
+    run.weapon = StartingWeapon::default();
+    run.date = None;
+    match run.kind {
+        RunKind::Normal => run.seed = thread_rng().gen(),
+        RunKind::Ghost => {
+            // Servers keep no ghost to race
+            run.seed = ghost
+                .and_then(|ghost| ghost.best_seed())
+                .unwrap_or_else(|| thread_rng().gen())
+        }
+        RunKind::Daily => {
+            let date = UtcDate::today();
+            run.seed = date.seed();
+            run.date = Some(date);
+            // Its own stream, so the field doesn't depend on how the day was set up
+            let mut day = StdRng::seed_from_u64(stream(run.seed, DAY_STREAM));
+            let pace = day.gen_range(0.6..1.2);
+            let speed = day.gen_range(0.8..1.5);
+            let defaults = Waves::default();
+            run.waves = Waves {
+                interval: (defaults.interval.0 * pace, defaults.interval.1 * pace),
+                speed: (defaults.speed.0 * speed, defaults.speed.1 * speed),
+            };
+            run.weapon = *StartingWeapon::ALL.choose(&mut day).unwrap();
+        }
+    }
+    *rng = RunRng::new(run.seed);
+}
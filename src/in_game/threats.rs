@@ -0,0 +1,222 @@
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+use bevy::render::render_graph::base::camera::CAMERA_3D;
+use bevy_rapier3d::physics::RigidBodyHandleComponent;
+use bevy_rapier3d::rapier::dynamics::RigidBodySet;
+
+use crate::in_game::{HudElement, TiedToGame};
+
+use super::asteroids::Asteroid;
+use super::controls::{Controllable, MainView};
+
+pub struct ThreatsPlugin<T>(pub T);
+
+impl<T: crate::util::StateType> Plugin for ThreatsPlugin<T> {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ThreatSettings>()
+            .init_resource::<ThreatAssets>()
+            .add_system_set(SystemSet::on_enter(self.0.clone()).with_system(setup.system()))
+            .add_system_set(
+                SystemSet::on_update(self.0.clone()).with_system(update_arrows.system()),
+            );
+    }
+}
+
+/// Which asteroids count as threats, and how they're shown
+pub struct ThreatSettings {
+    /// Only asteroids that will hit the ship sooner than this, in seconds, are shown
+    pub time_to_impact: f32,
+    /// Asteroids passing closer than this to the ship count as hitting it
+    pub collision_radius: f32,
+    pub max_arrows: usize,
+    /// Arrow size per cube root of the asteroid's mass
+    pub size_per_mass: f32,
+    pub min_size: f32,
+    pub max_size: f32,
+    /// Distance from the arrows to the edge of the screen
+    pub margin: f32,
+}
+
+impl Default for ThreatSettings {
+    fn default() -> Self {
+        Self {
+            time_to_impact: 5.,
+            collision_radius: 10.,
+            max_arrows: 6,
+            size_per_mass: 3.,
+            min_size: 16.,
+            max_size: 48.,
+            margin: 40.,
+        }
+    }
+}
+
+/// Number of colours between the least and most urgent threats
+const URGENCY_STEPS: usize = 4;
+
+struct ThreatAssets {
+    /// From least to most urgent
+    materials: Vec<Handle<ColorMaterial>>,
+}
+
+impl FromWorld for ThreatAssets {
+    fn from_world(world: &mut World) -> Self {
+        let texture = world
+            .get_resource_mut::<Assets<Texture>>()
+            .unwrap()
+            .add(crate::util::triangle_texture(64));
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        ThreatAssets {
+            materials: (0..URGENCY_STEPS)
+                .map(|i| {
+                    let urgency = i as f32 / (URGENCY_STEPS - 1) as f32;
+                    materials.add(ColorMaterial::modulated_texture(
+                        texture.clone(),
+                        Color::rgb(1., 0.9 * (1. - urgency), 0.1),
+                    ))
+                })
+                .collect(),
+        }
+    }
+}
+
+struct ThreatArrow;
+
+fn setup(mut commands: Commands, settings: Res<ThreatSettings>, assets: Res<ThreatAssets>) {
+    for _ in 0..settings.max_arrows {
+        commands
+            .spawn_bundle(ImageBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    display: Display::None,
+                    ..Default::default()
+                },
+                material: assets.materials[0].clone(),
+                ..Default::default()
+            })
+            .insert(ThreatArrow)
+            .insert(HudElement)
+            .insert(TiedToGame);
+    }
+}
+
+struct Threat {
+    /// Direction on screen, from the center
+    direction: Vec2,
+    urgency: f32,
+    mass: f32,
+}
+
+/// Point arrows at the edge of the screen towards off-screen asteroids that are about to hit
+/// the ship
+fn update_arrows(
+    view: MainView,
+    settings: Res<ThreatSettings>,
+    assets: Res<ThreatAssets>,
+    rigid_bodies: Res<RigidBodySet>,
+    ship: Query<(&Transform, &RigidBodyHandleComponent), With<Controllable>>,
+    asteroids: Query<(&Transform, &RigidBodyHandleComponent), With<Asteroid>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut arrows: Query<
+        (&mut Style, &mut Transform, &mut Handle<ColorMaterial>),
+        (With<ThreatArrow>, Without<Controllable>, Without<Asteroid>),
+    >,
+) {
+    let mut threats = vec![];
+    let (corner, view_size) = match view.rect() {
+        Some(rect) => rect,
+        None => return,
+    };
+    let center = corner + view_size / 2.;
+    let camera = cameras
+        .iter()
+        .find(|(camera, _)| camera.name.as_deref() == Some(CAMERA_3D));
+    let ship = ship
+        .single()
+        .ok()
+        .and_then(|(transform, rb)| Some((transform, rigid_bodies.get(rb.handle())?)));
+
+    if let (Some((camera, camera_transform)), Some((ship, ship_rb))) = (camera, ship) {
+        let ship_linvel: Vec3 = ship_rb.linvel().clone_owned().into();
+        for (transform, rigid_body_component) in asteroids.iter() {
+            let rb = match rigid_bodies.get(rigid_body_component.handle()) {
+                Some(rb) => rb,
+                None => continue,
+            };
+            let linvel: Vec3 = rb.linvel().clone_owned().into();
+            let position = transform.translation - ship.translation;
+            let velocity = linvel - ship_linvel;
+            let speed_squared = velocity.length_squared();
+            if speed_squared < 0.0001 {
+                continue;
+            }
+            // Closest approach, if it's coming closer at all
+            let time = -position.dot(velocity) / speed_squared;
+            if time <= 0. || time > settings.time_to_impact {
+                continue;
+            }
+            if (position + velocity * time).length() > settings.collision_radius {
+                continue;
+            }
+
+            let screen = view.world_to_screen(camera, camera_transform, transform.translation);
+            let in_front = camera_transform
+                .rotation
+                .inverse()
+                .mul_vec3(transform.translation - camera_transform.translation);
+            let direction = match screen {
+                Some(screen)
+                    if in_front.z < 0.
+                        && screen.cmpge(corner).all()
+                        && screen.cmple(corner + view_size).all() =>
+                {
+                    // It's on screen, so the player can already see it
+                    continue;
+                }
+                Some(screen) if in_front.z < 0. => screen - center,
+                // Behind the camera, where the projection flips
+                _ => Vec2::new(in_front.x, in_front.y),
+            };
+            threats.push(Threat {
+                direction: direction.normalize_or_zero(),
+                urgency: 1. - time / settings.time_to_impact,
+                mass: rb.mass(),
+            });
+        }
+    }
+
+    threats.sort_by(|a, b| b.urgency.partial_cmp(&a.urgency).unwrap());
+    let mut threats = threats.into_iter();
+    for (mut style, mut transform, mut material) in arrows.iter_mut() {
+        let threat = match threats.next() {
+            Some(threat) if threat.direction != Vec2::ZERO => threat,
+            _ => {
+                style.display = Display::None;
+                continue;
+            }
+        };
+        let size = (threat.mass.cbrt() * settings.size_per_mass)
+            .clamp(settings.min_size, settings.max_size);
+        // Push the arrow out along its direction until it reaches the margin
+        let half = view_size / 2. - Vec2::splat(settings.margin);
+        let scale = (half.x / threat.direction.x.abs()).min(half.y / threat.direction.y.abs());
+        let position = center + threat.direction * scale;
+
+        style.display = Display::Flex;
+        style.size = Size::new(Val::Px(size), Val::Px(size));
+        style.position = Rect {
+            left: Val::Px(position.x - size / 2.),
+            bottom: Val::Px(position.y - size / 2.),
+            ..Default::default()
+        };
+        // The texture points up
+        transform.rotation = Quat::from_rotation_z(
+            threat.direction.y.atan2(threat.direction.x) - std::f32::consts::FRAC_PI_2,
+        );
+        let step = (threat.urgency * (URGENCY_STEPS - 1) as f32).round() as usize;
+        let new_material = &assets.materials[step.min(URGENCY_STEPS - 1)];
+        if *material != *new_material {
+            *material = new_material.clone();
+        }
+    }
+}
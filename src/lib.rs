@@ -3,21 +3,32 @@ use bevy::prelude::*;
 
 #[macro_use]
 mod util;
+mod audio;
 mod custom_asset;
 mod end;
 mod home;
 mod in_game;
+mod net;
 mod pause;
 mod physics;
+mod players;
+mod settings;
+mod storage;
+mod ui;
 #[cfg(target_arch = "wasm32")]
 mod wasm;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
-enum AppState {
+pub enum AppState {
     Home,
     InGame,
     Paused,
+    Settings,
+    /// Gamepad stick settings, opened from [`AppState::Settings`]
+    Controller,
     End,
+    /// Playing on a server
+    Online,
 }
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen::prelude::wasm_bindgen)]
@@ -27,9 +38,25 @@ pub fn main() {
     let mut app = App::build();
     #[cfg(target_arch = "wasm32")]
     app.add_plugin(wasm::WasmPlugin);
-    app.insert_resource(Msaa { samples: 4 })
-        .add_plugins(DefaultPlugins)
-        .add_state(AppState::Home);
+    let loaded = settings::load();
+    let settings = loaded.as_ref().cloned().unwrap_or_default();
+    // Msaa is only read when the renderer starts up, so it has to come from saved settings
+    app.insert_resource(Msaa {
+        samples: settings.msaa_samples,
+    })
+    .insert_resource(settings);
+    #[cfg(not(target_arch = "wasm32"))]
+    app.add_plugins(DefaultPlugins);
+    // The audio output is opened by `audio::AudioPlugin` after the first user gesture
+    #[cfg(target_arch = "wasm32")]
+    app.add_plugins_with(DefaultPlugins, |group| {
+        group.disable::<bevy::audio::AudioPlugin>()
+    });
+    // Only once the log plugin is there to show it
+    if let Err(e) = loaded {
+        warn!("Couldn't load settings, using the defaults: {:?}", e);
+    }
+    app.add_state(AppState::Home);
     #[cfg(target_arch = "wasm32")]
     app.add_plugin(bevy_webgl2::WebGL2Plugin);
     #[cfg(all(feature = "inspector", not(target_arch = "wasm32")))]
@@ -38,41 +65,27 @@ pub fn main() {
         color: Color::WHITE,
         brightness: 1.0 / 5.0f32,
     })
-    .init_resource::<Option<Gamepad>>()
-    .add_system_to_stage(CoreStage::PreUpdate, connect_gamepad.system())
+    .add_plugin(players::PlayersPlugin)
     .add_plugin(custom_asset::CustomAssetPlugin)
     .add_plugin(util::UtilPlugin)
+    .add_plugin(audio::AudioPlugin)
+    .add_plugin(ui::UiPlugin)
     .add_plugin(in_game::InGamePlugin)
     .add_plugin(pause::PausePlugin)
     .add_plugin(home::HomePlugin)
     .add_plugin(end::EndPlugin)
+    .add_plugin(settings::SettingsPlugin)
+    .add_plugin(net::NetClientPlugin)
     .add_startup_system(setup.system())
     .run();
 }
 
-fn setup(mut commands: Commands) {
-    commands.spawn_bundle(UiCameraBundle::default());
+/// Runs the dedicated multiplayer server, for the `server` binary
+#[cfg(not(target_arch = "wasm32"))]
+pub fn server_main() -> anyhow::Result<()> {
+    net::server::main()
 }
 
-fn connect_gamepad(
-    mut lobby: ResMut<Option<Gamepad>>,
-    mut gamepad_event: EventReader<GamepadEvent>,
-) {
-    for event in gamepad_event.iter() {
-        match &event {
-            GamepadEvent(gamepad, GamepadEventType::Connected) => {
-                if lobby.is_none() {
-                    *lobby = Some(*gamepad);
-                }
-                println!("{:?} Connected", gamepad);
-            }
-            GamepadEvent(gamepad, GamepadEventType::Disconnected) => {
-                if *lobby == Some(*gamepad) {
-                    *lobby = None;
-                }
-                println!("{:?} Disconnected", gamepad);
-            }
-            _ => (),
-        }
-    }
+fn setup(mut commands: Commands) {
+    commands.spawn_bundle(UiCameraBundle::default());
 }
@@ -0,0 +1,48 @@
+//! Multiplayer over the network. A dedicated server runs the same game as local play, headless
+//! (see [`server`]), and clients send it their inputs, getting back snapshots of everything in
+//! it.
+
+mod client;
+mod protocol;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod server;
+mod transport;
+#[cfg(not(target_arch = "wasm32"))]
+mod udp;
+mod ws;
+
+pub use client::NetClientPlugin;
+
+/// Where the server listens for UDP, and where native clients connect, unless told otherwise
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7777";
+/// Where the server listens for WebSockets, unless told otherwise
+pub const DEFAULT_WS_ADDRESS: &str = "127.0.0.1:7778";
+
+pub struct NetSettings {
+    /// The server to connect to. A UDP address natively, and a WebSocket URL on the web.
+    pub address: String,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for NetSettings {
+    fn default() -> Self {
+        Self {
+            address: std::env::var("ASTEROIDS_SERVER")
+                .unwrap_or_else(|_| DEFAULT_ADDRESS.to_string()),
+        }
+    }
+}
+
+/// The page can pick a server with `?server=ws://host:port`
+#[cfg(target_arch = "wasm32")]
+impl Default for NetSettings {
+    fn default() -> Self {
+        let from_page = web_sys::window()
+            .and_then(|window| window.location().search().ok())
+            .and_then(|search| web_sys::UrlSearchParams::new_with_str(&search).ok())
+            .and_then(|params| params.get("server"));
+        Self {
+            address: from_page.unwrap_or_else(|| format!("ws://{}", DEFAULT_WS_ADDRESS)),
+        }
+    }
+}
@@ -0,0 +1,646 @@
+//! Playing on a server. The local ship is predicted by flying it the same way the server does
+//! as inputs are sent, then replaying the inputs the server hasn't applied yet on top of every
+//! snapshot. Everything else is drawn a few ticks in the past, interpolated between the two
+//! snapshots around that time.
+
+use std::collections::{HashMap, VecDeque};
+
+use bevy::ecs::system::SystemParam;
+use bevy::input::mouse::MouseMotion;
+use bevy::math::const_vec3;
+use bevy::prelude::*;
+use bevy_rapier3d::physics::{
+    EventQueue, InteractionPairFilters, RapierConfiguration, RigidBodyHandleComponent,
+};
+use bevy_rapier3d::rapier::dynamics::{CCDSolver, IntegrationParameters, JointSet, RigidBodySet};
+use bevy_rapier3d::rapier::geometry::{BroadPhase, ColliderSet, NarrowPhase};
+use bevy_rapier3d::rapier::math::Vector;
+use bevy_rapier3d::rapier::pipeline::{PhysicsHooks, PhysicsPipeline};
+
+use crate::in_game::{
+    beam_transform, fly, spawn_player_ship, ArenaPlugin, BodyKind, BoostSettings, Controls,
+    FlightAssist, Looks, MovementSettings, ShipInput,
+};
+use crate::players::{PlayerDevices, MAX_PLAYERS};
+use crate::util::{cursor_locked, nalgebra_pos, set_grab_cursor};
+use crate::AppState;
+
+use super::protocol::{
+    decode, encode, ClientMessage, InputFrame, ServerMessage, ShipState, Snapshot,
+    INPUT_REDUNDANCY, PROTOCOL_VERSION, TICK, TICK_RATE,
+};
+use super::transport::ClientTransport;
+use super::NetSettings;
+
+/// How far in the past other things are drawn, so there's usually a snapshot on either side
+const INTERPOLATION_TICKS: f32 = 6.;
+const SNAPSHOT_BUFFER: usize = 32;
+/// Inputs kept for replaying. Past this the server is too far behind to be worth predicting.
+const MAX_PENDING: usize = 64;
+const HELLO_INTERVAL: f32 = 0.5;
+/// Give up on a server that hasn't sent anything for this long
+const TIMEOUT: f32 = 5.;
+const CHASE_OFFSET: Vec3 = const_vec3!([0., 6., -20.]);
+
+pub struct NetClientPlugin;
+
+impl Plugin for NetClientPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let state = AppState::Online;
+        app.init_resource::<NetSettings>()
+            .insert_non_send_resource(NetClient::default())
+            .add_plugin(ArenaPlugin(state))
+            .add_system_set(
+                SystemSet::on_enter(state)
+                    .with_system(connect.system())
+                    .with_system(setup.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(state)
+                    .with_system(receive.system().label(Receive))
+                    .with_system(predict.system().label(Predict).after(Receive))
+                    .with_system(sync_bodies.system().after(Predict))
+                    .with_system(update_scores.system().after(Receive))
+                    .with_system(leave.system()),
+            )
+            .add_system_set(SystemSet::on_exit(state).with_system(disconnect.system()));
+    }
+}
+
+#[derive(SystemLabel, Clone, Eq, PartialEq, Hash, Debug, Default)]
+struct Receive;
+
+#[derive(SystemLabel, Clone, Eq, PartialEq, Hash, Debug, Default)]
+struct Predict;
+
+/// The connection and everything learned from it. Not `Send`, since not every transport is.
+#[derive(Default)]
+struct NetClient {
+    transport: Option<Box<dyn ClientTransport>>,
+    player: Option<u8>,
+    /// Sent inputs the server hasn't applied yet, oldest first
+    pending: VecDeque<InputFrame>,
+    sequence: u32,
+    /// Time not yet simulated, in seconds
+    accumulator: f32,
+    hello_timer: f32,
+    /// Seconds since the last packet
+    silence: f32,
+    /// Oldest first
+    snapshots: VecDeque<Snapshot>,
+    /// Whether the newest snapshot hasn't been reconciled with yet
+    unreconciled: bool,
+    /// The server tick other things are drawn at. Fractional, to interpolate between snapshots.
+    render_tick: f32,
+    /// Our own ship, flown ahead of the server
+    predicted: Option<Entity>,
+    /// What's been spawned for everything else in the snapshots
+    spawned: HashMap<NetBody, Entity>,
+}
+
+impl NetClient {
+    fn send(&mut self, message: &ClientMessage) {
+        if let Some(transport) = &mut self.transport {
+            match encode(message) {
+                Ok(packet) => transport.send(&packet),
+                Err(e) => error!("Couldn't encode a message: {:?}", e),
+            }
+        }
+    }
+
+    /// Our own ship in the newest snapshot
+    fn own_ship(&self) -> Option<&ShipState> {
+        let player = self.player?;
+        self.snapshots
+            .back()?
+            .ships
+            .iter()
+            .find(|ship| ship.player == player)
+    }
+}
+
+/// Things drawn from snapshots
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+enum NetBody {
+    Ship(u8),
+    Beam(u8),
+    Body(u64, BodyKind),
+}
+
+/// The physics world, stepped here one tick at a time instead of once a frame, so inputs can
+/// be replayed. Only our own ship and the game area's walls are in it.
+#[derive(SystemParam)]
+pub struct Physics<'a> {
+    config: Res<'a, RapierConfiguration>,
+    params: Res<'a, IntegrationParameters>,
+    filters: Res<'a, InteractionPairFilters>,
+    events: Res<'a, EventQueue>,
+    pipeline: ResMut<'a, PhysicsPipeline>,
+    broad_phase: ResMut<'a, BroadPhase>,
+    narrow_phase: ResMut<'a, NarrowPhase>,
+    bodies: ResMut<'a, RigidBodySet>,
+    colliders: ResMut<'a, ColliderSet>,
+    joints: ResMut<'a, JointSet>,
+    ccd: ResMut<'a, CCDSolver>,
+}
+
+impl<'a> Physics<'a> {
+    fn step(&mut self) {
+        let hooks: &dyn PhysicsHooks = match &self.filters.hook {
+            Some(hook) => hook.as_ref(),
+            None => &(),
+        };
+        self.pipeline.step(
+            &self.config.gravity,
+            &self.params,
+            &mut self.broad_phase,
+            &mut self.narrow_phase,
+            &mut self.bodies,
+            &mut self.colliders,
+            &mut self.joints,
+            &mut self.ccd,
+            hooks,
+            &*self.events,
+        );
+    }
+}
+
+/// Fly our ship one tick, the way the server will. `state` is the last the server said of it.
+fn fly_tick(
+    physics: &mut Physics,
+    ship: &RigidBodyHandleComponent,
+    input: &ShipInput,
+    state: &ShipState,
+    movement: &MovementSettings,
+    boost: &BoostSettings,
+) {
+    // The server doesn't know about local flight assist settings
+    let assist = FlightAssist::default();
+    let tuning = movement.tuning(assist);
+    // Energy isn't predicted, so go by the last the server said there was
+    let tuning = if input.boost && (state.boosting || state.energy >= boost.min_energy) {
+        tuning.boosted(boost)
+    } else {
+        *tuning
+    };
+    if let Some(rb) = physics.bodies.get_mut(ship.handle()) {
+        fly(rb, input, &tuning, assist, TICK);
+    }
+    physics.step();
+}
+
+/// Everything spawned while online, despawned when leaving
+struct OnlineScoped;
+
+struct ScoreText;
+
+struct OnlineCamera;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn open(address: &str) -> anyhow::Result<Box<dyn ClientTransport>> {
+    Ok(Box::new(super::udp::UdpClient::connect(address)?))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn open(address: &str) -> anyhow::Result<Box<dyn ClientTransport>> {
+    Ok(Box::new(super::ws::WsClient::connect(address)?))
+}
+
+fn connect(
+    mut client: NonSendMut<NetClient>,
+    settings: Res<NetSettings>,
+    mut config: ResMut<RapierConfiguration>,
+    mut state: ResMut<State<AppState>>,
+    mut windows: ResMut<Windows>,
+    #[cfg(target_arch = "wasm32")] winit_windows: Res<bevy::winit::WinitWindows>,
+) {
+    *client = NetClient::default();
+    // Stepped by `predict` instead
+    config.physics_pipeline_active = false;
+    match open(&settings.address) {
+        Ok(transport) => {
+            info!("Connecting to {}", settings.address);
+            client.transport = Some(transport);
+            set_grab_cursor(
+                windows.get_primary_mut().unwrap(),
+                true,
+                #[cfg(target_arch = "wasm32")]
+                &winit_windows,
+            );
+        }
+        Err(e) => {
+            error!("Couldn't connect to {}: {:?}", settings.address, e);
+            log_error!(state.replace(AppState::Home));
+        }
+    }
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(PerspectiveCameraBundle::default())
+        .insert(OnlineCamera)
+        .insert(OnlineScoped);
+    commands
+        .spawn_bundle(LightBundle {
+            light: Light {
+                intensity: 100_000.,
+                range: 1000.,
+                ..Default::default()
+            },
+            transform: Transform::from_xyz(0., 200., 0.),
+            ..Default::default()
+        })
+        .insert(OnlineScoped);
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(5.0),
+                    left: Val::Px(5.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "Connecting...",
+                TextStyle {
+                    font: asset_server.load(asset!("RobotoCondensed-Regular.ttf")),
+                    font_size: 30.0,
+                    color: Color::rgb(0.5, 0.5, 1.0),
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(ScoreText)
+        .insert(OnlineScoped);
+}
+
+fn receive(mut client: NonSendMut<NetClient>, time: Res<Time>, mut state: ResMut<State<AppState>>) {
+    let packets = match &mut client.transport {
+        Some(transport) => transport.receive(),
+        None => return,
+    };
+    let client = &mut *client;
+    client.silence += time.delta_seconds();
+    if client.player.is_none() {
+        client.hello_timer -= time.delta_seconds();
+        if client.hello_timer <= 0. {
+            client.hello_timer = HELLO_INTERVAL;
+            client.send(&ClientMessage::Hello {
+                version: PROTOCOL_VERSION,
+            });
+        }
+    }
+    for packet in packets {
+        client.silence = 0.;
+        match decode::<ServerMessage>(&packet) {
+            Ok(ServerMessage::Welcome { player }) => {
+                info!("Joined as player {}", player + 1);
+                client.player = Some(player);
+            }
+            Ok(ServerMessage::Rejected { reason }) => {
+                error!("Server rejected us: {}", reason);
+                log_error!(state.replace(AppState::Home));
+                return;
+            }
+            Ok(ServerMessage::Snapshot(snapshot)) => {
+                // Packets can arrive out of order
+                if client
+                    .snapshots
+                    .back()
+                    .map_or(false, |last| last.tick >= snapshot.tick)
+                {
+                    continue;
+                }
+                client.unreconciled = true;
+                client.snapshots.push_back(snapshot);
+                while client.snapshots.len() > SNAPSHOT_BUFFER {
+                    client.snapshots.pop_front();
+                }
+            }
+            Err(e) => warn!("Bad packet from the server: {:?}", e),
+        }
+    }
+    if client.silence > TIMEOUT {
+        error!("Lost connection to the server");
+        log_error!(state.replace(AppState::Home));
+    }
+}
+
+/// Take the server's word for where our ship is, redo every input it hasn't applied yet, then
+/// sample input once per server tick, fly with it, and send it
+fn predict(
+    mut commands: Commands,
+    mut client: NonSendMut<NetClient>,
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    devices: Res<PlayerDevices>,
+    controls: Controls,
+    movement: Res<MovementSettings>,
+    boost: Res<BoostSettings>,
+    windows: Res<Windows>,
+    #[cfg(target_arch = "wasm32")] winit_windows: Res<bevy::winit::WinitWindows>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut physics: Physics,
+    ships: Query<&RigidBodyHandleComponent>,
+    mut camera: Query<&mut Transform, With<OnlineCamera>>,
+    mut mouse: Local<Vec2>,
+) {
+    *mouse += mouse_motion
+        .iter()
+        .fold(Vec2::ZERO, |delta, ev| delta + ev.delta);
+    let client = &mut *client;
+    let player = match client.player {
+        Some(player) => player,
+        None => return,
+    };
+    let state = match client.own_ship().copied() {
+        Some(state) => state,
+        None => {
+            // Destroyed, or waiting for the next round
+            if let Some(ship) = client.predicted.take() {
+                commands.entity(ship).despawn_recursive();
+            }
+            client.pending.clear();
+            return;
+        }
+    };
+    let ship = match client.predicted {
+        Some(ship) => ship,
+        None => {
+            let mut ship =
+                spawn_player_ship(&mut commands, &asset_server, player as usize, MAX_PLAYERS);
+            ship.insert(Transform {
+                translation: state.position.into(),
+                rotation: state.rotation(),
+                scale: Vec3::splat(0.1),
+            });
+            client.predicted = Some(ship.id());
+            return;
+        }
+    };
+    // Its collider is built once its model is loaded
+    let handle = match ships.get(ship) {
+        Ok(handle) => handle,
+        Err(_) => return,
+    };
+
+    if client.unreconciled {
+        client.unreconciled = false;
+        let last_input = client.snapshots.back().map_or(0, |last| last.last_input);
+        while client
+            .pending
+            .front()
+            .map_or(false, |input| input.sequence <= last_input)
+        {
+            client.pending.pop_front();
+        }
+        if let Some(rb) = physics.bodies.get_mut(handle.handle()) {
+            rb.set_position(nalgebra_pos(state.position.into(), state.rotation()), true);
+            rb.set_linvel(Vector::from(Vec3::from(state.linvel)), true);
+            rb.set_angvel(Vector::from(Vec3::from(state.angvel)), true);
+        }
+        for frame in client.pending.iter() {
+            // Only flying is predicted, so what the triggers did before doesn't matter
+            let input = frame.apply(&ShipInput::default());
+            fly_tick(&mut physics, handle, &input, &state, &movement, &boost);
+        }
+    }
+
+    let window = windows.get_primary().unwrap();
+    let locked = cursor_locked(
+        window,
+        #[cfg(target_arch = "wasm32")]
+        &winit_windows,
+    );
+    let size = Vec2::new(window.width(), window.height());
+    client.accumulator += time.delta_seconds();
+    while client.accumulator >= TICK {
+        client.accumulator -= TICK;
+        let input = controls.read(
+            devices.input(0),
+            locked,
+            Some(*mouse).filter(|_| locked),
+            size,
+            true,
+            TICK,
+        );
+        *mouse = Vec2::ZERO;
+        client.sequence += 1;
+        let frame = InputFrame::new(client.sequence, &input);
+        fly_tick(&mut physics, handle, &input, &state, &movement, &boost);
+        client.pending.push_back(frame);
+        while client.pending.len() > MAX_PENDING {
+            client.pending.pop_front();
+        }
+        let recent = client
+            .pending
+            .iter()
+            .rev()
+            .take(INPUT_REDUNDANCY)
+            .rev()
+            .copied()
+            .collect();
+        client.send(&ClientMessage::Input(recent));
+    }
+
+    if let (Some(rb), Ok(mut camera)) = (physics.bodies.get(handle.handle()), camera.single_mut()) {
+        let position = Vec3::from(rb.position().translation.vector);
+        let rotation: Quat = rb.position().rotation.into();
+        *camera = Transform::from_translation(position + rotation * CHASE_OFFSET).looking_at(
+            position + rotation * Vec3::new(0., 0., 20.),
+            rotation * Vec3::Y,
+        );
+    }
+}
+
+/// Draw every other ship, everything else in play, and every beam where it should be right now
+fn sync_bodies(
+    mut commands: Commands,
+    mut client: NonSendMut<NetClient>,
+    time: Res<Time>,
+    looks: Looks,
+    mut transforms: Query<&mut Transform>,
+) {
+    let client = &mut *client;
+    let latest = match client.snapshots.back() {
+        Some(latest) => latest.tick as f32,
+        None => return,
+    };
+    // Run at the server's pace, but drift towards the target delay, or jump there if way off
+    let target = latest - INTERPOLATION_TICKS;
+    client.render_tick += time.delta_seconds() * TICK_RATE as f32;
+    if (client.render_tick - target).abs() > 20. {
+        client.render_tick = target;
+    } else {
+        client.render_tick += (target - client.render_tick) * 0.1;
+    }
+    let render_tick = client.render_tick;
+    let after = client
+        .snapshots
+        .iter()
+        .position(|snapshot| snapshot.tick as f32 >= render_tick)
+        .unwrap_or(client.snapshots.len() - 1);
+    let b = &client.snapshots[after];
+    let a = after
+        .checked_sub(1)
+        .map(|before| &client.snapshots[before])
+        .unwrap_or(b);
+    let alpha = if b.tick > a.tick {
+        ((render_tick - a.tick as f32) / (b.tick - a.tick) as f32).clamp(0., 1.)
+    } else {
+        1.
+    };
+    let lerp = |from: Option<(Vec3, Quat)>, (translation, rotation): (Vec3, Quat)| {
+        let (translation, rotation) = match from {
+            Some(from) => (
+                from.0.lerp(translation, alpha),
+                from.1.slerp(rotation, alpha),
+            ),
+            None => (translation, rotation),
+        };
+        Transform {
+            translation,
+            rotation,
+            ..Default::default()
+        }
+    };
+
+    // Beams are placed in full, everything else keeps the scale it was spawned with
+    let mut wanted: HashMap<NetBody, Transform> = HashMap::new();
+    let own = client
+        .predicted
+        .and_then(|ship| transforms.get_mut(ship).ok())
+        .map(|transform| *transform);
+    for ship in b.ships.iter() {
+        let placed = if Some(ship.player) == client.player {
+            match own {
+                Some(own) => own,
+                None => continue,
+            }
+        } else {
+            let from = a
+                .ships
+                .iter()
+                .find(|from| from.player == ship.player)
+                .map(|from| (Vec3::from(from.position), from.rotation()));
+            let placed = lerp(from, (ship.position.into(), ship.rotation()));
+            wanted.insert(NetBody::Ship(ship.player), placed);
+            placed
+        };
+        if let Some(length) = ship.beam {
+            wanted.insert(NetBody::Beam(ship.player), beam_transform(&placed, length));
+        }
+    }
+    for body in b.bodies.iter() {
+        let from = a
+            .bodies
+            .iter()
+            .find(|from| from.id == body.id)
+            .map(|from| (Vec3::from(from.position), from.rotation()));
+        wanted.insert(
+            NetBody::Body(body.id, body.kind),
+            lerp(from, (body.position.into(), body.rotation())),
+        );
+    }
+
+    client.spawned.retain(|key, &mut entity| {
+        let keep = wanted.contains_key(key);
+        if !keep {
+            commands.entity(entity).despawn_recursive();
+        }
+        keep
+    });
+    for (key, placed) in wanted {
+        let beam = matches!(key, NetBody::Beam(_));
+        match client.spawned.get(&key) {
+            Some(&entity) => {
+                if let Ok(mut transform) = transforms.get_mut(entity) {
+                    *transform = if beam {
+                        placed
+                    } else {
+                        Transform {
+                            scale: transform.scale,
+                            ..placed
+                        }
+                    };
+                }
+            }
+            None => {
+                let bundle = match key {
+                    NetBody::Ship(_) => looks.ship(),
+                    NetBody::Beam(_) => looks.beam(),
+                    NetBody::Body(_, kind) => looks.body(kind),
+                };
+                let transform = if beam {
+                    placed
+                } else {
+                    Transform {
+                        scale: bundle.transform.scale,
+                        ..placed
+                    }
+                };
+                let entity = commands
+                    .spawn_bundle(PbrBundle {
+                        transform,
+                        ..bundle
+                    })
+                    .insert(OnlineScoped)
+                    .id();
+                client.spawned.insert(key, entity);
+            }
+        }
+    }
+}
+
+fn update_scores(client: NonSend<NetClient>, mut query: Query<&mut Text, With<ScoreText>>) {
+    let (player, snapshot) = match (client.player, client.snapshots.back()) {
+        (Some(player), Some(snapshot)) => (player, snapshot),
+        _ => return,
+    };
+    let players: Vec<u8> = snapshot.ships.iter().map(|ship| ship.player).collect();
+    let scores: Vec<_> = snapshot
+        .scores
+        .iter()
+        .enumerate()
+        .filter(|&(i, &score)| players.contains(&(i as u8)) || score > 0)
+        .map(|(i, score)| {
+            if i as u8 == player {
+                format!("P{} (YOU): {}", i + 1, score)
+            } else {
+                format!("P{}: {}", i + 1, score)
+            }
+        })
+        .collect();
+    for mut text in query.iter_mut() {
+        text.sections[0].value = scores.join("   ");
+    }
+}
+
+fn leave(keys: Res<Input<KeyCode>>, mut state: ResMut<State<AppState>>) {
+    if keys.just_pressed(KeyCode::Escape) {
+        log_error!(state.replace(AppState::Home));
+    }
+}
+
+fn disconnect(
+    mut commands: Commands,
+    mut client: NonSendMut<NetClient>,
+    mut config: ResMut<RapierConfiguration>,
+    mut windows: ResMut<Windows>,
+    #[cfg(target_arch = "wasm32")] winit_windows: Res<bevy::winit::WinitWindows>,
+    query: Query<Entity, With<OnlineScoped>>,
+) {
+    client.send(&ClientMessage::Bye);
+    *client = NetClient::default();
+    config.physics_pipeline_active = true;
+    set_grab_cursor(
+        windows.get_primary_mut().unwrap(),
+        false,
+        #[cfg(target_arch = "wasm32")]
+        &winit_windows,
+    );
+    query.for_each(|entity| commands.entity(entity).despawn_recursive());
+}
@@ -0,0 +1,151 @@
+use bevy::math::{Quat, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+use crate::in_game::{BodyKind, ShipInput, ShipReplica};
+
+/// Bumped whenever a message changes, so old clients are turned away instead of misreading
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Ticks a second. The server runs a frame of the game each tick, and clients send an input.
+pub const TICK_RATE: u32 = 60;
+pub const TICK: f32 = 1. / TICK_RATE as f32;
+/// Snapshots are sent every this many ticks
+pub const SNAPSHOT_INTERVAL: u32 = 2;
+pub const SNAPSHOT_RATE: u32 = TICK_RATE / SNAPSHOT_INTERVAL;
+
+/// Inputs the client repeats in every packet, so one lost packet doesn't lose any input
+pub const INPUT_REDUNDANCY: usize = 4;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ClientMessage {
+    Hello {
+        version: u32,
+    },
+    /// The newest inputs, oldest first. Some of them have usually been sent before.
+    Input(Vec<InputFrame>),
+    Bye,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ServerMessage {
+    Welcome {
+        player: u8,
+    },
+    /// No free player slots, or a different protocol version
+    Rejected {
+        reason: String,
+    },
+    Snapshot(Snapshot),
+}
+
+/// One tick of a player's input
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct InputFrame {
+    /// Counts up by one every tick, so the server can tell which inputs it has already applied
+    pub sequence: u32,
+    /// See [`ShipInput`]
+    pub thrust: [f32; 3],
+    pub turn: [f32; 2],
+    pub fire: bool,
+    pub boost: bool,
+    pub laser: bool,
+    pub missile: bool,
+}
+
+impl InputFrame {
+    pub fn new(sequence: u32, input: &ShipInput) -> Self {
+        Self {
+            sequence,
+            thrust: input.thrust.into(),
+            turn: input.turn.into(),
+            fire: input.fire.pressed,
+            boost: input.boost,
+            laser: input.laser,
+            missile: input.missile.pressed,
+        }
+    }
+
+    /// The input this frame asks for, following on from `previous`
+    pub fn apply(&self, previous: &ShipInput) -> ShipInput {
+        ShipInput {
+            thrust: Vec3::from(self.thrust).clamp_length_max(2.),
+            turn: Vec2::from(self.turn).clamp_length_max(1.),
+            fire: previous.fire.then(self.fire),
+            boost: self.boost,
+            laser: self.laser,
+            missile: previous.missile.then(self.missile),
+        }
+    }
+}
+
+/// Everything a client needs to draw one tick of the game
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub tick: u32,
+    /// The last input the server applied for the client this was sent to
+    pub last_input: u32,
+    pub ships: Vec<ShipState>,
+    pub bodies: Vec<BodyState>,
+    /// By player
+    pub scores: Vec<u64>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShipState {
+    pub player: u8,
+    pub position: [f32; 3],
+    pub rotation: [f32; 4],
+    pub linvel: [f32; 3],
+    pub angvel: [f32; 3],
+    pub energy: f32,
+    pub boosting: bool,
+    /// How far its laser reaches, while it's firing
+    pub beam: Option<f32>,
+}
+
+impl ShipState {
+    pub fn rotation(&self) -> Quat {
+        let [x, y, z, w] = self.rotation;
+        Quat::from_xyzw(x, y, z, w)
+    }
+}
+
+impl From<&ShipReplica> for ShipState {
+    fn from(ship: &ShipReplica) -> Self {
+        Self {
+            player: ship.player as u8,
+            position: ship.translation.into(),
+            rotation: ship.rotation.into(),
+            linvel: ship.linvel.into(),
+            angvel: ship.angvel.into(),
+            energy: ship.energy,
+            boosting: ship.boosting,
+            beam: ship.beam,
+        }
+    }
+}
+
+/// Anything other than a ship
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BodyState {
+    /// The same for as long as the body stays in play
+    pub id: u64,
+    pub kind: BodyKind,
+    pub position: [f32; 3],
+    pub rotation: [f32; 4],
+}
+
+impl BodyState {
+    pub fn rotation(&self) -> Quat {
+        let [x, y, z, w] = self.rotation;
+        Quat::from_xyzw(x, y, z, w)
+    }
+}
+
+pub fn encode<T: Serialize>(message: &T) -> anyhow::Result<Vec<u8>> {
+    Ok(postcard::to_stdvec(message)?)
+}
+
+pub fn decode<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> anyhow::Result<T> {
+    Ok(postcard::from_bytes(bytes)?)
+}
@@ -0,0 +1,612 @@
+//! The dedicated server. It runs the same game as local play, headless, at a fixed tick rate.
+//! Each client's inputs fly their ship in order, and everyone gets a snapshot every few ticks.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use bevy::app::AppBuilder;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+
+use crate::audio::AudioCue;
+use crate::in_game::{
+    spawn_player_ship, Player, Points, ReadInput, Replica, ReplicaPlugin, ShipInput,
+    SimulationPlugin, VersusSettings,
+};
+use crate::players::MAX_PLAYERS;
+use crate::AppState;
+
+use super::protocol::{
+    decode, encode, ClientMessage, InputFrame, ServerMessage, Snapshot, PROTOCOL_VERSION,
+    SNAPSHOT_INTERVAL, TICK,
+};
+use super::transport::{ClientId, Combined, ServerTransport};
+
+/// Clients that haven't sent anything for this long are dropped
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Inputs buffered beyond this are dropped, so a client that got ahead can't build up lag
+const MAX_QUEUED_INPUTS: usize = 8;
+/// Seconds between every ship being destroyed and the next round
+const RESTART_DELAY: f32 = 3.;
+
+/// Whether players can shoot each other
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Mode {
+    Coop,
+    Versus,
+}
+
+struct Client {
+    player: usize,
+    inputs: VecDeque<InputFrame>,
+    /// The newest input received, applied or not
+    last_received: u32,
+    /// The last input that was applied, repeated while the queue is empty
+    last_applied: InputFrame,
+    last_heard: Instant,
+}
+
+pub struct Server<T> {
+    transport: T,
+    game: App,
+    clients: HashMap<ClientId, Client>,
+    tick: u32,
+}
+
+impl<T: ServerTransport> Server<T> {
+    /// Runs the game in `app`, which is otherwise empty but for maybe logging
+    pub fn new(transport: T, mut app: AppBuilder, mode: Mode) -> Self {
+        app.add_plugin(bevy::core::CorePlugin)
+            .add_plugin(bevy::transform::TransformPlugin)
+            .add_plugin(bevy::asset::AssetPlugin)
+            .add_plugin(crate::custom_asset::CustomAssetPlugin)
+            .add_plugin(crate::util::UtilPlugin)
+            .add_asset::<Mesh>()
+            .add_asset::<StandardMaterial>()
+            .init_asset_loader::<SkipModels>()
+            .add_event::<AudioCue>()
+            .add_state(AppState::Home)
+            .insert_resource(VersusSettings {
+                enabled: mode == Mode::Versus,
+                ..Default::default()
+            })
+            .init_resource::<Pilots>()
+            .init_resource::<Round>()
+            .add_plugin(SimulationPlugin(AppState::InGame))
+            .add_plugin(ReplicaPlugin)
+            .add_system_set(SystemSet::on_update(AppState::Home).with_system(start.system()))
+            .add_system_set(SystemSet::on_enter(AppState::InGame).with_system(new_round.system()))
+            .add_system_set(
+                SystemSet::on_update(AppState::InGame)
+                    .with_system(seat.system())
+                    .with_system(steer.system().label(ReadInput)),
+            )
+            .add_system_set(SystemSet::on_update(AppState::End).with_system(restart.system()));
+        Self {
+            transport,
+            game: app.app,
+            clients: HashMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Run ticks forever
+    pub fn run(&mut self) {
+        let tick = Duration::from_secs_f32(TICK);
+        let mut next = Instant::now();
+        loop {
+            self.tick();
+            next += tick;
+            let now = Instant::now();
+            if next > now {
+                std::thread::sleep(next - now);
+            } else {
+                // Too far behind to catch up, e.g. after the machine slept
+                next = now;
+            }
+        }
+    }
+
+    pub fn tick(&mut self) {
+        for (id, packet) in self.transport.receive() {
+            match decode::<ClientMessage>(&packet) {
+                Ok(message) => self.handle(id, message),
+                Err(e) => {
+                    println!("Bad packet from {:?}: {}", id, e);
+                    // Otherwise the transport keeps whoever sent it around for good
+                    if !self.clients.contains_key(&id) {
+                        self.transport.disconnect(id);
+                    }
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let timed_out: Vec<_> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| now - client.last_heard > TIMEOUT)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in timed_out {
+            println!("{:?} timed out", id);
+            self.remove(id);
+        }
+
+        let mut pilots = Pilots::default();
+        for client in self.clients.values_mut() {
+            if let Some(input) = client.inputs.pop_front() {
+                client.last_applied = input;
+            }
+            pilots.0[client.player] = Some(client.last_applied);
+        }
+        self.game.world.insert_resource(pilots);
+        self.game.update();
+
+        self.tick += 1;
+        if self.tick % SNAPSHOT_INTERVAL != 0 {
+            return;
+        }
+        let world = &self.game.world;
+        let replica = world.get_resource::<Replica>().unwrap();
+        let snapshot = Snapshot {
+            tick: self.tick,
+            last_input: 0,
+            ships: replica.ships.iter().map(Into::into).collect(),
+            bodies: replica
+                .bodies
+                .iter()
+                .map(|body| super::protocol::BodyState {
+                    id: body.id,
+                    kind: body.kind,
+                    position: body.translation.into(),
+                    rotation: body.rotation.into(),
+                })
+                .collect(),
+            scores: world.get_resource::<Points>().unwrap().players.to_vec(),
+        };
+        for (&id, client) in self.clients.iter() {
+            let snapshot = Snapshot {
+                last_input: client.last_applied.sequence,
+                ..snapshot.clone()
+            };
+            match encode(&ServerMessage::Snapshot(snapshot)) {
+                Ok(packet) => self.transport.send(id, &packet),
+                Err(e) => println!("Couldn't encode a snapshot: {}", e),
+            }
+        }
+    }
+
+    fn handle(&mut self, id: ClientId, message: ClientMessage) {
+        match self.clients.get_mut(&id) {
+            Some(client) => client.last_heard = Instant::now(),
+            // Someone who never said hello, or was already dropped. The transport would otherwise
+            // keep their address around for good.
+            None if !matches!(message, ClientMessage::Hello { .. }) => {
+                self.transport.disconnect(id);
+                return;
+            }
+            None => {}
+        }
+        match message {
+            ClientMessage::Hello { version } => {
+                if let Some(client) = self.clients.get(&id) {
+                    // The welcome was probably lost
+                    let player = client.player as u8;
+                    self.send(id, &ServerMessage::Welcome { player });
+                    return;
+                }
+                if version != PROTOCOL_VERSION {
+                    self.reject(
+                        id,
+                        format!("Server runs protocol version {}", PROTOCOL_VERSION),
+                    );
+                    return;
+                }
+                let free = (0..MAX_PLAYERS)
+                    .find(|&player| self.clients.values().all(|client| client.player != player));
+                let player = match free {
+                    Some(player) => player,
+                    None => {
+                        self.reject(id, "Server is full".to_string());
+                        return;
+                    }
+                };
+                println!("{:?} joined as player {}", id, player + 1);
+                self.clients.insert(
+                    id,
+                    Client {
+                        player,
+                        inputs: VecDeque::new(),
+                        last_received: 0,
+                        last_applied: InputFrame::default(),
+                        last_heard: Instant::now(),
+                    },
+                );
+                self.send(
+                    id,
+                    &ServerMessage::Welcome {
+                        player: player as u8,
+                    },
+                );
+            }
+            ClientMessage::Input(frames) => {
+                if let Some(client) = self.clients.get_mut(&id) {
+                    for frame in frames {
+                        if frame.sequence > client.last_received {
+                            client.last_received = frame.sequence;
+                            client.inputs.push_back(frame);
+                        }
+                    }
+                    while client.inputs.len() > MAX_QUEUED_INPUTS {
+                        client.inputs.pop_front();
+                    }
+                }
+            }
+            ClientMessage::Bye => {
+                println!("{:?} left", id);
+                self.remove(id);
+            }
+        }
+    }
+
+    fn send(&mut self, id: ClientId, message: &ServerMessage) {
+        match encode(message) {
+            Ok(packet) => self.transport.send(id, &packet),
+            Err(e) => println!("Couldn't encode a message: {}", e),
+        }
+    }
+
+    fn reject(&mut self, id: ClientId, reason: String) {
+        println!("Rejected {:?}: {}", id, reason);
+        self.send(id, &ServerMessage::Rejected { reason });
+        self.transport.disconnect(id);
+    }
+
+    fn remove(&mut self, id: ClientId) {
+        // Their ship goes with the next tick
+        self.clients.remove(&id);
+        self.transport.disconnect(id);
+    }
+}
+
+/// The newest input of the player in each slot, if anyone's in it
+#[derive(Default)]
+struct Pilots([Option<InputFrame>; MAX_PLAYERS]);
+
+impl Pilots {
+    fn any(&self) -> bool {
+        self.0.iter().any(Option::is_some)
+    }
+}
+
+/// Who has had a ship this round, so a ship that was destroyed stays that way until the next
+#[derive(Default)]
+struct Round {
+    flown: [bool; MAX_PLAYERS],
+    /// Seconds since every ship was destroyed
+    over_for: f32,
+}
+
+/// Models are only drawn by clients, so the server doesn't load them. Colliders come from the
+/// `.custom` files instead.
+#[derive(Default)]
+struct SkipModels;
+
+impl AssetLoader for SkipModels {
+    fn load<'a>(
+        &'a self,
+        _bytes: &'a [u8],
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["glb"]
+    }
+}
+
+/// Start a round once someone joins
+fn start(pilots: Res<Pilots>, mut state: ResMut<State<AppState>>) {
+    if pilots.any() {
+        log_error!(state.replace(AppState::InGame));
+    }
+}
+
+fn new_round(mut round: ResMut<Round>) {
+    *round = Round::default();
+}
+
+/// Give everyone who joined a ship, and take away the ships of those who left. The game waits on
+/// the Home state while nobody's there.
+fn seat(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    pilots: Res<Pilots>,
+    mut round: ResMut<Round>,
+    mut state: ResMut<State<AppState>>,
+    ships: Query<(Entity, &Player)>,
+) {
+    if !pilots.any() {
+        log_error!(state.replace(AppState::Home));
+        return;
+    }
+    for (entity, player) in ships.iter() {
+        if pilots.0[player.0].is_none() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    for (player, pilot) in pilots.0.iter().enumerate() {
+        if pilot.is_some() && !round.flown[player] {
+            round.flown[player] = true;
+            spawn_player_ship(&mut commands, &asset_server, player, MAX_PLAYERS);
+        }
+    }
+}
+
+/// Fly each ship with its pilot's input, in place of reading devices like local play does
+fn steer(pilots: Res<Pilots>, mut ships: Query<(&Player, &mut ShipInput)>) {
+    for (player, mut input) in ships.iter_mut() {
+        *input = match &pilots.0[player.0] {
+            Some(frame) => frame.apply(&input),
+            None => ShipInput::default(),
+        };
+    }
+}
+
+/// Start the next round a little after every ship is destroyed
+fn restart(
+    time: Res<Time>,
+    pilots: Res<Pilots>,
+    mut round: ResMut<Round>,
+    mut state: ResMut<State<AppState>>,
+) {
+    round.over_for += time.delta_seconds();
+    if !pilots.any() {
+        log_error!(state.replace(AppState::Home));
+    } else if round.over_for >= RESTART_DELAY {
+        log_error!(state.replace(AppState::InGame));
+    }
+}
+
+/// Entry point of the `server` binary. Takes the UDP address to listen on, `--ws` followed by
+/// the WebSocket one, and `--versus`.
+pub fn main() -> anyhow::Result<()> {
+    let mut address = super::DEFAULT_ADDRESS.to_string();
+    let mut ws_address = super::DEFAULT_WS_ADDRESS.to_string();
+    let mut mode = Mode::Coop;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--versus" => mode = Mode::Versus,
+            "--coop" => mode = Mode::Coop,
+            "--ws" => {
+                ws_address = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("--ws needs an address"))?
+            }
+            _ => address = arg,
+        }
+    }
+    let udp = super::udp::UdpServer::bind(&address)?;
+    let ws = super::ws::WsServer::bind(&ws_address)?;
+    println!(
+        "Listening on {} (UDP) and ws://{} ({:?})",
+        udp.local_addr()?,
+        ws.local_addr()?,
+        mode
+    );
+    let mut app = App::build();
+    app.add_plugin(bevy::log::LogPlugin);
+    Server::new(Combined(udp, ws), app, mode).run();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::transport::ClientTransport;
+    use super::super::udp::{UdpClient, UdpServer};
+    use super::*;
+
+    /// Ticks the server until the client gets a message `found` picks out
+    fn tick_until<T>(
+        server: &mut Server<UdpServer>,
+        client: &mut UdpClient,
+        mut found: impl FnMut(ServerMessage) -> Option<T>,
+    ) -> T {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            server.tick();
+            std::thread::sleep(Duration::from_millis(5));
+            for packet in client.receive() {
+                if let Some(found) = found(decode(&packet).unwrap()) {
+                    return found;
+                }
+            }
+        }
+        panic!("Nothing came back over loopback");
+    }
+
+    fn send(client: &mut UdpClient, message: &ClientMessage) {
+        client.send(&encode(message).unwrap());
+    }
+
+    /// Hands the server canned packets, and remembers who it disconnected
+    #[derive(Default)]
+    struct Scripted {
+        incoming: Vec<(ClientId, Vec<u8>)>,
+        disconnected: Vec<ClientId>,
+    }
+
+    impl ServerTransport for Scripted {
+        fn receive(&mut self) -> Vec<(ClientId, Vec<u8>)> {
+            std::mem::take(&mut self.incoming)
+        }
+
+        fn send(&mut self, _to: ClientId, _packet: &[u8]) {}
+
+        fn disconnect(&mut self, client: ClientId) {
+            self.disconnected.push(client);
+        }
+    }
+
+    #[test]
+    fn forgets_strangers_sending_garbage() {
+        let mut server = Server::new(Scripted::default(), App::build(), Mode::Coop);
+        server.transport.incoming.push((ClientId(1), vec![0xff; 3]));
+        server.transport.incoming.push((
+            ClientId(2),
+            encode(&ClientMessage::Hello { version: 0 }).unwrap(),
+        ));
+        server.tick();
+        assert_eq!(
+            server.transport.disconnected,
+            vec![ClientId(1), ClientId(2)]
+        );
+        assert!(server.clients.is_empty());
+    }
+
+    #[test]
+    fn plays_over_loopback() {
+        let transport = UdpServer::bind("127.0.0.1:0").unwrap();
+        let address = transport.local_addr().unwrap();
+        let mut server = Server::new(transport, App::build(), Mode::Coop);
+        let mut client = UdpClient::connect(address).unwrap();
+
+        send(
+            &mut client,
+            &ClientMessage::Hello {
+                version: PROTOCOL_VERSION,
+            },
+        );
+        let player = tick_until(&mut server, &mut client, |message| match message {
+            ServerMessage::Welcome { player } => Some(player),
+            _ => None,
+        });
+        assert_eq!(player, 0);
+
+        let frames = (1..=3)
+            .map(|sequence| InputFrame {
+                sequence,
+                thrust: [0., 0., 1.],
+                ..Default::default()
+            })
+            .collect();
+        send(&mut client, &ClientMessage::Input(frames));
+        // One input is applied per tick, so by the fifth they have all been, and a snapshot has
+        // gone out since
+        for _ in 0..5 {
+            std::thread::sleep(Duration::from_millis(5));
+            server.tick();
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        let last = client
+            .receive()
+            .into_iter()
+            .rev()
+            .find_map(|packet| match decode(&packet).unwrap() {
+                ServerMessage::Snapshot(snapshot) => Some(snapshot),
+                _ => None,
+            })
+            .expect("No snapshot came back");
+        assert_eq!(last.last_input, 3);
+    }
+
+    #[test]
+    fn flies_ships_in_the_game_itself() {
+        let transport = UdpServer::bind("127.0.0.1:0").unwrap();
+        let address = transport.local_addr().unwrap();
+        let mut server = Server::new(transport, App::build(), Mode::Coop);
+        let mut client = UdpClient::connect(address).unwrap();
+        send(
+            &mut client,
+            &ClientMessage::Hello {
+                version: PROTOCOL_VERSION,
+            },
+        );
+        tick_until(&mut server, &mut client, |message| match message {
+            ServerMessage::Welcome { .. } => Some(()),
+            _ => None,
+        });
+
+        // The ship only gets going once its collider has loaded
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut sequence = 0;
+        let ship =
+            loop {
+                assert!(Instant::now() < deadline, "The ship never got going");
+                sequence += 1;
+                let frame = InputFrame {
+                    sequence,
+                    thrust: [0., 0., 1.],
+                    ..Default::default()
+                };
+                send(&mut client, &ClientMessage::Input(vec![frame]));
+                server.tick();
+                std::thread::sleep(Duration::from_millis(5));
+                let moving = client.receive().into_iter().find_map(|packet| {
+                    match decode(&packet).unwrap() {
+                        ServerMessage::Snapshot(snapshot) => snapshot
+                            .ships
+                            .into_iter()
+                            .find(|ship| ship.player == 0 && ship.linvel != [0.; 3]),
+                        _ => None,
+                    }
+                });
+                if let Some(ship) = moving {
+                    break ship;
+                }
+            };
+        assert!(ship.energy > 0.);
+    }
+
+    #[test]
+    fn plays_over_websockets() {
+        use super::super::ws::WsServer;
+        use std::net::TcpStream;
+        use tungstenite::Message;
+
+        let transport = WsServer::bind("127.0.0.1:0").unwrap();
+        let address = transport.local_addr().unwrap();
+        let mut server = Server::new(transport, App::build(), Mode::Coop);
+        let (connected, handshake) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let url = format!("ws://{}/", address);
+            let stream = TcpStream::connect(address).unwrap();
+            connected
+                .send(tungstenite::client(url.as_str(), stream).unwrap())
+                .unwrap();
+        });
+        // The handshake only gets answered while the server ticks
+        let (mut client, _) = loop {
+            server.tick();
+            std::thread::sleep(Duration::from_millis(5));
+            if let Ok(client) = handshake.try_recv() {
+                break client;
+            }
+        };
+        client.get_mut().set_nonblocking(true).unwrap();
+
+        let hello = encode(&ClientMessage::Hello {
+            version: PROTOCOL_VERSION,
+        })
+        .unwrap();
+        client.write_message(Message::Binary(hello)).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            server.tick();
+            std::thread::sleep(Duration::from_millis(5));
+            while let Ok(message) = client.read_message() {
+                if let Message::Binary(packet) = message {
+                    if let ServerMessage::Welcome { player } = decode(&packet).unwrap() {
+                        assert_eq!(player, 0);
+                        return;
+                    }
+                }
+            }
+        }
+        panic!("Nothing came back over the WebSocket");
+    }
+}
@@ -0,0 +1,60 @@
+//! How packets get between clients and the server. The game only deals in whole messages, so
+//! any transport that can send and receive byte packets without blocking will do.
+
+/// A client, as far as the server's transport can tell them apart
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct ClientId(pub u64);
+
+#[cfg(not(target_arch = "wasm32"))]
+pub trait ServerTransport {
+    /// Every packet received since the last call
+    fn receive(&mut self) -> Vec<(ClientId, Vec<u8>)>;
+    fn send(&mut self, to: ClientId, packet: &[u8]);
+    /// Forget about a client that left or timed out
+    fn disconnect(&mut self, client: ClientId);
+}
+
+/// Two transports served as one, so clients on either can play together. Even IDs are the
+/// first transport's clients and odd ones the second's.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Combined<A, B>(pub A, pub B);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<A: ServerTransport, B: ServerTransport> ServerTransport for Combined<A, B> {
+    fn receive(&mut self) -> Vec<(ClientId, Vec<u8>)> {
+        let first = self
+            .0
+            .receive()
+            .into_iter()
+            .map(|(id, packet)| (ClientId(id.0 * 2), packet));
+        let second = self
+            .1
+            .receive()
+            .into_iter()
+            .map(|(id, packet)| (ClientId(id.0 * 2 + 1), packet));
+        first.chain(second).collect()
+    }
+
+    fn send(&mut self, to: ClientId, packet: &[u8]) {
+        if to.0 % 2 == 0 {
+            self.0.send(ClientId(to.0 / 2), packet)
+        } else {
+            self.1.send(ClientId(to.0 / 2), packet)
+        }
+    }
+
+    fn disconnect(&mut self, client: ClientId) {
+        if client.0 % 2 == 0 {
+            self.0.disconnect(ClientId(client.0 / 2))
+        } else {
+            self.1.disconnect(ClientId(client.0 / 2))
+        }
+    }
+}
+
+pub trait ClientTransport {
+    /// Every packet received since the last call
+    fn receive(&mut self) -> Vec<Vec<u8>>;
+    fn send(&mut self, packet: &[u8]);
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use anyhow::Context;
+use bevy::log::debug;
+
+use super::transport::{ClientId, ClientTransport, ServerTransport};
+
+/// Larger than any snapshot the server sends
+const MAX_PACKET: usize = 64 * 1024;
+
+pub struct UdpServer {
+    socket: UdpSocket,
+    clients: HashMap<ClientId, SocketAddr>,
+    ids: HashMap<SocketAddr, ClientId>,
+    next_id: u64,
+    buffer: Vec<u8>,
+}
+
+impl UdpServer {
+    pub fn bind(address: impl ToSocketAddrs) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(address).context("Couldn't bind the server socket")?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            clients: HashMap::new(),
+            ids: HashMap::new(),
+            next_id: 0,
+            buffer: vec![0; MAX_PACKET],
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+impl ServerTransport for UdpServer {
+    fn receive(&mut self) -> Vec<(ClientId, Vec<u8>)> {
+        let mut packets = Vec::new();
+        loop {
+            match self.socket.recv_from(&mut self.buffer) {
+                Ok((len, address)) => {
+                    let id = match self.ids.get(&address) {
+                        Some(&id) => id,
+                        None => {
+                            self.next_id += 1;
+                            let id = ClientId(self.next_id);
+                            self.ids.insert(address, id);
+                            self.clients.insert(id, address);
+                            id
+                        }
+                    };
+                    packets.push((id, self.buffer[..len].to_vec()));
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // e.g. a client went away and its port is unreachable
+                Err(e) => {
+                    debug!("Receive failed: {}", e);
+                    break;
+                }
+            }
+        }
+        packets
+    }
+
+    fn send(&mut self, to: ClientId, packet: &[u8]) {
+        if let Some(address) = self.clients.get(&to) {
+            if let Err(e) = self.socket.send_to(packet, address) {
+                debug!("Send to {} failed: {}", address, e);
+            }
+        }
+    }
+
+    fn disconnect(&mut self, client: ClientId) {
+        if let Some(address) = self.clients.remove(&client) {
+            self.ids.remove(&address);
+        }
+    }
+}
+
+pub struct UdpClient {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+}
+
+impl UdpClient {
+    pub fn connect(server: impl ToSocketAddrs) -> anyhow::Result<Self> {
+        let server = server
+            .to_socket_addrs()?
+            .next()
+            .context("Server address didn't resolve")?;
+        let local: SocketAddr = if server.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(server)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            buffer: vec![0; MAX_PACKET],
+        })
+    }
+}
+
+impl ClientTransport for UdpClient {
+    fn receive(&mut self) -> Vec<Vec<u8>> {
+        let mut packets = Vec::new();
+        loop {
+            match self.socket.recv(&mut self.buffer) {
+                Ok(len) => packets.push(self.buffer[..len].to_vec()),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    debug!("Receive failed: {}", e);
+                    break;
+                }
+            }
+        }
+        packets
+    }
+
+    fn send(&mut self, packet: &[u8]) {
+        if let Err(e) = self.socket.send(packet) {
+            debug!("Send failed: {}", e);
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! WebSockets, for browsers, which can't send UDP. Every message is one binary frame.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::WsServer;
+#[cfg(target_arch = "wasm32")]
+pub use web::WsClient;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::collections::HashMap;
+    use std::io::ErrorKind;
+    use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+    use std::time::{Duration, Instant};
+
+    use anyhow::Context;
+    use bevy::log::debug;
+    use tungstenite::handshake::server::{NoCallback, ServerHandshake};
+    use tungstenite::handshake::{HandshakeError, MidHandshake};
+    use tungstenite::protocol::WebSocketConfig;
+    use tungstenite::{Error, Message, WebSocket};
+
+    use crate::net::transport::{ClientId, ServerTransport};
+
+    /// How long a new connection gets to finish its handshake before it's dropped
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+    /// Messages a client can have waiting to be sent, about a second of snapshots. One that
+    /// falls this far behind is dropped, as everything queued is already stale.
+    const MAX_SEND_QUEUE: usize = crate::net::protocol::SNAPSHOT_RATE as usize;
+
+    type Role = ServerHandshake<TcpStream, NoCallback>;
+
+    pub struct WsServer {
+        listener: TcpListener,
+        clients: HashMap<ClientId, WebSocket<TcpStream>>,
+        /// Connections still handshaking, and when they were accepted. Each is picked up again
+        /// on every `receive` until it's done, so a slow one never holds up the tick.
+        handshakes: Vec<(MidHandshake<Role>, Instant)>,
+        next_id: u64,
+    }
+
+    impl WsServer {
+        pub fn bind(address: impl ToSocketAddrs) -> anyhow::Result<Self> {
+            let listener =
+                TcpListener::bind(address).context("Couldn't bind the WebSocket listener")?;
+            listener.set_nonblocking(true)?;
+            Ok(Self {
+                listener,
+                clients: HashMap::new(),
+                handshakes: Vec::new(),
+                next_id: 0,
+            })
+        }
+
+        pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+            self.listener.local_addr()
+        }
+
+        fn accept(&mut self, stream: TcpStream) -> anyhow::Result<()> {
+            stream.set_nonblocking(true)?;
+            stream.set_nodelay(true)?;
+            let config = WebSocketConfig {
+                max_send_queue: Some(MAX_SEND_QUEUE),
+                ..Default::default()
+            };
+            self.shake(
+                tungstenite::server::accept_with_config(stream, Some(config)),
+                Instant::now(),
+            );
+            Ok(())
+        }
+
+        /// Keep a connection whose handshake finished, or hold on to it until the next try
+        fn shake(
+            &mut self,
+            result: Result<WebSocket<TcpStream>, HandshakeError<Role>>,
+            accepted: Instant,
+        ) {
+            match result {
+                Ok(socket) => {
+                    self.next_id += 1;
+                    self.clients.insert(ClientId(self.next_id), socket);
+                }
+                Err(HandshakeError::Interrupted(handshake)) => {
+                    if accepted.elapsed() < HANDSHAKE_TIMEOUT {
+                        self.handshakes.push((handshake, accepted));
+                    } else {
+                        debug!("WebSocket handshake timed out");
+                    }
+                }
+                Err(HandshakeError::Failure(e)) => debug!("WebSocket handshake failed: {}", e),
+            }
+        }
+    }
+
+    fn would_block(e: &Error) -> bool {
+        matches!(e, Error::Io(e) if e.kind() == ErrorKind::WouldBlock)
+    }
+
+    impl ServerTransport for WsServer {
+        fn receive(&mut self) -> Vec<(ClientId, Vec<u8>)> {
+            for (handshake, accepted) in std::mem::take(&mut self.handshakes) {
+                self.shake(handshake.handshake(), accepted);
+            }
+            loop {
+                match self.listener.accept() {
+                    Ok((stream, address)) => {
+                        if let Err(e) = self.accept(stream) {
+                            debug!("Couldn't accept {}: {:?}", address, e);
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        debug!("Accept failed: {}", e);
+                        break;
+                    }
+                }
+            }
+
+            let mut packets = Vec::new();
+            let mut closed = Vec::new();
+            for (&id, socket) in self.clients.iter_mut() {
+                loop {
+                    match socket.read_message() {
+                        Ok(Message::Binary(packet)) => packets.push((id, packet)),
+                        // Pings are answered by tungstenite
+                        Ok(Message::Close(_)) => {
+                            closed.push(id);
+                            break;
+                        }
+                        Ok(_) => {}
+                        Err(e) if would_block(&e) => break,
+                        Err(e) => {
+                            debug!("Receive from {:?} failed: {}", id, e);
+                            closed.push(id);
+                            break;
+                        }
+                    }
+                }
+                // Finish sending anything that didn't fit last time
+                match socket.write_pending() {
+                    Err(e) if !would_block(&e) => debug!("Send to {:?} failed: {}", id, e),
+                    _ => {}
+                }
+            }
+            // The server notices them leave by timing them out, like with UDP
+            for id in closed {
+                self.clients.remove(&id);
+            }
+            packets
+        }
+
+        fn send(&mut self, to: ClientId, packet: &[u8]) {
+            if let Some(socket) = self.clients.get_mut(&to) {
+                match socket.write_message(Message::Binary(packet.to_vec())) {
+                    // Queued, and sent by `write_pending` later
+                    Err(e) if would_block(&e) => {}
+                    // Like a closed socket, the server times them out
+                    Err(Error::SendQueueFull(_)) => {
+                        debug!("{:?} fell too far behind", to);
+                        self.clients.remove(&to);
+                    }
+                    Err(e) => debug!("Send to {:?} failed: {}", to, e),
+                    Ok(()) => {}
+                }
+            }
+        }
+
+        fn disconnect(&mut self, client: ClientId) {
+            if let Some(mut socket) = self.clients.remove(&client) {
+                let _ = socket.close(None);
+                let _ = socket.write_pending();
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn drops_clients_that_stop_reading() {
+            let mut server = WsServer::bind("127.0.0.1:0").unwrap();
+            let url = format!("ws://{}/", server.local_addr().unwrap());
+            let address = server.local_addr().unwrap();
+            let client = std::thread::spawn(move || {
+                tungstenite::client(url.as_str(), TcpStream::connect(address).unwrap()).unwrap()
+            });
+            while server.clients.is_empty() {
+                server.receive();
+                std::thread::sleep(Duration::from_millis(5));
+            }
+            // Kept open, but never read from
+            let _client = client.join().unwrap();
+
+            let id = *server.clients.keys().next().unwrap();
+            let packet = vec![0; 64 * 1024];
+            for _ in 0..10_000 {
+                server.send(id, &packet);
+                if server.clients.is_empty() {
+                    return;
+                }
+            }
+            panic!("A client that never reads was kept");
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use bevy::log::debug;
+    use wasm_bindgen::closure::Closure;
+    use wasm_bindgen::JsCast;
+    use web_sys::{BinaryType, MessageEvent, WebSocket};
+
+    use crate::net::transport::ClientTransport;
+
+    pub struct WsClient {
+        socket: WebSocket,
+        received: Rc<RefCell<Vec<Vec<u8>>>>,
+        /// Kept so the browser can keep calling it
+        _on_message: Closure<dyn FnMut(MessageEvent)>,
+    }
+
+    impl WsClient {
+        /// Start connecting to a `ws://` URL. Messages sent before it's open are dropped, like
+        /// lost packets.
+        pub fn connect(url: &str) -> anyhow::Result<Self> {
+            let socket = WebSocket::new(url)
+                .map_err(|e| anyhow::anyhow!("Couldn't open a WebSocket: {:?}", e))?;
+            socket.set_binary_type(BinaryType::Arraybuffer);
+            let received = Rc::new(RefCell::new(Vec::new()));
+            let on_message = {
+                let received = received.clone();
+                Closure::wrap(Box::new(move |event: MessageEvent| {
+                    if let Ok(buffer) = event.data().dyn_into::<js_sys::ArrayBuffer>() {
+                        received
+                            .borrow_mut()
+                            .push(js_sys::Uint8Array::new(&buffer).to_vec());
+                    }
+                }) as Box<dyn FnMut(MessageEvent)>)
+            };
+            socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+            Ok(Self {
+                socket,
+                received,
+                _on_message: on_message,
+            })
+        }
+    }
+
+    impl ClientTransport for WsClient {
+        fn receive(&mut self) -> Vec<Vec<u8>> {
+            std::mem::take(&mut *self.received.borrow_mut())
+        }
+
+        fn send(&mut self, packet: &[u8]) {
+            if self.socket.ready_state() != WebSocket::OPEN {
+                return;
+            }
+            if let Err(e) = self.socket.send_with_u8_array(packet) {
+                debug!("Send failed: {:?}", e);
+            }
+        }
+    }
+
+    impl Drop for WsClient {
+        fn drop(&mut self) {
+            self.socket.set_onmessage(None);
+            let _ = self.socket.close();
+        }
+    }
+}
@@ -1,217 +1,57 @@
-#[cfg(not(target_arch = "wasm32"))]
-use bevy::app::AppExit;
-use bevy::ecs::system::EntityCommands;
 use bevy::prelude::*;
 
-use crate::util::{cursor_locked, set_grab_cursor};
+use crate::players::PlayerDevices;
+use crate::ui::{Menu, MenuAction, MenuAlign, MenuButton, MenuPlugin, MenuTitle};
+use crate::util::cursor_locked;
+use crate::AppState;
 
 pub struct PausePlugin;
 
 impl Plugin for PausePlugin {
     fn build(&self, app: &mut AppBuilder) {
-        let update_set = SystemSet::on_update(crate::AppState::Paused)
-            .with_system(button_system.system())
-            .with_system(resume_button_system.system())
-            .with_system(resume.system())
-            .with_system(quit_to_title.system());
-        #[cfg(not(target_arch = "wasm32"))]
-        let update_set = update_set.with_system(quit_button_system.system());
-        app.init_resource::<ButtonMaterials>()
-            .add_system_set(
-                SystemSet::on_enter(crate::AppState::Paused).with_system(setup_menu.system()),
-            )
-            .add_system_set(update_set)
-            .add_system_set(
-                SystemSet::on_exit(crate::AppState::Paused).with_system(remove_menu.system()),
-            );
-    }
-}
-
-struct ButtonMaterials {
-    normal: Handle<ColorMaterial>,
-    hovered: Handle<ColorMaterial>,
-    pressed: Handle<ColorMaterial>,
-}
-
-impl FromWorld for ButtonMaterials {
-    fn from_world(world: &mut World) -> Self {
-        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
-        ButtonMaterials {
-            normal: materials.add(Color::rgba(0.15, 0.15, 0.15, 0.).into()),
-            hovered: materials.add(Color::rgba(0.25, 0.25, 0.25, 0.9).into()),
-            pressed: materials.add(Color::rgb(0.2, 0.2, 0.2).into()),
-        }
-    }
-}
-
-fn create_button<'a, 'c>(
-    parent: &'c mut ChildBuilder<'a, '_>,
-    button_materials: &ButtonMaterials,
-    font: Handle<Font>,
-    text: &str,
-) -> EntityCommands<'a, 'c> {
-    let mut button = parent.spawn_bundle(ButtonBundle {
-        style: Style {
-            size: Size::new(Val::Percent(100.), Val::Auto),
-            padding: Rect::all(Val::Px(5.)),
-            margin: Rect::all(Val::Auto),
-            justify_content: JustifyContent::FlexStart,
-            align_items: AlignItems::Center,
-            ..Default::default()
-        },
-        material: button_materials.normal.clone(),
-        ..Default::default()
-    });
-    button.with_children(|parent| {
-        parent.spawn_bundle(TextBundle {
-            text: Text::with_section(
-                text,
-                TextStyle {
-                    font,
-                    font_size: 40.0,
-                    color: Color::rgb(0.65, 0.65, 0.65),
-                },
-                Default::default(),
-            ),
-            ..Default::default()
-        });
-    });
-    button
-}
-
-struct PartOfUi;
-
-struct ResumeButton;
-
-struct QuitToTitleButton;
-
-#[cfg(not(target_arch = "wasm32"))]
-struct QuitButton;
-
-fn setup_menu(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    button_materials: Res<ButtonMaterials>,
-    mut materials: ResMut<Assets<ColorMaterial>>,
-) {
-    commands
-        .spawn_bundle(NodeBundle {
-            style: Style {
-                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
-                ..Default::default()
+        app.add_plugin(MenuPlugin {
+            state: AppState::Paused,
+            menu: Menu {
+                title: Some("PAUSED"),
+                buttons: vec![
+                    MenuButton::new("RESUME", MenuAction::GrabCursor),
+                    MenuButton::new("SETTINGS", MenuAction::Push(AppState::Settings)),
+                    MenuButton::new("QUIT TO TITLE", MenuAction::Replace(AppState::Home)),
+                    MenuButton::new("QUIT TO DESKTOP", MenuAction::Quit),
+                ],
+                align: MenuAlign::Left,
+                back: Some(MenuAction::GrabCursor),
             },
-            material: materials.add(Color::rgba(0., 0., 0.05, 0.85).into()),
-            ..Default::default()
         })
-        .insert(PartOfUi)
-        .with_children(|parent| {
-            parent
-                .spawn_bundle(NodeBundle {
-                    style: Style {
-                        size: Size::new(Val::Px(400.), Val::Auto),
-                        margin: Rect {
-                            left: Val::Px(30.),
-                            ..Default::default()
-                        },
-                        flex_direction: FlexDirection::Column,
-                        justify_content: JustifyContent::Center,
-                        ..Default::default()
-                    },
-                    material: materials.add(Color::rgba(0., 0., 0., 0.).into()),
-                    ..Default::default()
-                })
-                .with_children(|parent| {
-                    parent
-                        .spawn_bundle(NodeBundle {
-                            style: Style {
-                                flex_direction: FlexDirection::ColumnReverse,
-                                ..Default::default()
-                            },
-                            material: materials.add(Color::rgba(0., 0., 0., 0.).into()),
-                            ..Default::default()
-                        })
-                        .with_children(|parent| {
-                            create_button(
-                                parent,
-                                &button_materials,
-                                asset_server.load(asset!("RobotoCondensed-Regular.ttf")),
-                                "RESUME",
-                            )
-                            .insert(ResumeButton);
-                            create_button(
-                                parent,
-                                &button_materials,
-                                asset_server.load(asset!("RobotoCondensed-Regular.ttf")),
-                                "QUIT TO TITLE",
-                            )
-                            .insert(QuitToTitleButton);
-                            #[cfg(not(target_arch = "wasm32"))]
-                            create_button(
-                                parent,
-                                &button_materials,
-                                asset_server.load(asset!("RobotoCondensed-Regular.ttf")),
-                                "QUIT TO DESKTOP",
-                            )
-                            .insert(QuitButton);
-                        });
-                });
-        });
-}
-
-fn remove_menu(mut commands: Commands, query: Query<Entity, With<PartOfUi>>) {
-    query.for_each(|e| commands.entity(e).despawn_recursive());
-}
-
-fn button_system(
-    button_materials: Res<ButtonMaterials>,
-    mut interaction_query: Query<
-        (&Interaction, &mut Handle<ColorMaterial>, &Children),
-        (Changed<Interaction>, With<Button>),
-    >,
-    mut text_query: Query<&mut Text>,
-) {
-    for (interaction, mut material, children) in interaction_query.iter_mut() {
-        let mut text = text_query.get_mut(children[0]).unwrap();
-        match *interaction {
-            Interaction::Clicked => {
-                *material = button_materials.pressed.clone();
-            }
-            Interaction::Hovered => {
-                *material = button_materials.hovered.clone();
-                text.sections[0].style.color = Color::rgb(1., 1., 1.);
-            }
-            Interaction::None => {
-                *material = button_materials.normal.clone();
-                text.sections[0].style.color = Color::rgb(0.65, 0.65, 0.65);
-            }
-        }
+        .add_system_set(SystemSet::on_update(AppState::Paused).with_system(resume.system()))
+        // After the menu's commands have been applied, like the score on the end screen
+        .add_system_to_stage(CoreStage::PostUpdate, show_prompt.system());
     }
 }
 
-fn resume_button_system(
-    mut windows: ResMut<Windows>,
-    #[cfg(target_arch = "wasm32")] winit_windows: Res<bevy::winit::WinitWindows>,
-    interaction_query: Query<&Interaction, (Changed<Interaction>, With<ResumeButton>)>,
-    mut change_next: Local<bool>,
+/// Ask for a gamepad in place of the title while a player has lost theirs
+fn show_prompt(
+    state: Res<State<AppState>>,
+    devices: Res<PlayerDevices>,
+    added: Query<(), Added<MenuTitle>>,
+    mut query: Query<&mut Text, With<MenuTitle>>,
 ) {
-    if let Ok(interaction) = interaction_query.single() {
-        if *interaction == Interaction::Clicked {
-            *change_next = true;
-        } else if *change_next {
-            let window = windows.get_primary_mut().unwrap();
-            set_grab_cursor(
-                window,
-                true,
-                #[cfg(target_arch = "wasm32")]
-                &winit_windows,
-            );
-            *change_next = false;
-        }
+    if *state.current() != AppState::Paused
+        || !devices.is_changed() && added.iter().next().is_none()
+    {
+        return;
+    }
+    for mut text in query.iter_mut() {
+        text.sections[0].value = match devices.waiting() {
+            Some(player) => format!("P{}: PRESS A TO TAKE CONTROL", player + 1),
+            None => "PAUSED".to_string(),
+        };
     }
 }
 
+/// Go back to the game once RESUME has grabbed the cursor
 fn resume(
-    mut state: ResMut<State<crate::AppState>>,
+    mut state: ResMut<State<AppState>>,
     windows: ResMut<Windows>,
     #[cfg(target_arch = "wasm32")] winit_windows: Res<bevy::winit::WinitWindows>,
 ) {
@@ -224,22 +64,3 @@ fn resume(
         log_error!(state.pop());
     }
 }
-
-fn quit_to_title(
-    mut state: ResMut<State<crate::AppState>>,
-    interaction_query: Query<&Interaction, (Changed<Interaction>, With<QuitToTitleButton>)>,
-) {
-    if matches!(interaction_query.single(), Ok(&Interaction::Clicked)) {
-        log_error!(state.replace(crate::AppState::Home));
-    }
-}
-
-#[cfg(not(target_arch = "wasm32"))]
-fn quit_button_system(
-    interaction_query: Query<&Interaction, (Changed<Interaction>, With<QuitButton>)>,
-    mut app_exit_events: EventWriter<AppExit>,
-) {
-    if matches!(interaction_query.single(), Ok(&Interaction::Clicked)) {
-        app_exit_events.send(AppExit);
-    }
-}
@@ -0,0 +1,269 @@
+use bevy::prelude::*;
+
+use crate::settings::Settings;
+
+/// Most players that can share one computer
+pub const MAX_PLAYERS: usize = 2;
+
+/// Keeps track of connected gamepads, and which player each one controls. Pads are remembered by
+/// ID, so one that drops out and reconnects goes back to the same player.
+pub struct PlayersPlugin;
+
+impl Plugin for PlayersPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<PlayerDevices>()
+            .add_event::<GamepadLost>()
+            .add_system_to_stage(CoreStage::PreUpdate, connect_gamepads.system())
+            .add_system(claim_gamepad.system());
+    }
+}
+
+/// The devices one player controls their ship with
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct PlayerInput {
+    pub keyboard_mouse: bool,
+    pub gamepad: Option<Gamepad>,
+    /// The last gamepad this player had. It's kept while that pad is disconnected, so the player
+    /// gets it back when it reconnects.
+    remembered: Option<Gamepad>,
+}
+
+/// A player's gamepad was disconnected
+pub struct GamepadLost {
+    pub player: usize,
+}
+
+/// Which devices belong to which player. Player one always has the keyboard and mouse, and
+/// player two gets a gamepad of their own whenever one is available.
+#[derive(Default)]
+pub struct PlayerDevices {
+    players: Vec<PlayerInput>,
+    /// In the order they were connected
+    connected: Vec<Gamepad>,
+}
+
+impl PlayerDevices {
+    pub fn count(&self) -> usize {
+        self.players.len().max(1)
+    }
+
+    pub fn input(&self, player: usize) -> PlayerInput {
+        self.players.get(player).copied().unwrap_or_default()
+    }
+
+    pub fn gamepad(&self, player: usize) -> Option<Gamepad> {
+        self.input(player).gamepad
+    }
+
+    /// Every connected gamepad, whether or not it belongs to a player
+    pub fn connected(&self) -> &[Gamepad] {
+        &self.connected
+    }
+
+    /// The player a gamepad belongs to, if any
+    pub fn owner(&self, gamepad: Gamepad) -> Option<usize> {
+        self.players
+            .iter()
+            .position(|input| input.gamepad == Some(gamepad))
+    }
+
+    /// The first player whose gamepad was disconnected, and who's waiting for another one
+    pub fn waiting(&self) -> Option<usize> {
+        self.players
+            .iter()
+            .position(|input| input.remembered.is_some() && input.gamepad.is_none())
+    }
+
+    /// Hand out every connected pad from scratch, e.g. when the number of players changes
+    fn assign(&mut self, players: usize) {
+        let players = players.clamp(1, MAX_PLAYERS);
+        let mut pads = self.connected.iter().copied();
+        self.players = (0..players)
+            .map(|player| PlayerInput {
+                keyboard_mouse: player == 0,
+                ..Default::default()
+            })
+            .collect();
+        if players == 1 || self.connected.len() >= players {
+            // Everyone gets a gamepad, and player one keeps the keyboard too
+            for input in self.players.iter_mut() {
+                input.gamepad = pads.next();
+            }
+        } else {
+            // Not enough to go around, so the keyboard player goes without
+            for input in self.players.iter_mut().skip(1) {
+                input.gamepad = pads.next();
+            }
+        }
+        for input in self.players.iter_mut() {
+            input.remembered = input.gamepad;
+        }
+    }
+
+    fn connect(&mut self, gamepad: Gamepad) {
+        if !self.connected.contains(&gamepad) {
+            self.connected.push(gamepad);
+        }
+        if self.owner(gamepad).is_some() {
+            return;
+        }
+        if let Some(input) = self
+            .players
+            .iter_mut()
+            .find(|input| input.gamepad.is_none() && input.remembered == Some(gamepad))
+        {
+            input.gamepad = Some(gamepad);
+            return;
+        }
+        // A new pad is only handed out automatically while nobody's waiting to claim one. Players
+        // without a keyboard get theirs first.
+        if self.waiting().is_none() {
+            if let Some(player) = (0..self.players.len())
+                .rev()
+                .find(|&player| self.players[player].gamepad.is_none())
+            {
+                self.claim(player, gamepad);
+            }
+        }
+    }
+
+    /// Forget a pad, returning the player who lost it
+    fn disconnect(&mut self, gamepad: Gamepad) -> Option<usize> {
+        self.connected.retain(|&connected| connected != gamepad);
+        let player = self.owner(gamepad)?;
+        self.players[player].gamepad = None;
+        Some(player)
+    }
+
+    fn claim(&mut self, player: usize, gamepad: Gamepad) {
+        let input = &mut self.players[player];
+        input.gamepad = Some(gamepad);
+        input.remembered = Some(gamepad);
+    }
+}
+
+fn connect_gamepads(
+    mut devices: ResMut<PlayerDevices>,
+    settings: Res<Settings>,
+    mut gamepad_event: EventReader<GamepadEvent>,
+    mut lost: EventWriter<GamepadLost>,
+) {
+    if settings.is_changed() && settings.players.clamp(1, MAX_PLAYERS) != devices.players.len() {
+        devices.assign(settings.players);
+    }
+    for event in gamepad_event.iter() {
+        match &event {
+            GamepadEvent(gamepad, GamepadEventType::Connected) => {
+                devices.connect(*gamepad);
+                println!("{:?} Connected", gamepad);
+            }
+            GamepadEvent(gamepad, GamepadEventType::Disconnected) => {
+                if let Some(player) = devices.disconnect(*gamepad) {
+                    lost.send(GamepadLost { player });
+                }
+                println!("{:?} Disconnected", gamepad);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// While a player's waiting for a gamepad, pressing A on any pad nobody else has takes control
+fn claim_gamepad(mut devices: ResMut<PlayerDevices>, button_inputs: Res<Input<GamepadButton>>) {
+    let player = match devices.waiting() {
+        Some(player) => player,
+        None => return,
+    };
+    let pressed = devices.connected().iter().copied().find(|&gamepad| {
+        devices.owner(gamepad).is_none()
+            && button_inputs.just_pressed(GamepadButton(gamepad, GamepadButtonType::South))
+    });
+    if let Some(gamepad) = pressed {
+        devices.claim(player, gamepad);
+        info!("{:?} now controls player {}", gamepad, player + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn devices(pads: usize, players: usize) -> PlayerDevices {
+        let mut devices = PlayerDevices::default();
+        for pad in 0..pads {
+            devices.connect(Gamepad(pad));
+        }
+        devices.assign(players);
+        devices
+    }
+
+    #[test]
+    fn one_player_gets_keyboard_and_pad() {
+        let devices = devices(1, 1);
+        assert_eq!(devices.count(), 1);
+        assert!(devices.input(0).keyboard_mouse);
+        assert_eq!(devices.gamepad(0), Some(Gamepad(0)));
+    }
+
+    #[test]
+    fn two_players_with_one_pad() {
+        let devices = devices(1, 2);
+        assert_eq!(devices.count(), 2);
+        assert!(devices.input(0).keyboard_mouse);
+        assert_eq!(devices.gamepad(0), None);
+        assert!(!devices.input(1).keyboard_mouse);
+        assert_eq!(devices.gamepad(1), Some(Gamepad(0)));
+    }
+
+    #[test]
+    fn two_players_with_two_pads() {
+        let devices = devices(2, 2);
+        assert!(devices.input(0).keyboard_mouse);
+        assert_eq!(devices.gamepad(0), Some(Gamepad(0)));
+        assert_eq!(devices.gamepad(1), Some(Gamepad(1)));
+        assert_eq!(devices.waiting(), None);
+    }
+
+    #[test]
+    fn remembered_pad_goes_back_to_its_player() {
+        let mut devices = devices(2, 2);
+        assert_eq!(devices.disconnect(Gamepad(1)), Some(1));
+        assert_eq!(devices.gamepad(1), None);
+        assert_eq!(devices.waiting(), Some(1));
+
+        devices.connect(Gamepad(1));
+        assert_eq!(devices.gamepad(1), Some(Gamepad(1)));
+        assert_eq!(devices.waiting(), None);
+
+        // Even when player one's pad comes back first
+        devices.disconnect(Gamepad(0));
+        devices.disconnect(Gamepad(1));
+        devices.connect(Gamepad(0));
+        assert_eq!(devices.gamepad(0), Some(Gamepad(0)));
+        assert_eq!(devices.gamepad(1), None);
+    }
+
+    #[test]
+    fn new_pad_waits_to_be_claimed_while_someone_is_waiting() {
+        let mut devices = devices(2, 2);
+        devices.disconnect(Gamepad(1));
+        devices.connect(Gamepad(2));
+        assert_eq!(devices.owner(Gamepad(2)), None);
+        assert_eq!(devices.gamepad(1), None);
+        assert_eq!(devices.waiting(), Some(1));
+
+        devices.claim(1, Gamepad(2));
+        assert_eq!(devices.gamepad(1), Some(Gamepad(2)));
+        assert_eq!(devices.waiting(), None);
+    }
+
+    #[test]
+    fn new_pad_goes_to_whoever_has_no_keyboard_first() {
+        let mut devices = devices(0, 2);
+        devices.connect(Gamepad(0));
+        assert_eq!(devices.gamepad(1), Some(Gamepad(0)));
+        assert_eq!(devices.gamepad(0), None);
+        devices.connect(Gamepad(1));
+        assert_eq!(devices.gamepad(0), Some(Gamepad(1)));
+    }
+}
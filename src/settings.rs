@@ -0,0 +1,403 @@
+use anyhow::Context;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::players::MAX_PLAYERS;
+use crate::storage;
+use crate::ui::{Menu, MenuAction, MenuAdjust, MenuAlign, MenuButton, MenuPlugin};
+use crate::AppState;
+
+/// The settings screen, and saving settings whenever they change
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let adjust = |label, id| MenuButton::new(label, MenuAction::Adjust(id));
+        app.add_plugin(MenuPlugin {
+            state: AppState::Settings,
+            menu: Menu {
+                title: Some("SETTINGS"),
+                buttons: vec![
+                    adjust("SENSITIVITY", "sensitivity"),
+                    adjust("INVERT Y", "invert_y"),
+                    MenuButton::new("CONTROLLER", MenuAction::Push(AppState::Controller)),
+                    adjust("MASTER VOLUME", "master_volume"),
+                    adjust("EFFECTS VOLUME", "sfx_volume"),
+                    adjust("MUSIC VOLUME", "music_volume"),
+                    adjust("FIELD OF VIEW", "fov"),
+                    adjust("HUD SCALE", "hud_scale"),
+                    adjust("SCREEN SHAKE", "screen_shake"),
+                    adjust("ANTI-ALIASING", "msaa_samples"),
+                    MenuButton::new("BACK", MenuAction::Pop),
+                ],
+                align: MenuAlign::Center,
+                back: Some(MenuAction::Pop),
+            },
+        })
+        // Sticks have too many settings to fit on the main screen
+        .add_plugin(MenuPlugin {
+            state: AppState::Controller,
+            menu: Menu {
+                title: Some("CONTROLLER"),
+                buttons: vec![
+                    adjust("STICK SENSITIVITY", "stick_sensitivity"),
+                    adjust("STICK DEADZONE", "stick_deadzone"),
+                    adjust("OUTER DEADZONE", "stick_outer_deadzone"),
+                    adjust("AXIAL DEADZONE", "stick_axial_deadzone"),
+                    adjust("STICK CURVE", "stick_curve"),
+                    adjust("INVERT LOOK STICK", "look_stick_invert"),
+                    adjust("INVERT MOVE STICK", "move_stick_invert"),
+                    MenuButton::new("BACK", MenuAction::Pop),
+                ],
+                align: MenuAlign::Center,
+                back: Some(MenuAction::Pop),
+            },
+        })
+        // Some settings can also be changed from other menus
+        .add_system(adjust_settings.system())
+        .add_system(save_settings.system())
+        // After the menu's commands have been applied, so labels never show without values
+        .add_system_to_stage(CoreStage::PostUpdate, update_labels.system());
+    }
+}
+
+/// Everything the player can change on the settings screen. Each part of the game copies what
+/// it needs from here whenever this changes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Multiplies the default mouse sensitivity
+    pub sensitivity: f32,
+    pub invert_y: bool,
+    /// Turning speed at full deflection of the look stick
+    pub stick_sensitivity: f32,
+    /// How far sticks have to move before they do anything, from 0 to 1
+    pub stick_deadzone: f32,
+    /// How close to the edge sticks count as fully pushed, from 0 to 1
+    pub stick_outer_deadzone: f32,
+    /// How far each stick axis has to move on its own before it does anything, from 0 to 1
+    pub stick_axial_deadzone: f32,
+    /// Response curve exponent of the look stick
+    pub stick_curve: f32,
+    /// Flips the look stick, on top of [`Settings::invert_y`]
+    pub look_stick_invert_x: bool,
+    pub look_stick_invert_y: bool,
+    pub move_stick_invert_x: bool,
+    pub move_stick_invert_y: bool,
+    pub master_volume: f32,
+    pub sfx_volume: f32,
+    pub music_volume: f32,
+    /// Vertical field of view, in degrees
+    pub fov: f32,
+    pub hud_scale: f32,
+    pub screen_shake: bool,
+    /// Only read at startup
+    pub msaa_samples: u32,
+    /// Ships in local co-op, up to [`crate::players::MAX_PLAYERS`]
+    pub players: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            sensitivity: 1.,
+            invert_y: false,
+            stick_sensitivity: 3.,
+            stick_deadzone: 0.15,
+            stick_outer_deadzone: 0.05,
+            stick_axial_deadzone: 0.05,
+            stick_curve: 2.,
+            look_stick_invert_x: false,
+            look_stick_invert_y: false,
+            move_stick_invert_x: false,
+            move_stick_invert_y: false,
+            master_volume: 0.8,
+            sfx_volume: 1.,
+            music_volume: 0.5,
+            fov: 45.,
+            hud_scale: 1.,
+            screen_shake: true,
+            msaa_samples: 4,
+            players: 1,
+        }
+    }
+}
+
+const SENSITIVITIES: &[f32] = &[0.25, 0.5, 0.75, 1., 1.25, 1.5, 2., 3.];
+const STICK_SENSITIVITIES: &[f32] = &[1., 1.5, 2., 3., 4., 5., 6.];
+const STICK_DEADZONES: &[f32] = &[0.05, 0.1, 0.15, 0.2, 0.25, 0.3];
+const STICK_EDGE_DEADZONES: &[f32] = &[0., 0.05, 0.1, 0.15, 0.2];
+const STICK_CURVES: &[f32] = &[1., 1.5, 2., 2.5, 3.];
+/// Which of a stick's axes are flipped, as (x, y)
+const STICK_INVERSIONS: &[(bool, bool)] =
+    &[(false, false), (true, false), (false, true), (true, true)];
+const VOLUMES: &[f32] = &[0., 0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9, 1.];
+const FOVS: &[f32] = &[35., 40., 45., 50., 55., 60., 65., 70., 75., 80., 90.];
+const HUD_SCALES: &[f32] = &[0.75, 1., 1.25, 1.5];
+const MSAA_SAMPLES: &[u32] = &[1, 2, 4, 8];
+
+/// The option `step` places away from the one closest to `current`, wrapping around
+fn cycle<T: Copy>(options: &[T], current: T, step: i32, distance: impl Fn(T, T) -> f32) -> T {
+    let closest = (0..options.len())
+        .min_by(|&a, &b| {
+            distance(options[a], current)
+                .partial_cmp(&distance(options[b], current))
+                .unwrap()
+        })
+        .unwrap();
+    options[(closest as i32 + step).rem_euclid(options.len() as i32) as usize]
+}
+
+fn cycle_f32(options: &[f32], current: f32, step: i32) -> f32 {
+    cycle(options, current, step, |a, b| (a - b).abs())
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "ON"
+    } else {
+        "OFF"
+    }
+}
+
+fn cycle_inversion(current: (bool, bool), step: i32) -> (bool, bool) {
+    cycle(STICK_INVERSIONS, current, step, |a, b| {
+        if a == b {
+            0.
+        } else {
+            1.
+        }
+    })
+}
+
+fn inversion(x: bool, y: bool) -> &'static str {
+    match (x, y) {
+        (false, false) => "OFF",
+        (true, false) => "X",
+        (false, true) => "Y",
+        (true, true) => "X AND Y",
+    }
+}
+
+/// `value` kept between the first and last of `options`, or `default` if it isn't a number
+fn clamp_to(options: &[f32], value: f32, default: f32) -> f32 {
+    if value.is_finite() {
+        value.clamp(options[0], options[options.len() - 1])
+    } else {
+        default
+    }
+}
+
+impl Settings {
+    /// Brings values from a hand-edited or corrupt file back in range. The renderer panics on
+    /// sample counts it doesn't support, so those snap to the closest one on offer.
+    fn sanitized(self) -> Self {
+        let defaults = Settings::default();
+        Self {
+            sensitivity: clamp_to(SENSITIVITIES, self.sensitivity, defaults.sensitivity),
+            stick_sensitivity: clamp_to(
+                STICK_SENSITIVITIES,
+                self.stick_sensitivity,
+                defaults.stick_sensitivity,
+            ),
+            stick_deadzone: clamp_to(
+                STICK_DEADZONES,
+                self.stick_deadzone,
+                defaults.stick_deadzone,
+            ),
+            stick_outer_deadzone: clamp_to(
+                STICK_EDGE_DEADZONES,
+                self.stick_outer_deadzone,
+                defaults.stick_outer_deadzone,
+            ),
+            stick_axial_deadzone: clamp_to(
+                STICK_EDGE_DEADZONES,
+                self.stick_axial_deadzone,
+                defaults.stick_axial_deadzone,
+            ),
+            stick_curve: clamp_to(STICK_CURVES, self.stick_curve, defaults.stick_curve),
+            master_volume: clamp_to(VOLUMES, self.master_volume, defaults.master_volume),
+            sfx_volume: clamp_to(VOLUMES, self.sfx_volume, defaults.sfx_volume),
+            music_volume: clamp_to(VOLUMES, self.music_volume, defaults.music_volume),
+            fov: clamp_to(FOVS, self.fov, defaults.fov),
+            hud_scale: clamp_to(HUD_SCALES, self.hud_scale, defaults.hud_scale),
+            msaa_samples: cycle(MSAA_SAMPLES, self.msaa_samples, 0, |a, b| {
+                (a as f32 - b as f32).abs()
+            }),
+            players: self.players.clamp(1, crate::players::MAX_PLAYERS),
+            ..self
+        }
+    }
+
+    fn adjust(&mut self, id: &str, step: i32) {
+        match id {
+            "sensitivity" => self.sensitivity = cycle_f32(SENSITIVITIES, self.sensitivity, step),
+            "invert_y" => self.invert_y = !self.invert_y,
+            "stick_sensitivity" => {
+                self.stick_sensitivity =
+                    cycle_f32(STICK_SENSITIVITIES, self.stick_sensitivity, step)
+            }
+            "stick_deadzone" => {
+                self.stick_deadzone = cycle_f32(STICK_DEADZONES, self.stick_deadzone, step)
+            }
+            "stick_outer_deadzone" => {
+                self.stick_outer_deadzone =
+                    cycle_f32(STICK_EDGE_DEADZONES, self.stick_outer_deadzone, step)
+            }
+            "stick_axial_deadzone" => {
+                self.stick_axial_deadzone =
+                    cycle_f32(STICK_EDGE_DEADZONES, self.stick_axial_deadzone, step)
+            }
+            "stick_curve" => self.stick_curve = cycle_f32(STICK_CURVES, self.stick_curve, step),
+            "look_stick_invert" => {
+                let (x, y) =
+                    cycle_inversion((self.look_stick_invert_x, self.look_stick_invert_y), step);
+                self.look_stick_invert_x = x;
+                self.look_stick_invert_y = y;
+            }
+            "move_stick_invert" => {
+                let (x, y) =
+                    cycle_inversion((self.move_stick_invert_x, self.move_stick_invert_y), step);
+                self.move_stick_invert_x = x;
+                self.move_stick_invert_y = y;
+            }
+            "master_volume" => self.master_volume = cycle_f32(VOLUMES, self.master_volume, step),
+            "sfx_volume" => self.sfx_volume = cycle_f32(VOLUMES, self.sfx_volume, step),
+            "music_volume" => self.music_volume = cycle_f32(VOLUMES, self.music_volume, step),
+            "fov" => self.fov = cycle_f32(FOVS, self.fov, step),
+            "hud_scale" => self.hud_scale = cycle_f32(HUD_SCALES, self.hud_scale, step),
+            "screen_shake" => self.screen_shake = !self.screen_shake,
+            "msaa_samples" => {
+                self.msaa_samples = cycle(MSAA_SAMPLES, self.msaa_samples, step, |a, b| {
+                    (a as f32 - b as f32).abs()
+                })
+            }
+            "players" => {
+                self.players =
+                    (self.players as i32 - 1 + step).rem_euclid(MAX_PLAYERS as i32) as usize + 1
+            }
+            _ => warn!("Unknown setting {}", id),
+        }
+    }
+
+    /// How a setting is shown on its button
+    fn label(&self, id: &str) -> String {
+        let percent = |volume: f32| format!("{:.0}%", volume * 100.);
+        match id {
+            "sensitivity" => format!("SENSITIVITY {}x", self.sensitivity),
+            "invert_y" => format!("INVERT Y {}", on_off(self.invert_y)),
+            "stick_sensitivity" => format!("STICK SENSITIVITY {}", self.stick_sensitivity),
+            "stick_deadzone" => format!("STICK DEADZONE {}", percent(self.stick_deadzone)),
+            "stick_outer_deadzone" => {
+                format!("OUTER DEADZONE {}", percent(self.stick_outer_deadzone))
+            }
+            "stick_axial_deadzone" => {
+                format!("AXIAL DEADZONE {}", percent(self.stick_axial_deadzone))
+            }
+            "stick_curve" => format!("STICK CURVE {}", self.stick_curve),
+            "look_stick_invert" => format!(
+                "INVERT LOOK STICK {}",
+                inversion(self.look_stick_invert_x, self.look_stick_invert_y)
+            ),
+            "move_stick_invert" => format!(
+                "INVERT MOVE STICK {}",
+                inversion(self.move_stick_invert_x, self.move_stick_invert_y)
+            ),
+            "master_volume" => format!("MASTER VOLUME {}", percent(self.master_volume)),
+            "sfx_volume" => format!("EFFECTS VOLUME {}", percent(self.sfx_volume)),
+            "music_volume" => format!("MUSIC VOLUME {}", percent(self.music_volume)),
+            "fov" => format!("FIELD OF VIEW {:.0}°", self.fov),
+            "hud_scale" => format!("HUD SCALE {}x", self.hud_scale),
+            "screen_shake" => format!("SCREEN SHAKE {}", on_off(self.screen_shake)),
+            "msaa_samples" => match self.msaa_samples {
+                1 => "ANTI-ALIASING OFF (RESTART)".to_string(),
+                samples => format!("ANTI-ALIASING {}x (RESTART)", samples),
+            },
+            "players" => format!("PLAYERS {}", self.players),
+            _ => id.to_uppercase(),
+        }
+    }
+}
+
+fn adjust_settings(mut events: EventReader<MenuAdjust>, mut settings: ResMut<Settings>) {
+    for event in events.iter() {
+        settings.adjust(event.id, event.step);
+    }
+}
+
+fn update_labels(
+    settings: Res<Settings>,
+    buttons: Query<(&MenuAction, &Children)>,
+    added: Query<(), Added<MenuAction>>,
+    mut text_query: Query<&mut Text>,
+) {
+    if !settings.is_changed() && added.iter().next().is_none() {
+        return;
+    }
+    for (action, children) in buttons.iter() {
+        if let MenuAction::Adjust(id) = action {
+            if let Ok(mut text) = text_query.get_mut(children[0]) {
+                text.sections[0].value = settings.label(id);
+            }
+        }
+    }
+}
+
+fn save_settings(settings: Res<Settings>) {
+    if !settings.is_changed() || settings.is_added() {
+        return;
+    }
+    if let Err(e) = save(&settings) {
+        error!("Couldn't save settings: {:?}", e);
+    }
+}
+
+/// Saved settings, or the defaults if there aren't any
+pub fn load() -> anyhow::Result<Settings> {
+    match storage::read("settings")? {
+        Some(json) => serde_json::from_str(&json)
+            .map(Settings::sanitized)
+            .context("Saved settings are invalid"),
+        None => Ok(Settings::default()),
+    }
+}
+
+fn save(settings: &Settings) -> anyhow::Result<()> {
+    storage::write("settings", &serde_json::to_string_pretty(settings)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitizing_brings_values_back_in_range() {
+        let settings = Settings {
+            msaa_samples: 0,
+            fov: 500.,
+            master_volume: -1.,
+            music_volume: f32::NAN,
+            players: 9,
+            ..Default::default()
+        }
+        .sanitized();
+        assert_eq!(settings.msaa_samples, 1);
+        assert_eq!(settings.fov, 90.);
+        assert_eq!(settings.master_volume, 0.);
+        assert_eq!(settings.music_volume, Settings::default().music_volume);
+        assert_eq!(settings.players, crate::players::MAX_PLAYERS);
+    }
+
+    #[test]
+    fn unsupported_sample_counts_snap_to_one_on_offer() {
+        for samples in [3, 5, 16].iter().copied() {
+            let settings = Settings {
+                msaa_samples: samples,
+                ..Default::default()
+            }
+            .sanitized();
+            assert!(MSAA_SAMPLES.contains(&settings.msaa_samples));
+        }
+        assert_eq!(Settings::default().sanitized(), Settings::default());
+    }
+}
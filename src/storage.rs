@@ -0,0 +1,57 @@
+//! Small JSON files that outlive the game: in the config directory natively, and in
+//! localStorage on the web. Each one is named, like `settings`.
+
+use anyhow::Context;
+
+#[cfg(not(target_arch = "wasm32"))]
+fn path(name: &str) -> anyhow::Result<std::path::PathBuf> {
+    let dir = dirs::config_dir().context("No config directory")?;
+    Ok(dir.join("asteroids_3d").join(format!("{}.json", name)))
+}
+
+/// The saved contents, or `None` if nothing has been saved yet
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read(name: &str) -> anyhow::Result<Option<String>> {
+    match std::fs::read_to_string(path(name)?) {
+        Ok(json) => Ok(Some(json)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn write(name: &str, json: &str) -> anyhow::Result<()> {
+    let path = path(name)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, json)?;
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn storage() -> anyhow::Result<web_sys::Storage> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .context("localStorage isn't available")
+}
+
+#[cfg(target_arch = "wasm32")]
+fn key(name: &str) -> String {
+    format!("asteroids_3d.{}", name)
+}
+
+/// The saved contents, or `None` if nothing has been saved yet
+#[cfg(target_arch = "wasm32")]
+pub fn read(name: &str) -> anyhow::Result<Option<String>> {
+    storage()?
+        .get_item(&key(name))
+        .map_err(|e| anyhow::anyhow!("Couldn't read localStorage: {:?}", e))
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn write(name: &str, json: &str) -> anyhow::Result<()> {
+    storage()?
+        .set_item(&key(name), json)
+        .map_err(|e| anyhow::anyhow!("Couldn't write localStorage: {:?}", e))
+}
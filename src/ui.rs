@@ -0,0 +1,468 @@
+use std::collections::HashMap;
+
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::app::AppExit;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+
+use crate::audio::AudioUnlocked;
+use crate::in_game::{Run, RunKind};
+use crate::players::PlayerDevices;
+use crate::util::set_grab_cursor;
+use crate::AppState;
+
+/// Shared pieces of every menu: the theme, button visuals and button actions. Each screen then
+/// adds a [`MenuPlugin`] describing its menu.
+pub struct UiPlugin;
+
+impl Plugin for UiPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<UiTheme>()
+            .init_resource::<Menus>()
+            .init_resource::<MenuFocus>()
+            .add_event::<MenuAdjust>()
+            .add_system(button_visuals.system())
+            .add_system(button_actions.system())
+            .add_system(navigate.system());
+    }
+}
+
+/// Shows `menu` while `state` is the current state, and removes it when leaving or pausing
+/// `state`
+pub struct MenuPlugin {
+    pub state: AppState,
+    pub menu: Menu,
+}
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.world_mut()
+            .get_resource_or_insert_with(Menus::default)
+            .0
+            .insert(self.state, self.menu.clone());
+        app.add_system_set(SystemSet::on_enter(self.state).with_system(spawn_menu.system()))
+            .add_system_set(SystemSet::on_resume(self.state).with_system(spawn_menu.system()))
+            .add_system_set(SystemSet::on_exit(self.state).with_system(despawn_scoped.system()))
+            .add_system_set(SystemSet::on_pause(self.state).with_system(despawn_scoped.system()));
+    }
+}
+
+#[derive(Clone)]
+pub struct Menu {
+    pub title: Option<&'static str>,
+    pub buttons: Vec<MenuButton>,
+    pub align: MenuAlign,
+    /// What Escape or B does
+    pub back: Option<MenuAction>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MenuAlign {
+    /// A column down the left of the screen, leaving room for other things on the right
+    Left,
+    Center,
+}
+
+#[derive(Clone)]
+pub struct MenuButton {
+    pub label: &'static str,
+    pub action: MenuAction,
+}
+
+impl MenuButton {
+    pub fn new(label: &'static str, action: MenuAction) -> Self {
+        Self { label, action }
+    }
+}
+
+/// What a menu button does when it's released
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MenuAction {
+    /// Lock the cursor. The screen decides what happens once it's locked.
+    GrabCursor,
+    /// Pick the kind of run to play, then lock the cursor like [`MenuAction::GrabCursor`]
+    Start(RunKind),
+    Replace(AppState),
+    Push(AppState),
+    Pop,
+    /// Change the setting with this ID by sending a [`MenuAdjust`]. Pressing the button steps
+    /// forward; left and right on the keyboard or D-pad step either way.
+    Adjust(&'static str),
+    /// Exit the game. These buttons are left out on the web, where there's nothing to exit to.
+    Quit,
+}
+
+/// Sent by [`MenuAction::Adjust`] buttons
+pub struct MenuAdjust {
+    pub id: &'static str,
+    /// Which way, and how many steps, to change the setting
+    pub step: i32,
+}
+
+#[derive(Default)]
+struct Menus(HashMap<AppState, Menu>);
+
+/// Colors, fonts and sizes shared by all menus
+pub struct UiTheme {
+    pub font: Handle<Font>,
+    pub font_size: f32,
+    pub title_size: f32,
+    pub text: Color,
+    pub text_hovered: Color,
+    pub title: Color,
+    /// Dims the game behind menus
+    pub background: Handle<ColorMaterial>,
+    pub transparent: Handle<ColorMaterial>,
+    pub button: Handle<ColorMaterial>,
+    pub button_hovered: Handle<ColorMaterial>,
+    pub button_pressed: Handle<ColorMaterial>,
+}
+
+impl FromWorld for UiTheme {
+    fn from_world(world: &mut World) -> Self {
+        let font = world
+            .get_resource::<AssetServer>()
+            .unwrap()
+            .load(asset!("RobotoCondensed-Regular.ttf"));
+        let mut materials = world.get_resource_mut::<Assets<ColorMaterial>>().unwrap();
+        UiTheme {
+            font,
+            font_size: 40.,
+            title_size: 60.,
+            text: Color::rgb(0.65, 0.65, 0.65),
+            text_hovered: Color::rgb(1., 1., 1.),
+            title: Color::rgb(0.5, 0.5, 1.0),
+            background: materials.add(Color::rgba(0., 0., 0.05, 0.85).into()),
+            transparent: materials.add(Color::NONE.into()),
+            button: materials.add(Color::rgba(0.15, 0.15, 0.15, 0.).into()),
+            button_hovered: materials.add(Color::rgba(0.25, 0.25, 0.25, 0.9).into()),
+            button_pressed: materials.add(Color::rgb(0.2, 0.2, 0.2).into()),
+        }
+    }
+}
+
+impl UiTheme {
+    pub fn text_style(&self) -> TextStyle {
+        TextStyle {
+            font: self.font.clone(),
+            font_size: self.font_size,
+            color: self.text,
+        }
+    }
+}
+
+/// Removed when leaving or pausing the state it belongs to. Screens can add this to their own
+/// entities to have them cleaned up along with the menu.
+pub struct StateScoped(pub AppState);
+
+/// The menu's title text, for screens that want to change it
+pub struct MenuTitle;
+
+/// The button that keyboard and gamepad input acts on. Hovering a button with the mouse also
+/// focuses it, so only one button is ever highlighted.
+#[derive(Default)]
+pub struct MenuFocus(pub Option<Entity>);
+
+/// Position of a button in its menu, from the top
+struct ButtonIndex(usize);
+
+fn spawn_menu(
+    mut commands: Commands,
+    state: Res<State<AppState>>,
+    menus: Res<Menus>,
+    theme: Res<UiTheme>,
+) {
+    let state = *state.current();
+    let menu = match menus.0.get(&state) {
+        Some(menu) => menu,
+        None => return,
+    };
+    let (justify_content, margin) = match menu.align {
+        MenuAlign::Left => (
+            JustifyContent::FlexStart,
+            Rect {
+                left: Val::Px(30.),
+                ..Default::default()
+            },
+        ),
+        MenuAlign::Center => (JustifyContent::Center, Rect::default()),
+    };
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                justify_content,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: theme.background.clone(),
+            ..Default::default()
+        })
+        .insert(StateScoped(state))
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::ColumnReverse,
+                        align_items: AlignItems::Stretch,
+                        min_size: Size::new(Val::Px(200.), Val::Auto),
+                        margin,
+                        ..Default::default()
+                    },
+                    material: theme.transparent.clone(),
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    if let Some(title) = menu.title {
+                        parent
+                            .spawn_bundle(TextBundle {
+                                style: Style {
+                                    margin: Rect {
+                                        bottom: Val::Px(30.),
+                                        ..Default::default()
+                                    },
+                                    align_self: AlignSelf::Center,
+                                    ..Default::default()
+                                },
+                                text: Text::with_section(
+                                    title,
+                                    TextStyle {
+                                        font_size: theme.title_size,
+                                        color: theme.title,
+                                        ..theme.text_style()
+                                    },
+                                    Default::default(),
+                                ),
+                                ..Default::default()
+                            })
+                            .insert(MenuTitle);
+                    }
+                    for (index, button) in menu.buttons.iter().enumerate() {
+                        if cfg!(target_arch = "wasm32") && button.action == MenuAction::Quit {
+                            continue;
+                        }
+                        spawn_button(parent, &theme, button, index);
+                    }
+                });
+        });
+}
+
+fn spawn_button(parent: &mut ChildBuilder, theme: &UiTheme, button: &MenuButton, index: usize) {
+    parent
+        .spawn_bundle(ButtonBundle {
+            style: Style {
+                padding: Rect::all(Val::Px(5.)),
+                margin: Rect::all(Val::Px(2.)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            material: theme.button.clone(),
+            ..Default::default()
+        })
+        .insert(button.action.clone())
+        .insert(ButtonIndex(index))
+        .with_children(|parent| {
+            parent.spawn_bundle(TextBundle {
+                text: Text::with_section(button.label, theme.text_style(), Default::default()),
+                ..Default::default()
+            });
+        });
+}
+
+fn despawn_scoped(
+    mut commands: Commands,
+    state: Res<State<AppState>>,
+    query: Query<(Entity, &StateScoped)>,
+) {
+    // While leaving or pausing, the current state is still the one being left
+    for (entity, scoped) in query.iter() {
+        if scoped.0 == *state.current() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn button_visuals(
+    theme: Res<UiTheme>,
+    mut focus: ResMut<MenuFocus>,
+    changed: Query<(Entity, &Interaction), (Changed<Interaction>, With<Button>)>,
+    mut buttons: Query<(Entity, &Interaction, &mut Handle<ColorMaterial>, &Children), With<Button>>,
+    mut text_query: Query<&mut Text>,
+) {
+    let mut any_changed = false;
+    for (entity, interaction) in changed.iter() {
+        any_changed = true;
+        if *interaction == Interaction::Hovered {
+            focus.0 = Some(entity);
+        }
+    }
+    if !any_changed && !focus.is_changed() {
+        return;
+    }
+    for (entity, interaction, mut material, children) in buttons.iter_mut() {
+        let mut text = text_query.get_mut(children[0]).unwrap();
+        match *interaction {
+            Interaction::Clicked => {
+                *material = theme.button_pressed.clone();
+            }
+            _ if focus.0 == Some(entity) => {
+                *material = theme.button_hovered.clone();
+                text.sections[0].style.color = theme.text_hovered;
+            }
+            _ => {
+                *material = theme.button.clone();
+                text.sections[0].style.color = theme.text;
+            }
+        }
+    }
+}
+
+/// Carries out [`MenuAction`]s
+#[derive(SystemParam)]
+pub struct MenuActions<'a> {
+    state: ResMut<'a, State<AppState>>,
+    windows: ResMut<'a, Windows>,
+    #[cfg(target_arch = "wasm32")]
+    winit_windows: Res<'a, bevy::winit::WinitWindows>,
+    audio_unlocked: ResMut<'a, AudioUnlocked>,
+    run: ResMut<'a, Run>,
+    adjust_events: EventWriter<'a, MenuAdjust>,
+    #[cfg(not(target_arch = "wasm32"))]
+    app_exit_events: EventWriter<'a, AppExit>,
+}
+
+impl<'a> MenuActions<'a> {
+    pub fn current_state(&self) -> AppState {
+        *self.state.current()
+    }
+
+    pub fn adjust(&mut self, id: &'static str, step: i32) {
+        self.adjust_events.send(MenuAdjust { id, step });
+    }
+
+    fn grab_cursor(&mut self) {
+        let window = self.windows.get_primary_mut().unwrap();
+        set_grab_cursor(
+            window,
+            true,
+            #[cfg(target_arch = "wasm32")]
+            &self.winit_windows,
+        );
+        // Browsers only let audio start in response to a gesture like this one
+        self.audio_unlocked.0 = true;
+    }
+
+    pub fn run(&mut self, action: &MenuAction) {
+        match action {
+            MenuAction::GrabCursor => self.grab_cursor(),
+            MenuAction::Start(kind) => {
+                self.run.kind = *kind;
+                self.grab_cursor();
+            }
+            MenuAction::Replace(state) => log_error!(self.state.replace(*state)),
+            MenuAction::Push(state) => log_error!(self.state.push(*state)),
+            MenuAction::Pop => log_error!(self.state.pop()),
+            MenuAction::Adjust(id) => self.adjust(*id, 1),
+            #[cfg(not(target_arch = "wasm32"))]
+            MenuAction::Quit => self.app_exit_events.send(AppExit),
+            #[cfg(target_arch = "wasm32")]
+            MenuAction::Quit => {}
+        }
+    }
+}
+
+/// Run a button's action when it's released, so the cursor isn't grabbed while the button is
+/// still held down
+fn button_actions(
+    mut actions: MenuActions,
+    query: Query<(Entity, &Interaction, &MenuAction), Changed<Interaction>>,
+    mut pressed: Local<Option<Entity>>,
+) {
+    for (entity, interaction, action) in query.iter() {
+        match interaction {
+            Interaction::Clicked => *pressed = Some(entity),
+            Interaction::Hovered if *pressed == Some(entity) => {
+                *pressed = None;
+                actions.run(action);
+            }
+            _ if *pressed == Some(entity) => *pressed = None,
+            _ => {}
+        }
+    }
+}
+
+/// Move focus with the arrow keys, WASD or the D-pad, press the focused button with Enter or A,
+/// and go back with Escape or B. Left and right change settings instead of moving, when there's
+/// a setting focused.
+fn navigate(
+    keys: Res<Input<KeyCode>>,
+    devices: Res<PlayerDevices>,
+    button_inputs: Res<Input<GamepadButton>>,
+    menus: Res<Menus>,
+    mut focus: ResMut<MenuFocus>,
+    mut actions: MenuActions,
+    buttons: Query<(Entity, &ButtonIndex, &MenuAction)>,
+    added: Query<(), Added<ButtonIndex>>,
+) {
+    // Don't act on the same press that opened this menu
+    if buttons.iter().next().is_none() || added.iter().next().is_some() {
+        return;
+    }
+    let key = |keys_for: &[KeyCode]| keys_for.iter().any(|key| keys.just_pressed(*key));
+    // Any connected gamepad can drive the menus, even one no player has
+    let pad = |button| {
+        devices
+            .connected()
+            .iter()
+            .any(|&gamepad| button_inputs.just_pressed(GamepadButton(gamepad, button)))
+    };
+    let up = key(&[KeyCode::Up, KeyCode::W]) || pad(GamepadButtonType::DPadUp);
+    let down = key(&[KeyCode::Down, KeyCode::S]) || pad(GamepadButtonType::DPadDown);
+    let left = key(&[KeyCode::Left, KeyCode::A]) || pad(GamepadButtonType::DPadLeft);
+    let right = key(&[KeyCode::Right, KeyCode::D]) || pad(GamepadButtonType::DPadRight);
+    let activate = key(&[KeyCode::Return, KeyCode::NumpadEnter]) || pad(GamepadButtonType::South);
+    let back = key(&[KeyCode::Escape]) || pad(GamepadButtonType::East);
+
+    let mut ordered: Vec<_> = buttons.iter().collect();
+    ordered.sort_by_key(|(_, index, _)| index.0);
+    let current = ordered
+        .iter()
+        .position(|(entity, _, _)| focus.0 == Some(*entity));
+    let (up, down) = match current.map(|current| ordered[current].2) {
+        Some(MenuAction::Adjust(id)) => {
+            if left != right {
+                let step = if left { -1 } else { 1 };
+                actions.adjust(*id, step);
+            }
+            (up, down)
+        }
+        _ => (up || left, down || right),
+    };
+    let last = ordered.len() - 1;
+    let next = match current {
+        _ if up == down => current,
+        Some(current) if up => Some(current.checked_sub(1).unwrap_or(last)),
+        Some(current) => Some(if current == last { 0 } else { current + 1 }),
+        None if up => Some(last),
+        None => Some(0),
+    };
+    if next != current {
+        focus.0 = next.map(|next| ordered[next].0);
+    }
+
+    if activate {
+        match current {
+            Some(current) => actions.run(ordered[current].2),
+            None => focus.0 = Some(ordered[0].0),
+        }
+    } else if back {
+        let action = menus
+            .0
+            .get(&actions.current_state())
+            .and_then(|menu| menu.back.clone());
+        if let Some(action) = action {
+            actions.run(&action);
+        }
+    }
+}
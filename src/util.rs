@@ -3,6 +3,7 @@ use std::hash::Hash;
 
 use bevy::ecs::component::Component;
 use bevy::prelude::*;
+use bevy::render::texture::{Extent3d, TextureDimension, TextureFormat};
 use bevy_rapier3d::na::Translation3;
 use bevy_rapier3d::physics::ColliderHandleComponent;
 use bevy_rapier3d::rapier::geometry::ColliderSet;
@@ -53,6 +54,50 @@ pub fn nalgebra_pos(pos: Vec3, rot: Quat) -> Isometry<f32> {
     Isometry::from_parts(Translation3::from(Vector::from(pos)), rot.into())
 }
 
+/// Create a white ring texture for UI elements. `inner` is the inner radius, from 0 (a filled
+/// disc) to 1.
+pub fn ring_texture(size: u32, inner: f32) -> Texture {
+    let center = (size as f32 - 1.) / 2.;
+    let mut data = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let distance = Vec2::new(x as f32 - center, y as f32 - center).length() / center;
+            let alpha = if distance <= 1. && distance >= inner {
+                255
+            } else {
+                0
+            };
+            data.extend_from_slice(&[255, 255, 255, alpha]);
+        }
+    }
+    Texture::new(
+        Extent3d::new(size, size, 1),
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+/// Create a white triangle texture pointing up, for UI arrows
+pub fn triangle_texture(size: u32) -> Texture {
+    let mut data = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        // Rows go from top to bottom, so the triangle widens as `y` grows
+        let half_width = (y as f32 + 0.5) / size as f32 / 2.;
+        for x in 0..size {
+            let offset = ((x as f32 + 0.5) / size as f32 - 0.5).abs();
+            let alpha = if offset <= half_width { 255 } else { 0 };
+            data.extend_from_slice(&[255, 255, 255, alpha]);
+        }
+    }
+    Texture::new(
+        Extent3d::new(size, size, 1),
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 macro_rules! asset {
     ($path:literal, $part:literal) => {